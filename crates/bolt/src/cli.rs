@@ -1,2 +1,82 @@
-// #[derive(clap::Parser)]
-// pub struct Args {}
+use crate::sites;
+use bolt_config::ast::Printer;
+use bolt_config::{Config, FileConfigProvider};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+#[clap(version, about)]
+pub struct Args {
+    /// Path of the main config file
+    #[clap(short, long, global = true, default_value = "./config/bolt.conf")]
+    pub config: PathBuf,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Start the server (default)
+    Run,
+    /// Validate the config, its snippets, routes and certificates without starting the server
+    Check,
+    /// Print the effective config of every site with all snippets expanded
+    Dump,
+}
+
+pub fn load(path: &Path) -> Option<Config> {
+    match FileConfigProvider::load(path) {
+        Ok(provider) => Some(provider.config().as_ref().clone()),
+        Err(err) => {
+            eprintln!("{}", err);
+            None
+        }
+    }
+}
+
+pub fn check(path: &Path) -> i32 {
+    let config = match load(path) {
+        Some(config) => config,
+        None => return 1,
+    };
+
+    let compiled = sites::compile(&config).and_then(|sites| {
+        sites::listeners(&sites)?;
+        Ok(sites)
+    });
+
+    match compiled {
+        Ok(sites) => {
+            for site in &sites {
+                println!("ok: {}", site.site.path.display());
+            }
+            println!(
+                "{}: {} site(s) checked successfully",
+                config.path.display(),
+                sites.len()
+            );
+            0
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            1
+        }
+    }
+}
+
+pub fn dump(path: &Path) -> i32 {
+    let config = match load(path) {
+        Some(config) => config,
+        None => return 1,
+    };
+
+    for (i, site) in config.sites.iter().enumerate() {
+        if i != 0 {
+            println!();
+        }
+        println!("# {}", site.path.display());
+        print!("{}", Printer(&site.directives));
+    }
+
+    0
+}
@@ -0,0 +1,205 @@
+//! A connection to a FastCGI application, shared by concurrent requests if the application
+//! multiplexes
+//!
+//! A reader task hands the records to the request they belong to, a writer task sends the frames
+//! of all requests in order. Either closes the connection for everyone once it fails.
+
+use super::record::{self, ProtocolStatus, RecordType, MANAGEMENT_ID};
+use crate::layers::raw::stream::Socket;
+use hyper::body::Bytes;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use tracing::debug;
+
+/// Records read ahead for a request before the connection waits for it to catch up
+const REQUEST_BUFFER: usize = 16;
+/// Frames waiting to be written
+const WRITE_BUFFER: usize = 32;
+
+/// A record of one request, as read from the connection
+#[derive(Debug)]
+pub enum Event {
+    Stdout(Bytes),
+    Stderr(Bytes),
+    End(ProtocolStatus),
+}
+
+pub struct Conn {
+    writer: mpsc::Sender<Vec<u8>>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Requests waiting for records, by id
+    requests: HashMap<u16, mpsc::Sender<Event>>,
+    last_id: u16,
+    closed: bool,
+    /// Waiting for the reply to `FCGI_GET_VALUES`
+    values: Option<oneshot::Sender<Bytes>>,
+}
+
+impl State {
+    fn close(&mut self) {
+        self.closed = true;
+        // wakes up every request with the end of its channel
+        self.requests.clear();
+        self.values = None;
+    }
+}
+
+impl Conn {
+    /// Spawns the tasks reading and writing `socket`, `server` is only used for logging
+    pub fn new(socket: Socket, server: &str) -> Self {
+        let (reader, writer) = tokio::io::split(socket);
+        let (frames, queued) = mpsc::channel(WRITE_BUFFER);
+        let state = Arc::new(Mutex::new(State::default()));
+        tokio::spawn(read_records(reader, state.clone(), server.to_string()));
+        tokio::spawn(write_frames(
+            writer,
+            queued,
+            state.clone(),
+            server.to_string(),
+        ));
+        Self {
+            writer: frames,
+            state,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Asks the application for the values of `FCGI_GET_VALUES`, `None` if it doesn't answer
+    pub async fn get_values(&self, names: &[&str]) -> Option<Vec<(String, String)>> {
+        let (sender, values) = oneshot::channel();
+        self.state.lock().unwrap().values = Some(sender);
+
+        let content = record::encode_params(names.iter().map(|name| (name.as_bytes(), &b""[..])));
+        let mut frame = vec![];
+        record::encode(&mut frame, RecordType::GetValues, MANAGEMENT_ID, &content);
+        self.send(frame).await.ok()?;
+
+        let content = values.await.ok()?;
+        let values = record::decode_params(&content)?
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    String::from_utf8_lossy(name).into_owned(),
+                    String::from_utf8_lossy(value).into_owned(),
+                )
+            })
+            .collect();
+        Some(values)
+    }
+
+    /// Reserves an id for a new request and the channel its records arrive on, `None` once the
+    /// connection is closed
+    ///
+    /// The id is only released by `FCGI_END_REQUEST`, even if the receiver is dropped earlier.
+    pub fn start(&self) -> Option<(u16, mpsc::Receiver<Event>)> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return None;
+        }
+        let last_id = state.last_id;
+        let id = (1..=u16::MAX)
+            .map(|offset| last_id.wrapping_add(offset))
+            .find(|id| *id != MANAGEMENT_ID && !state.requests.contains_key(id))?;
+        let (sender, events) = mpsc::channel(REQUEST_BUFFER);
+        state.requests.insert(id, sender);
+        state.last_id = id;
+        Some((id, events))
+    }
+
+    /// Queues `frame`, fails once the connection is closed
+    pub async fn send(&self, frame: Vec<u8>) -> Result<(), ()> {
+        self.writer.send(frame).await.map_err(|_| ())
+    }
+
+    /// For streaming the body of a request while its response is read
+    pub fn writer(&self) -> mpsc::Sender<Vec<u8>> {
+        self.writer.clone()
+    }
+
+    /// `FCGI_ABORT_REQUEST`, dropped if the connection is too busy to take it right away
+    pub fn abort(&self, request_id: u16) {
+        let mut frame = vec![];
+        record::encode(&mut frame, RecordType::AbortRequest, request_id, b"");
+        let _ = self.writer.try_send(frame);
+    }
+}
+
+async fn read_records(mut reader: ReadHalf<Socket>, state: Arc<Mutex<State>>, server: String) {
+    loop {
+        let record = match record::read(&mut reader).await {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(err) => {
+                debug!("FastCGI connection to {} failed: {}", server, err);
+                break;
+            }
+        };
+
+        let id = record.request_id;
+        let event = match record.kind {
+            Some(RecordType::GetValuesResult) if id == MANAGEMENT_ID => {
+                if let Some(values) = state.lock().unwrap().values.take() {
+                    let _ = values.send(record.content);
+                }
+                continue;
+            }
+            // the empty record ending the stream carries nothing
+            Some(RecordType::Stdout | RecordType::Stderr) if record.content.is_empty() => continue,
+            Some(RecordType::Stdout) => Event::Stdout(record.content),
+            Some(RecordType::Stderr) => Event::Stderr(record.content),
+            Some(RecordType::EndRequest) => match record::end_request(&record.content) {
+                Some((_, status)) => Event::End(status),
+                None => {
+                    debug!("Invalid FCGI_END_REQUEST from {}", server);
+                    break;
+                }
+            },
+            kind => {
+                debug!(
+                    "Ignoring FastCGI record {:?} for request {} from {}",
+                    kind, id, server
+                );
+                continue;
+            }
+        };
+
+        let sender = {
+            let mut state = state.lock().unwrap();
+            match event {
+                Event::End(_) => state.requests.remove(&id),
+                _ => state.requests.get(&id).cloned(),
+            }
+        };
+        // the request may have been given up on already
+        if let Some(sender) = sender {
+            let _ = sender.send(event).await;
+        }
+    }
+    state.lock().unwrap().close();
+}
+
+async fn write_frames(
+    mut writer: WriteHalf<Socket>,
+    mut frames: mpsc::Receiver<Vec<u8>>,
+    state: Arc<Mutex<State>>,
+    server: String,
+) {
+    while let Some(frame) = frames.recv().await {
+        if let Err(err) = writer.write_all(&frame).await {
+            debug!("Unable to write to FastCGI server {}: {}", server, err);
+            state.lock().unwrap().close();
+            return;
+        }
+    }
+    // every sender is gone, the connection is no longer used
+    let _ = writer.shutdown().await;
+}
@@ -0,0 +1,709 @@
+//! FastCGI client for `fastcgi_pass`, as specified by
+//! <https://fastcgi-archives.github.io/FastCGI_Specification.html>
+//!
+//! Requests go through the same upstream stack as those of `proxy_pass`, with [`Gateway`] in
+//! place of the HTTP client. Each server has a pool of connections: applications announcing
+//! `FCGI_MPXS_CONNS` share them between concurrent requests, each with its own request id, all
+//! others get one connection per request in flight. Connections are kept open for later
+//! requests. The request body is streamed as `FCGI_STDIN` while the response is read and
+//! `FCGI_STDERR` is logged.
+
+use crate::layers::upstream::pick::Picked;
+use crate::layers::upstream::ProxyError;
+use crate::upstream::connect::{self, Connector};
+use crate::util::PinResultFuture;
+use conn::{Conn, Event};
+use hyper::body::{Bytes, HttpBody, Sender};
+use hyper::header::{HeaderName, HeaderValue, LOCATION};
+use hyper::http::response::Parts;
+use hyper::http::uri::PathAndQuery;
+use hyper::{Body, Request, Response, StatusCode};
+use record::{ProtocolStatus, RecordType};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tower::{Service, ServiceExt};
+use tracing::{debug, warn};
+
+mod conn;
+pub mod record;
+
+/// Concurrent requests on one connection of a multiplexing application
+const MAX_MULTIPLEXED: usize = 64;
+/// Unused connections kept open per server
+const MAX_IDLE: usize = 8;
+/// Longest response head, up to the empty line
+const MAX_HEAD: usize = 64 * 1024;
+/// How long to wait for the answer to `FCGI_GET_VALUES`, applications not answering don't
+/// multiplex
+const GET_VALUES_TIMEOUT: Duration = Duration::from_secs(1);
+const MPXS_CONNS: &str = "FCGI_MPXS_CONNS";
+
+#[derive(thiserror::Error, Debug)]
+pub enum FastCgiError {
+    #[error("error trying to connect: {0}")]
+    Connect(Box<dyn Error + Send + Sync>),
+    /// A pooled connection closed before the request was sent
+    #[error("the connection was closed by the application")]
+    Stale,
+    #[error("the connection closed before the response was complete")]
+    Closed,
+    #[error("the application refused the request: {0:?}")]
+    Refused(ProtocolStatus),
+    #[error("invalid response: {0}")]
+    InvalidResponse(&'static str),
+}
+
+impl FastCgiError {
+    /// Responded to the client instead of the response of the application
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FastCgiError::Refused(ProtocolStatus::Overloaded) => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// The application didn't process the request, so it's safe to send it again
+    pub fn unsent(&self) -> bool {
+        matches!(
+            self,
+            FastCgiError::Connect(_) | FastCgiError::Stale | FastCgiError::Refused(_)
+        )
+    }
+}
+
+/// `FCGI_PARAMS` of a request, passed through the upstream stack as an extension
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params(Vec<(Vec<u8>, Vec<u8>)>);
+
+impl Params {
+    /// Sets `name`, replacing an earlier value
+    pub fn set(&mut self, name: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) {
+        let (name, value) = (name.into(), value.into());
+        match self.0.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((name, value)),
+        }
+    }
+
+    /// Content of the `FCGI_PARAMS` stream
+    pub fn encode(&self) -> Vec<u8> {
+        record::encode_params(
+            self.0
+                .iter()
+                .map(|(name, value)| (name.as_slice(), value.as_slice())),
+        )
+    }
+}
+
+/// Connections to the FastCGI servers of all sites, by address
+#[derive(Default)]
+pub struct Clients {
+    servers: Mutex<HashMap<String, Arc<Server>>>,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn server(&self, addr: &str) -> Arc<Server> {
+        let mut servers = self.servers.lock().unwrap();
+        servers
+            .entry(addr.to_string())
+            .or_insert_with(|| {
+                Arc::new(Server {
+                    addr: addr.to_string(),
+                    pool: Mutex::default(),
+                    multiplex: Mutex::default(),
+                })
+            })
+            .clone()
+    }
+}
+
+/// The last service of the upstream stack for `fastcgi_pass`, sends requests to the server
+/// picked for them
+#[derive(Clone)]
+pub struct Gateway {
+    clients: Arc<Clients>,
+    connect_timeout: Duration,
+}
+
+impl Gateway {
+    pub fn new(clients: Arc<Clients>, connect_timeout: Duration) -> Self {
+        Self {
+            clients,
+            connect_timeout,
+        }
+    }
+}
+
+impl Service<Request<Body>> for Gateway {
+    type Response = Response<Body>;
+    type Error = ProxyError;
+    type Future = PinResultFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let Picked(pick) = req
+            .extensions()
+            .get::<Picked>()
+            .cloned()
+            .expect("requests are picked before reaching the gateway");
+        let server = self.clients.server(&pick.server().addr);
+        let params = req.extensions_mut().remove::<Params>().unwrap_or_default();
+        let response = server.request(params, req.into_body(), self.connect_timeout);
+
+        Box::pin(async move { Ok(response.await?) })
+    }
+}
+
+struct Server {
+    /// `host:port` or `unix:<path>`
+    addr: String,
+    pool: Mutex<Vec<Pooled>>,
+    /// Whether the application takes concurrent requests on one connection, asked once
+    multiplex: Mutex<Option<bool>>,
+}
+
+struct Pooled {
+    conn: Arc<Conn>,
+    /// Requests in flight
+    active: usize,
+}
+
+/// A connection taken from the pool for one request
+struct Lease {
+    server: Arc<Server>,
+    conn: Arc<Conn>,
+    /// Connections of requests which were given up on may still be busy with them
+    reusable: bool,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.server.release(&self.conn, self.reusable);
+    }
+}
+
+impl Server {
+    async fn request(
+        self: Arc<Self>,
+        params: Params,
+        body: Body,
+        connect_timeout: Duration,
+    ) -> Result<Response<Body>, FastCgiError> {
+        let lease = self.clone().lease(connect_timeout).await?;
+        let (id, events) = lease.conn.start().ok_or(FastCgiError::Stale)?;
+        let conn = lease.conn.clone();
+        let mut exchange = Exchange {
+            id,
+            lease,
+            stdin: None,
+            ended: false,
+        };
+
+        let mut frame = vec![];
+        record::begin_request(&mut frame, id, true);
+        record::encode_stream(&mut frame, RecordType::Params, id, &params.encode());
+        record::encode(&mut frame, RecordType::Params, id, b"");
+        let streamed = !body.is_end_stream();
+        if !streamed {
+            record::encode(&mut frame, RecordType::Stdin, id, b"");
+        }
+        if conn.send(frame).await.is_err() {
+            // nothing was written, there's no request to abort
+            exchange.ended = true;
+            return Err(FastCgiError::Stale);
+        }
+        if streamed {
+            exchange.stdin = Some(tokio::spawn(send_stdin(conn.writer(), id, body)));
+        }
+
+        self.read_response(exchange, events).await
+    }
+
+    /// A connection with room for another request, a new one if there is none
+    async fn lease(self: Arc<Self>, connect_timeout: Duration) -> Result<Lease, FastCgiError> {
+        let multiplex = *self.multiplex.lock().unwrap();
+        let limit = match multiplex {
+            Some(true) => MAX_MULTIPLEXED,
+            _ => 1,
+        };
+        let pooled = {
+            let mut pool = self.pool.lock().unwrap();
+            pool.retain(|pooled| !pooled.conn.is_closed());
+            pool.iter_mut()
+                .find(|pooled| pooled.active < limit)
+                .map(|pooled| {
+                    pooled.active += 1;
+                    pooled.conn.clone()
+                })
+        };
+        if let Some(conn) = pooled {
+            return Ok(Lease {
+                server: self,
+                conn,
+                reusable: true,
+            });
+        }
+
+        if multiplex.is_none() {
+            let multiplex = self.probe(connect_timeout).await?;
+            debug!(
+                "FastCGI server {} multiplexes connections: {}",
+                self.addr, multiplex
+            );
+            *self.multiplex.lock().unwrap() = Some(multiplex);
+        }
+        let conn = Arc::new(Conn::new(self.connect(connect_timeout).await?, &self.addr));
+        self.pool.lock().unwrap().push(Pooled {
+            conn: conn.clone(),
+            active: 1,
+        });
+        Ok(Lease {
+            server: self,
+            conn,
+            reusable: true,
+        })
+    }
+
+    /// Asks for `FCGI_MPXS_CONNS` over a separate connection, since some applications close the
+    /// connection after answering management records
+    async fn probe(&self, connect_timeout: Duration) -> Result<bool, FastCgiError> {
+        let conn = Conn::new(self.connect(connect_timeout).await?, &self.addr);
+        let values = tokio::time::timeout(GET_VALUES_TIMEOUT, conn.get_values(&[MPXS_CONNS]))
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        Ok(values
+            .iter()
+            .any(|(name, value)| name == MPXS_CONNS && value.trim() == "1"))
+    }
+
+    async fn connect(
+        &self,
+        connect_timeout: Duration,
+    ) -> Result<crate::layers::raw::stream::Socket, FastCgiError> {
+        let uri = connect::uri(&self.addr, PathAndQuery::from_static("/"))
+            .map_err(|err| FastCgiError::Connect(err.into()))?;
+        Connector::new(Some(connect_timeout))
+            .oneshot(uri)
+            .await
+            .map_err(FastCgiError::Connect)
+    }
+
+    fn release(&self, conn: &Arc<Conn>, reusable: bool) {
+        let mut pool = self.pool.lock().unwrap();
+        let i = match pool
+            .iter()
+            .position(|pooled| Arc::ptr_eq(&pooled.conn, conn))
+        {
+            Some(i) => i,
+            None => return,
+        };
+        pool[i].active -= 1;
+        let idle = pool.iter().filter(|pooled| pooled.active == 0).count();
+        if pool[i].active == 0 && (!reusable || idle > MAX_IDLE || conn.is_closed()) {
+            pool.swap_remove(i);
+        }
+    }
+
+    /// Waits for the response head, the body is streamed by a separate task
+    async fn read_response(
+        &self,
+        mut exchange: Exchange,
+        mut events: mpsc::Receiver<Event>,
+    ) -> Result<Response<Body>, FastCgiError> {
+        let mut head = vec![];
+        let (parts, rest) = loop {
+            match events.recv().await {
+                Some(Event::Stdout(data)) => {
+                    head.extend_from_slice(&data);
+                    if let Some((end, separator)) = head_end(&head) {
+                        let rest = head.split_off(end + separator);
+                        break (response_head(&head[..end])?, rest);
+                    }
+                    if head.len() > MAX_HEAD {
+                        return Err(FastCgiError::InvalidResponse("response head is too long"));
+                    }
+                }
+                Some(Event::Stderr(data)) => log_stderr(&self.addr, &data),
+                Some(Event::End(status)) => {
+                    exchange.ended = true;
+                    return Err(match status {
+                        ProtocolStatus::RequestComplete => {
+                            FastCgiError::InvalidResponse("incomplete response head")
+                        }
+                        ProtocolStatus::CantMpxConn => {
+                            *self.multiplex.lock().unwrap() = Some(false);
+                            FastCgiError::Refused(status)
+                        }
+                        status => FastCgiError::Refused(status),
+                    });
+                }
+                None => return Err(FastCgiError::Closed),
+            }
+        };
+
+        let (sender, body) = Body::channel();
+        tokio::spawn(stream_body(
+            exchange,
+            events,
+            sender,
+            rest.into(),
+            self.addr.clone(),
+        ));
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+/// A request in flight, aborted if dropped before the application ended it
+struct Exchange {
+    id: u16,
+    lease: Lease,
+    stdin: Option<JoinHandle<()>>,
+    ended: bool,
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        if let Some(stdin) = &self.stdin {
+            stdin.abort();
+        }
+        if !self.ended {
+            self.lease.conn.abort(self.id);
+            self.lease.reusable = false;
+        }
+    }
+}
+
+/// `FCGI_STDIN`, split into records as the body arrives
+async fn send_stdin(writer: mpsc::Sender<Vec<u8>>, id: u16, mut body: Body) {
+    while let Some(data) = body.data().await {
+        let mut frame = vec![];
+        match data {
+            Ok(data) => record::encode_stream(&mut frame, RecordType::Stdin, id, &data),
+            Err(err) => {
+                debug!("Unable to read the request body: {}", err);
+                record::encode(&mut frame, RecordType::AbortRequest, id, b"");
+                let _ = writer.send(frame).await;
+                return;
+            }
+        }
+        if writer.send(frame).await.is_err() {
+            return;
+        }
+    }
+    let mut frame = vec![];
+    record::encode(&mut frame, RecordType::Stdin, id, b"");
+    let _ = writer.send(frame).await;
+}
+
+async fn stream_body(
+    mut exchange: Exchange,
+    mut events: mpsc::Receiver<Event>,
+    mut sender: Sender,
+    first: Bytes,
+    addr: String,
+) {
+    if !first.is_empty() && sender.send_data(first).await.is_err() {
+        return;
+    }
+    while let Some(event) = events.recv().await {
+        match event {
+            Event::Stdout(data) => {
+                if sender.send_data(data).await.is_err() {
+                    debug!("Client went away while streaming the response");
+                    return;
+                }
+            }
+            Event::Stderr(data) => log_stderr(&addr, &data),
+            Event::End(_) => {
+                exchange.ended = true;
+                return;
+            }
+        }
+    }
+    debug!("FastCGI server {} closed the connection mid-response", addr);
+    sender.abort();
+}
+
+fn log_stderr(addr: &str, data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    for line in text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+    {
+        warn!("FastCGI server {}: {}", addr, line);
+    }
+}
+
+/// Position and length of the empty line ending the head
+fn head_end(head: &[u8]) -> Option<(usize, usize)> {
+    let crlf = head.windows(4).position(|window| window == b"\r\n\r\n");
+    let lf = head.windows(2).position(|window| window == b"\n\n");
+    match (crlf, lf) {
+        (Some(crlf), Some(lf)) if lf < crlf => Some((lf, 2)),
+        (Some(crlf), _) => Some((crlf, 4)),
+        (None, lf) => lf.map(|lf| (lf, 2)),
+    }
+}
+
+/// CGI response headers, RFC 3875 section 6.3
+///
+/// `Status` sets the status code, which defaults to `302 Found` with `Location` and `200 OK`
+/// otherwise.
+fn response_head(head: &[u8]) -> Result<Parts, FastCgiError> {
+    let (mut parts, ()) = Response::new(()).into_parts();
+    let mut status = None;
+    for line in head.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let colon = line
+            .iter()
+            .position(|b| *b == b':')
+            .ok_or(FastCgiError::InvalidResponse("invalid header line"))?;
+        let (name, value) = (&line[..colon], line[colon + 1..].trim_ascii());
+
+        if name.eq_ignore_ascii_case(b"status") {
+            let code = value
+                .get(..3)
+                .and_then(|code| StatusCode::from_bytes(code).ok())
+                .ok_or(FastCgiError::InvalidResponse("invalid status"))?;
+            status = Some(code);
+            continue;
+        }
+        let name = HeaderName::from_bytes(name)
+            .map_err(|_| FastCgiError::InvalidResponse("invalid header name"))?;
+        let value = HeaderValue::from_bytes(value)
+            .map_err(|_| FastCgiError::InvalidResponse("invalid header value"))?;
+        parts.headers.append(name, value);
+    }
+
+    parts.status = status.unwrap_or(match parts.headers.contains_key(LOCATION) {
+        true => StatusCode::FOUND,
+        false => StatusCode::OK,
+    });
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::record::MANAGEMENT_ID;
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Answers every request with its `SCRIPT_NAME` followed by the body, `/busy` is refused as
+    /// overloaded. Returns the address and the number of accepted connections.
+    async fn responder(multiplex: bool) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(respond(stream, multiplex));
+            }
+        });
+        (addr, accepted)
+    }
+
+    async fn respond(stream: TcpStream, multiplex: bool) {
+        let (mut reader, mut writer) = stream.into_split();
+        let mut requests = HashMap::<u16, (Vec<u8>, Vec<u8>)>::new();
+        while let Ok(Some(record)) = record::read(&mut reader).await {
+            let id = record.request_id;
+            let mut out = vec![];
+            match record.kind.unwrap() {
+                RecordType::GetValues => {
+                    let value = if multiplex { &b"1"[..] } else { b"0" };
+                    let content = record::encode_params([(MPXS_CONNS.as_bytes(), value)]);
+                    record::encode(
+                        &mut out,
+                        RecordType::GetValuesResult,
+                        MANAGEMENT_ID,
+                        &content,
+                    );
+                    // as php-fpm does
+                    let _ = writer.write_all(&out).await;
+                    return;
+                }
+                RecordType::BeginRequest => {
+                    requests.insert(id, Default::default());
+                }
+                RecordType::Params => requests
+                    .get_mut(&id)
+                    .unwrap()
+                    .0
+                    .extend_from_slice(&record.content),
+                RecordType::Stdin if !record.content.is_empty() => requests
+                    .get_mut(&id)
+                    .unwrap()
+                    .1
+                    .extend_from_slice(&record.content),
+                RecordType::Stdin => {
+                    let (params, stdin) = requests.remove(&id).unwrap();
+                    let params = record::decode_params(&params).unwrap();
+                    let script = params
+                        .iter()
+                        .find(|(name, _)| *name == b"SCRIPT_NAME")
+                        .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+                        .unwrap_or_default();
+                    let status = match script.as_str() {
+                        "/busy" => 2,
+                        _ => {
+                            let notice = format!("PHP Notice: {}", script);
+                            record::encode(&mut out, RecordType::Stderr, id, notice.as_bytes());
+                            let head = format!(
+                                "Status: 201 Created\r\nX-Request-Id: {}\r\n\r\n{} ",
+                                id, script
+                            );
+                            record::encode(&mut out, RecordType::Stdout, id, head.as_bytes());
+                            record::encode_stream(&mut out, RecordType::Stdout, id, &stdin);
+                            record::encode(&mut out, RecordType::Stdout, id, b"");
+                            0
+                        }
+                    };
+                    let end = [0, 0, 0, 0, status, 0, 0, 0];
+                    record::encode(&mut out, RecordType::EndRequest, id, &end);
+                }
+                _ => {}
+            }
+            if writer.write_all(&out).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn params(script: &str) -> Params {
+        let mut params = Params::default();
+        params.set("SCRIPT_NAME", script);
+        params
+    }
+
+    async fn body_of(response: Response<Body>) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn multiplexed() {
+        let (addr, accepted) = responder(true).await;
+        let server = Clients::new().server(&addr);
+        let response = server
+            .clone()
+            .request(params("/warmup"), Body::empty(), TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(body_of(response).await, "/warmup ");
+
+        // still sending its body while the next request is answered on the same connection
+        let (mut stdin, body) = Body::channel();
+        let upload = tokio::spawn(server.clone().request(params("/upload"), body, TIMEOUT));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let response = server
+            .clone()
+            .request(params("/index"), Body::empty(), TIMEOUT)
+            .await
+            .unwrap();
+        let index_id = response.headers()["x-request-id"].clone();
+        assert_eq!(body_of(response).await, "/index ");
+
+        stdin
+            .send_data(Bytes::from(vec![b'a'; record::MAX_CONTENT + 1]))
+            .await
+            .unwrap();
+        stdin.send_data(Bytes::from_static(b"b")).await.unwrap();
+        drop(stdin);
+        let response = upload.await.unwrap().unwrap();
+        assert_ne!(response.headers()["x-request-id"], index_id);
+        let body = body_of(response).await;
+        assert_eq!(body.len(), "/upload ".len() + record::MAX_CONTENT + 2);
+        assert!(body.starts_with("/upload aaa") && body.ends_with("ab"));
+
+        // the probe for `FCGI_MPXS_CONNS` and the shared connection
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn one_request_per_connection() {
+        let (addr, accepted) = responder(false).await;
+        let server = Clients::new().server(&addr);
+        let request = |script| {
+            server
+                .clone()
+                .request(params(script), Body::empty(), TIMEOUT)
+        };
+
+        body_of(request("/a").await.unwrap()).await;
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+        // the idle connection is reused, the concurrent request needs another one
+        let (b, c) = tokio::join!(request("/b"), request("/c"));
+        assert_eq!(body_of(b.unwrap()).await, "/b ");
+        assert_eq!(body_of(c.unwrap()).await, "/c ");
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+        body_of(request("/d").await.unwrap()).await;
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+
+        let err = request("/busy").await.unwrap_err();
+        assert!(matches!(
+            err,
+            FastCgiError::Refused(ProtocolStatus::Overloaded)
+        ));
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(err.unsent());
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = closed.local_addr().unwrap().to_string();
+        drop(closed);
+        let err = Clients::new()
+            .server(&addr)
+            .request(params("/"), Body::empty(), TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FastCgiError::Connect(_)));
+    }
+
+    #[test]
+    fn response_heads() {
+        let head = b"Status: 404 Not Found\r\nContent-Type: text/html\r\nX-A: 1\r\nX-A: 2";
+        let parts = response_head(head).unwrap();
+        assert_eq!(parts.status, StatusCode::NOT_FOUND);
+        assert_eq!(parts.headers["content-type"], "text/html");
+        assert_eq!(parts.headers.get_all("x-a").iter().count(), 2);
+        assert!(!parts.headers.contains_key("status"));
+
+        assert_eq!(
+            response_head(b"Location: /login").unwrap().status,
+            StatusCode::FOUND
+        );
+        assert_eq!(
+            response_head(b"Content-Type: text/plain").unwrap().status,
+            StatusCode::OK
+        );
+        assert!(response_head(b"Status: abc").is_err());
+        assert!(response_head(b"no colon").is_err());
+
+        assert_eq!(head_end(b"A: 1\r\n\r\nbody"), Some((4, 4)));
+        assert_eq!(head_end(b"A: 1\n\nbody\r\n\r\n"), Some((4, 2)));
+        assert_eq!(head_end(b"A: 1\r\n"), None);
+    }
+}
@@ -0,0 +1,258 @@
+//! Framing of the records exchanged with a FastCGI application
+//!
+//! Every record starts with an 8 byte header carrying its type, the request it belongs to and the
+//! length of its content, which is followed by padding up to a multiple of 8 bytes.
+
+use hyper::body::Bytes;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 8;
+/// Longest content of a single record, streams are split into multiple records
+pub const MAX_CONTENT: usize = u16::MAX as usize;
+/// Management records aren't part of any request
+pub const MANAGEMENT_ID: u16 = 0;
+
+/// `FCGI_KEEP_CONN`, the application must not close the connection after responding
+const KEEP_CONN: u8 = 1;
+/// `FCGI_RESPONDER`, the only role bolt asks for
+const RESPONDER: u16 = 1;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RecordType {
+    BeginRequest = 1,
+    AbortRequest = 2,
+    EndRequest = 3,
+    Params = 4,
+    Stdin = 5,
+    Stdout = 6,
+    Stderr = 7,
+    Data = 8,
+    GetValues = 9,
+    GetValuesResult = 10,
+    UnknownType = 11,
+}
+
+impl RecordType {
+    fn from_u8(kind: u8) -> Option<Self> {
+        Some(match kind {
+            1 => RecordType::BeginRequest,
+            2 => RecordType::AbortRequest,
+            3 => RecordType::EndRequest,
+            4 => RecordType::Params,
+            5 => RecordType::Stdin,
+            6 => RecordType::Stdout,
+            7 => RecordType::Stderr,
+            8 => RecordType::Data,
+            9 => RecordType::GetValues,
+            10 => RecordType::GetValuesResult,
+            11 => RecordType::UnknownType,
+            _ => return None,
+        })
+    }
+}
+
+/// `protocolStatus` of `FCGI_END_REQUEST`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ProtocolStatus {
+    RequestComplete,
+    /// The application doesn't take concurrent requests on one connection
+    CantMpxConn,
+    /// The application ran out of resources, e.g. its worker processes
+    Overloaded,
+    UnknownRole,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Record {
+    /// `None` for types this implementation doesn't know
+    pub kind: Option<RecordType>,
+    pub request_id: u16,
+    pub content: Bytes,
+}
+
+/// Appends a record, `content` has to fit into a single one
+pub fn encode(buf: &mut Vec<u8>, kind: RecordType, request_id: u16, content: &[u8]) {
+    assert!(content.len() <= MAX_CONTENT, "record content is too long");
+    let padding = (8 - content.len() % 8) % 8;
+    let [id_hi, id_lo] = request_id.to_be_bytes();
+    let [len_hi, len_lo] = (content.len() as u16).to_be_bytes();
+    buf.extend_from_slice(&[
+        VERSION,
+        kind as u8,
+        id_hi,
+        id_lo,
+        len_hi,
+        len_lo,
+        padding as u8,
+        0,
+    ]);
+    buf.extend_from_slice(content);
+    buf.resize(buf.len() + padding, 0);
+}
+
+/// Appends `content` as part of a stream, split into as many records as needed
+///
+/// Empty content is skipped since an empty record ends the stream.
+pub fn encode_stream(buf: &mut Vec<u8>, kind: RecordType, request_id: u16, content: &[u8]) {
+    for chunk in content.chunks(MAX_CONTENT) {
+        encode(buf, kind, request_id, chunk);
+    }
+}
+
+/// `FCGI_BEGIN_REQUEST` for the responder role
+pub fn begin_request(buf: &mut Vec<u8>, request_id: u16, keep_conn: bool) {
+    let [role_hi, role_lo] = RESPONDER.to_be_bytes();
+    let flags = if keep_conn { KEEP_CONN } else { 0 };
+    let body = [role_hi, role_lo, flags, 0, 0, 0, 0, 0];
+    encode(buf, RecordType::BeginRequest, request_id, &body);
+}
+
+/// `appStatus` and `protocolStatus` of `FCGI_END_REQUEST`
+pub fn end_request(content: &[u8]) -> Option<(u32, ProtocolStatus)> {
+    let app_status = u32::from_be_bytes(content.get(..4)?.try_into().ok()?);
+    let status = match content.get(4)? {
+        0 => ProtocolStatus::RequestComplete,
+        1 => ProtocolStatus::CantMpxConn,
+        2 => ProtocolStatus::Overloaded,
+        3 => ProtocolStatus::UnknownRole,
+        _ => return None,
+    };
+    Some((app_status, status))
+}
+
+/// Name-value pairs of `FCGI_PARAMS` and `FCGI_GET_VALUES`, lengths below 128 take a single
+/// byte, longer ones four with the high bit set
+pub fn encode_params<'p>(params: impl IntoIterator<Item = (&'p [u8], &'p [u8])>) -> Vec<u8> {
+    let mut buf = vec![];
+    for (name, value) in params {
+        encode_length(&mut buf, name.len());
+        encode_length(&mut buf, value.len());
+        buf.extend_from_slice(name);
+        buf.extend_from_slice(value);
+    }
+    buf
+}
+
+fn encode_length(buf: &mut Vec<u8>, len: usize) {
+    match u8::try_from(len) {
+        Ok(len) if len < 0x80 => buf.push(len),
+        _ => buf.extend_from_slice(&(len as u32 | 0x8000_0000).to_be_bytes()),
+    }
+}
+
+/// The pairs of a complete params stream, `None` if it's truncated
+pub fn decode_params(mut buf: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let mut params = vec![];
+    while !buf.is_empty() {
+        let name_len = decode_length(&mut buf)?;
+        let value_len = decode_length(&mut buf)?;
+        if buf.len() < name_len + value_len {
+            return None;
+        }
+        let (name, rest) = buf.split_at(name_len);
+        let (value, rest) = rest.split_at(value_len);
+        params.push((name, value));
+        buf = rest;
+    }
+    Some(params)
+}
+
+fn decode_length(buf: &mut &[u8]) -> Option<usize> {
+    let first = *buf.first()?;
+    if first < 0x80 {
+        *buf = &buf[1..];
+        return Some(first as usize);
+    }
+    let len = u32::from_be_bytes(buf.get(..4)?.try_into().ok()?) & 0x7fff_ffff;
+    *buf = &buf[4..];
+    Some(len as usize)
+}
+
+/// Reads the next record, `None` if the connection was closed in between records
+pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Record>> {
+    let mut header = [0; HEADER_LEN];
+    match reader.read(&mut header[..1]).await? {
+        0 => return Ok(None),
+        _ => reader.read_exact(&mut header[1..]).await?,
+    };
+    if header[0] != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported FastCGI version {}", header[0]),
+        ));
+    }
+
+    let request_id = u16::from_be_bytes([header[2], header[3]]);
+    let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut content = vec![0; len + header[6] as usize];
+    reader.read_exact(&mut content).await?;
+    content.truncate(len);
+
+    Ok(Some(Record {
+        kind: RecordType::from_u8(header[1]),
+        request_id,
+        content: content.into(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn params() {
+        let long = vec![b'x'; 300];
+        let encoded = encode_params([
+            (&b"SCRIPT_NAME"[..], &b"/index.php"[..]),
+            (b"EMPTY", b""),
+            (b"LONG", &long),
+        ]);
+        assert_eq!(&encoded[..2], &[11, 10]);
+        assert_eq!(&encoded[30..36], &[4, 0x80, 0, 1, 44, b'L']);
+        assert_eq!(
+            decode_params(&encoded).unwrap(),
+            vec![
+                (&b"SCRIPT_NAME"[..], &b"/index.php"[..]),
+                (b"EMPTY", b""),
+                (b"LONG", &long),
+            ]
+        );
+        assert_eq!(decode_params(&encoded[..encoded.len() - 1]), None);
+    }
+
+    #[tokio::test]
+    async fn framing() {
+        let mut buf = vec![];
+        begin_request(&mut buf, 1, true);
+        encode_stream(&mut buf, RecordType::Stdin, 1, &vec![7; MAX_CONTENT + 3]);
+        encode(&mut buf, RecordType::Stdin, 1, b"");
+        assert_eq!(
+            &buf[..16],
+            &[1, 1, 0, 1, 0, 8, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0]
+        );
+        // padded to a multiple of 8
+        assert_eq!(buf.len(), 16 + (8 + MAX_CONTENT + 1) + (8 + 3 + 5) + 8);
+
+        let mut reader = &buf[..];
+        let begin = read(&mut reader).await.unwrap().unwrap();
+        assert_eq!(begin.kind, Some(RecordType::BeginRequest));
+        assert_eq!(begin.request_id, 1);
+        let stdin = read(&mut reader).await.unwrap().unwrap();
+        assert_eq!(stdin.content.len(), MAX_CONTENT);
+        assert_eq!(
+            read(&mut reader).await.unwrap().unwrap().content,
+            &[7; 3][..]
+        );
+        assert_eq!(read(&mut reader).await.unwrap().unwrap().content, "");
+        assert_eq!(read(&mut reader).await.unwrap(), None);
+
+        assert!(read(&mut &buf[..12]).await.is_err());
+        assert_eq!(
+            end_request(&[0, 0, 0, 255, 2, 0, 0, 0]),
+            Some((255, ProtocolStatus::Overloaded))
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Listings of directories without an index file, as HTML or JSON depending on `Accept`
+
+use crate::handlers::status;
+use crate::mime::{MimeTypes, DEFAULT_TYPE};
+use bolt_config::model::Autoindex;
+use hyper::header::{HeaderMap, ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use tracing::debug;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Html,
+    Json,
+}
+
+/// Lists `dir`, `parts` are the decoded segments of the request path
+///
+/// Entries are followed like any other file, names which aren't valid UTF-8 are left out.
+pub async fn list(
+    req: &Request<Body>,
+    dir: &Path,
+    parts: &[String],
+    autoindex: Autoindex,
+    types: &MimeTypes,
+) -> Response<Body> {
+    let mut entries = match read(dir, autoindex.dotfiles).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            debug!("Unable to list {}: {}", dir.display(), err);
+            return status(StatusCode::FORBIDDEN);
+        }
+    };
+    entries.sort_by(order);
+
+    let (extension, body) = match format(req.headers()) {
+        Format::Html => ("html", html(parts, &entries)),
+        Format::Json => ("json", json(&entries)),
+    };
+    let content_type = types.for_extension(extension).unwrap_or(DEFAULT_TYPE);
+
+    let response = Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_LENGTH, body.len())
+        .header(VARY, "Accept");
+    let body = match req.method() {
+        &Method::HEAD => Body::empty(),
+        _ => Body::from(body),
+    };
+    response.body(body).unwrap()
+}
+
+async fn read(dir: &Path, dotfiles: bool) -> std::io::Result<Vec<Entry>> {
+    let mut entries = vec![];
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = match entry.file_name().into_string() {
+            Ok(name) if dotfiles || !name.starts_with('.') => name,
+            _ => continue,
+        };
+        // follows symlinks, broken ones are skipped
+        let metadata = match tokio::fs::metadata(entry.path()).await {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        entries.push(Entry {
+            name,
+            dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Directories first, then by name
+fn order(a: &Entry, b: &Entry) -> Ordering {
+    b.dir.cmp(&a.dir).then_with(|| a.name.cmp(&b.name))
+}
+
+/// JSON if the client prefers `application/json` over `text/html`
+fn format(headers: &HeaderMap) -> Format {
+    let ranges = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let range = params.next()?.to_ascii_lowercase();
+            let q = params
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(Some(1.0), |(_, q)| q.trim().parse::<f32>().ok())?;
+            Some((range, q))
+        })
+        .collect::<Vec<_>>();
+
+    // the most specific matching range decides
+    let quality = |mime: &str| {
+        let (kind, _) = mime.split_once('/').unwrap_or((mime, ""));
+        let wildcard = format!("{}/*", kind);
+        let q = [mime, &wildcard, "*/*"].into_iter().find_map(|candidate| {
+            ranges
+                .iter()
+                .find(|(range, _)| range == candidate)
+                .map(|(_, q)| *q)
+        });
+        q
+    };
+
+    match (quality("text/html"), quality("application/json")) {
+        (html, Some(json)) if json > 0.0 && json > html.unwrap_or(0.0) => Format::Json,
+        _ => Format::Html,
+    }
+}
+
+fn html(parts: &[String], entries: &[Entry]) -> String {
+    let mut path = String::from("/");
+    for part in parts {
+        path.push_str(part);
+        path.push('/');
+    }
+    let title = escape_html(&path);
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Index of {title}</title>\n</head>\n<body>\n<h1>Index of {title}</h1>\n\
+         <table>\n<tr><th>Name</th><th>Last modified</th><th>Size</th></tr>\n",
+    );
+    if !parts.is_empty() {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let suffix = if entry.dir { "/" } else { "" };
+        let modified = entry.modified.map(httpdate::fmt_http_date);
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{href}{suffix}\">{name}{suffix}</a></td><td>{modified}</td><td>{size}</td></tr>",
+            href = bolt_url::encode_segment(&entry.name),
+            name = escape_html(&entry.name),
+            modified = modified.as_deref().unwrap_or("-"),
+            size = match entry.dir {
+                true => "-".to_string(),
+                false => entry.size.to_string(),
+            },
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn json(entries: &[Entry]) -> String {
+    let mut json = String::from("[");
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"name\":{},\"type\":\"{}\"",
+            escape_json(&entry.name),
+            if entry.dir { "directory" } else { "file" }
+        );
+        if let Some(modified) = entry.modified {
+            let _ = write!(json, ",\"mtime\":\"{}\"", httpdate::fmt_http_date(modified));
+        }
+        if !entry.dir {
+            let _ = write!(json, ",\"size\":{}", entry.size);
+        }
+        json.push('}');
+    }
+    json.push(']');
+    json
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(name: &str, dir: bool) -> Entry {
+        Entry {
+            name: name.to_string(),
+            dir,
+            size: 42,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(1_000_000)),
+        }
+    }
+
+    fn format_for(accept: &str) -> Format {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, accept.parse().unwrap());
+        format(&headers)
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(format(&HeaderMap::new()), Format::Html);
+        assert_eq!(format_for("application/json"), Format::Json);
+        assert_eq!(format_for("text/html, application/json"), Format::Html);
+        assert_eq!(
+            format_for("text/html;q=0.5, application/json"),
+            Format::Json
+        );
+        assert_eq!(format_for("application/*, text/*;q=0.1"), Format::Json);
+        assert_eq!(format_for("*/*"), Format::Html);
+        assert_eq!(format_for("application/json;q=0"), Format::Html);
+    }
+
+    #[test]
+    fn sorting() {
+        let mut entries = [entry("b", false), entry("z", true), entry("a", false)];
+        entries.sort_by(order);
+        let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["z", "a", "b"]);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            escape_html("<a href=\"x\">'&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+        assert_eq!(escape_json("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+
+    #[test]
+    fn rendering() {
+        let entries = vec![entry("sub", true), entry("<x>.txt", false)];
+
+        let html = html(&["a&b".to_string()], &entries);
+        assert!(html.contains("<title>Index of /a&amp;b/</title>"));
+        assert!(html.contains("<a href=\"../\">../</a>"));
+        assert!(html.contains("<a href=\"sub/\">sub/</a>"));
+        assert!(html.contains(
+            "<a href=\"%3Cx%3E.txt\">&lt;x&gt;.txt</a></td><td>Mon, 12 Jan 1970 13:46:40 GMT</td><td>42</td>"
+        ));
+
+        assert_eq!(
+            json(&entries),
+            "[{\"name\":\"sub\",\"type\":\"directory\",\"mtime\":\"Mon, 12 Jan 1970 13:46:40 GMT\"},\
+             {\"name\":\"<x>.txt\",\"type\":\"file\",\"mtime\":\"Mon, 12 Jan 1970 13:46:40 GMT\",\"size\":42}]"
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Validators and the evaluation of conditional requests, following the precedence of
+//! [RFC 9110 section 13.2.2](https://www.rfc-editor.org/rfc/rfc9110#section-13.2.2)
+
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    IF_UNMODIFIED_SINCE,
+};
+use hyper::Method;
+use std::fmt::{Display, Formatter};
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    pub weak: bool,
+    /// Opaque tag, without quotes
+    pub tag: String,
+}
+
+/// Validators of a file, sent as `ETag` and `Last-Modified`
+#[derive(Debug, Clone)]
+pub struct Validators {
+    pub etag: ETag,
+    pub last_modified: SystemTime,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Precondition {
+    /// Respond normally
+    Proceed,
+    /// `304 Not Modified`
+    NotModified,
+    /// `412 Precondition Failed`
+    Failed,
+}
+
+impl Validators {
+    /// The etag is built from inode, size and modification time. Files modified within the last
+    /// second only get a weak etag, as they could change again without the modification time
+    /// changing.
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let mtime = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let weak = SystemTime::now()
+            .duration_since(modified)
+            .map_or(true, |age| age < Duration::from_secs(1));
+
+        Self {
+            etag: ETag {
+                weak,
+                tag: format!(
+                    "{:x}-{:x}-{:x}",
+                    inode(metadata),
+                    metadata.len(),
+                    mtime.as_nanos()
+                ),
+            },
+            // http dates only have a resolution of seconds
+            last_modified: UNIX_EPOCH + Duration::from_secs(mtime.as_secs()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_: &Metadata) -> u64 {
+    0
+}
+
+impl ETag {
+    /// Parses a single entity tag, `W/"tag"` or `"tag"`
+    pub fn parse(value: &str) -> Option<Self> {
+        let (weak, value) = match value.strip_prefix("W/") {
+            Some(value) => (true, value),
+            None => (false, value),
+        };
+        let tag = value.strip_prefix('"')?.strip_suffix('"')?;
+        if tag.contains('"') {
+            return None;
+        }
+
+        Some(Self {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Both tags are strong and identical
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Identical tags, ignoring weakness
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl Display for ETag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// Evaluates the preconditions of a request against the current validators
+pub fn evaluate(method: &Method, headers: &HeaderMap, validators: &Validators) -> Precondition {
+    let safe = method == Method::GET || method == Method::HEAD;
+
+    // 1. If-Match, 2. If-Unmodified-Since when there is no If-Match
+    if headers.contains_key(IF_MATCH) {
+        if !matches_any(headers, IF_MATCH, |tag| tag.strong_eq(&validators.etag)) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = date(headers, IF_UNMODIFIED_SINCE) {
+        if validators.last_modified > since {
+            return Precondition::Failed;
+        }
+    }
+
+    // 3. If-None-Match, 4. If-Modified-Since when there is no If-None-Match
+    if headers.contains_key(IF_NONE_MATCH) {
+        if matches_any(headers, IF_NONE_MATCH, |tag| tag.weak_eq(&validators.etag)) {
+            return match safe {
+                true => Precondition::NotModified,
+                false => Precondition::Failed,
+            };
+        }
+    } else if let Some(since) = date(headers, IF_MODIFIED_SINCE).filter(|_| safe) {
+        if validators.last_modified <= since {
+            return Precondition::NotModified;
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// `*` matches any current representation, otherwise one of the listed tags has to match
+fn matches_any(headers: &HeaderMap, name: HeaderName, matches: impl Fn(&ETag) -> bool) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|value| value == "*" || ETag::parse(value).is_some_and(|tag| matches(&tag)))
+}
+
+/// Invalid dates are ignored, as required for `If-Modified-Since` and `If-Unmodified-Since`
+fn date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+}
+
+pub fn http_date(time: SystemTime) -> HeaderValue {
+    HeaderValue::from_str(&httpdate::fmt_http_date(time)).expect("http dates are valid headers")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn validators() -> Validators {
+        Validators {
+            etag: ETag {
+                weak: false,
+                tag: "abc".to_string(),
+            },
+            last_modified: UNIX_EPOCH + Duration::from_secs(1_000_000),
+        }
+    }
+
+    fn evaluate_with(method: Method, headers: &[(&'static str, &str)]) -> Precondition {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, value.parse().unwrap());
+        }
+        evaluate(&method, &map, &validators())
+    }
+
+    const BEFORE: &str = "Mon, 12 Jan 1970 13:46:39 GMT";
+    const EXACT: &str = "Mon, 12 Jan 1970 13:46:40 GMT";
+
+    #[test]
+    fn etags() {
+        assert_eq!(
+            ETag::parse("W/\"x\""),
+            Some(ETag {
+                weak: true,
+                tag: "x".to_string()
+            })
+        );
+        assert_eq!(ETag::parse("\"x\"").map(|t| t.weak), Some(false));
+        assert_eq!(ETag::parse("x"), None);
+        assert_eq!(ETag::parse("\"x"), None);
+        assert_eq!(validators().etag.to_string(), "\"abc\"");
+    }
+
+    #[test]
+    fn none() {
+        assert_eq!(evaluate_with(Method::GET, &[]), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_none_match() {
+        use Precondition::*;
+
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-none-match", "\"abc\"")]),
+            NotModified
+        );
+        assert_eq!(
+            evaluate_with(Method::HEAD, &[("if-none-match", "W/\"abc\"")]),
+            NotModified
+        );
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-none-match", "\"x\", \"abc\"")]),
+            NotModified
+        );
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-none-match", "*")]),
+            NotModified
+        );
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-none-match", "\"x\"")]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate_with(Method::POST, &[("if-none-match", "\"abc\"")]),
+            Failed
+        );
+    }
+
+    #[test]
+    fn if_match() {
+        use Precondition::*;
+
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-match", "\"abc\"")]),
+            Proceed
+        );
+        assert_eq!(evaluate_with(Method::GET, &[("if-match", "*")]), Proceed);
+        // weak tags never match strongly
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-match", "W/\"abc\"")]),
+            Failed
+        );
+        assert_eq!(evaluate_with(Method::GET, &[("if-match", "\"x\"")]), Failed);
+    }
+
+    #[test]
+    fn dates() {
+        use Precondition::*;
+
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-modified-since", EXACT)]),
+            NotModified
+        );
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-modified-since", BEFORE)]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate_with(Method::POST, &[("if-modified-since", EXACT)]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-modified-since", "garbage")]),
+            Proceed
+        );
+
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-unmodified-since", EXACT)]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate_with(Method::GET, &[("if-unmodified-since", BEFORE)]),
+            Failed
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        use Precondition::*;
+
+        // If-None-Match takes precedence over If-Modified-Since
+        assert_eq!(
+            evaluate_with(
+                Method::GET,
+                &[("if-none-match", "\"x\""), ("if-modified-since", EXACT)]
+            ),
+            Proceed
+        );
+        // If-Match takes precedence over If-Unmodified-Since
+        assert_eq!(
+            evaluate_with(
+                Method::GET,
+                &[("if-match", "\"abc\""), ("if-unmodified-since", BEFORE)]
+            ),
+            Proceed
+        );
+        // failed If-Match wins over a matching If-None-Match
+        assert_eq!(
+            evaluate_with(
+                Method::GET,
+                &[("if-match", "\"x\""), ("if-none-match", "\"abc\"")]
+            ),
+            Failed
+        );
+    }
+}
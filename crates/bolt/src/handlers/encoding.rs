@@ -0,0 +1,150 @@
+//! `Accept-Encoding` negotiation as specified in
+//! [RFC 9110 section 12.5.3](https://www.rfc-editor.org/rfc/rfc9110#section-12.5.3)
+
+use bolt_config::model::Encoding;
+use hyper::header::{HeaderMap, ACCEPT_ENCODING};
+
+/// Quality values in thousandths, `q=0.5` is `500`
+type Quality = u16;
+
+const MAX_QUALITY: Quality = 1000;
+
+/// The encodings of `offered` the client accepts, most preferred first
+///
+/// Equally preferred encodings keep the order of `offered`. Encodings the client explicitly likes
+/// less than `identity` are left out, as is everything if there's no `Accept-Encoding`.
+pub fn acceptable(headers: &HeaderMap, offered: &[Encoding]) -> Vec<Encoding> {
+    if !headers.contains_key(ACCEPT_ENCODING) {
+        return vec![];
+    }
+    let accepted = parse(headers);
+    let quality = |token: &str| {
+        accepted
+            .iter()
+            .find(|(name, _)| name == token)
+            .or_else(|| accepted.iter().find(|(name, _)| name == "*"))
+            .map(|(_, q)| *q)
+    };
+
+    // only compared against if the client states a preference for it
+    let identity = quality("identity");
+
+    let mut encodings = offered
+        .iter()
+        .filter_map(|&encoding| {
+            let q = quality(encoding.token())?;
+            (q > 0 && identity.is_none_or(|identity| q >= identity)).then_some((encoding, q))
+        })
+        .collect::<Vec<_>>();
+    encodings.sort_by_key(|(_, q)| std::cmp::Reverse(*q));
+    encodings
+        .into_iter()
+        .map(|(encoding, _)| encoding)
+        .collect()
+}
+
+/// Lowercase codings with their quality, invalid elements are skipped
+fn parse(headers: &HeaderMap) -> Vec<(String, Quality)> {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            let mut params = element.split(';').map(str::trim);
+            let coding = params.next().filter(|coding| !coding.is_empty())?;
+            let coding = match coding.to_ascii_lowercase() {
+                // deprecated alias, recipients should treat it as gzip
+                alias if alias == "x-gzip" => "gzip".to_string(),
+                coding => coding,
+            };
+
+            let mut q = MAX_QUALITY;
+            for param in params {
+                if let Some((name, value)) = param.split_once('=') {
+                    if name.trim().eq_ignore_ascii_case("q") {
+                        q = quality(value.trim())?;
+                    }
+                }
+            }
+            Some((coding, q))
+        })
+        .collect()
+}
+
+/// `0`, `1`, or up to three decimals, `None` if invalid
+fn quality(value: &str) -> Option<Quality> {
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let thousandths = format!("{:0<3}", frac).parse::<Quality>().ok()?;
+    match int {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(MAX_QUALITY),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use Encoding::*;
+
+    const ALL: &[Encoding] = &[Brotli, Gzip, Zstd];
+
+    fn accept(value: &str, offered: &[Encoding]) -> Vec<Encoding> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+        acceptable(&headers, offered)
+    }
+
+    #[test]
+    fn qualities() {
+        assert_eq!(quality("1"), Some(1000));
+        assert_eq!(quality("1.000"), Some(1000));
+        assert_eq!(quality("0.5"), Some(500));
+        assert_eq!(quality("0.05"), Some(50));
+        assert_eq!(quality("0"), Some(0));
+        assert_eq!(quality("1.5"), None);
+        assert_eq!(quality("0.0001"), None);
+        assert_eq!(quality("-1"), None);
+        assert_eq!(quality(".5"), None);
+    }
+
+    #[test]
+    fn preference() {
+        assert_eq!(acceptable(&HeaderMap::new(), ALL), vec![]);
+        assert_eq!(accept("", ALL), vec![]);
+        // ties keep the configured order
+        assert_eq!(accept("gzip, deflate, br", ALL), vec![Brotli, Gzip]);
+        assert_eq!(accept("gzip, br", &[Gzip, Brotli]), vec![Gzip, Brotli]);
+        assert_eq!(
+            accept("br;q=0.5, gzip;q=0.8, zstd", ALL),
+            vec![Zstd, Gzip, Brotli]
+        );
+        assert_eq!(accept("GZIP; Q=0.1", ALL), vec![Gzip]);
+        assert_eq!(accept("x-gzip", ALL), vec![Gzip]);
+        // only offered encodings are returned
+        assert_eq!(accept("br, gzip", &[Gzip]), vec![Gzip]);
+    }
+
+    #[test]
+    fn exclusions() {
+        assert_eq!(accept("br;q=0, gzip", ALL), vec![Gzip]);
+        assert_eq!(accept("*", ALL), vec![Brotli, Gzip, Zstd]);
+        assert_eq!(accept("*;q=0.5, br", ALL), vec![Brotli, Gzip, Zstd]);
+        assert_eq!(accept("*;q=0, gzip", ALL), vec![Gzip]);
+        // invalid elements are ignored
+        assert_eq!(accept("br;q=2, gzip", ALL), vec![Gzip]);
+    }
+
+    #[test]
+    fn identity() {
+        // the unencoded file is preferred
+        assert_eq!(accept("gzip;q=0.5, identity", ALL), vec![]);
+        assert_eq!(accept("gzip;q=0.5, identity;q=0.5", ALL), vec![Gzip]);
+        assert_eq!(accept("gzip;q=0.1, identity;q=0", ALL), vec![Gzip]);
+    }
+}
@@ -0,0 +1,257 @@
+//! `fastcgi_pass`, sends requests to a FastCGI application such as php-fpm
+
+use crate::fastcgi::{self, Gateway, Params};
+use crate::handlers::proxy::{strip_hop_by_hop, Route};
+use crate::handlers::status;
+use crate::layers::http::vars::RequestVariables;
+use crate::layers::upstream;
+use crate::layers::upstream::timeout::{Timeouts, DEFAULT_CONNECT_TIMEOUT};
+use bolt_config::model::{FastCgiParam, Root};
+use bolt_config::template::{Variable, Variables};
+use hyper::header::{HeaderName, CONTENT_LENGTH, CONTENT_TYPE, COOKIE};
+use hyper::{Body, Request, Response};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+/// Never passed as `HTTP_*`, CGI has dedicated parameters for the first two and `HTTP_PROXY`
+/// would be mistaken for the proxy to use by many applications
+static SKIPPED_HEADERS: &[&str] = &["content-type", "content-length", "proxy"];
+
+/// Sends `req` with `params` to the FastCGI upstream of `route`
+///
+/// Failures are answered like those of `proxy_pass`, an application refusing the request as
+/// overloaded with `503 Service Unavailable`.
+pub async fn forward(
+    clients: &Arc<fastcgi::Clients>,
+    route: Route,
+    mut req: Request<Body>,
+    params: Params,
+) -> Response<Body> {
+    let version = req.version();
+    req.extensions_mut().insert(params);
+
+    let connect_timeout = route.timeouts.connect.unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+    let gateway = Gateway::new(clients.clone(), connect_timeout);
+    let timeouts = Timeouts::from(route.timeouts);
+    let stack = upstream::stack(gateway, route.upstream, route.key, timeouts);
+    match stack.oneshot(req).await {
+        Ok(mut response) => {
+            strip_hop_by_hop(response.headers_mut());
+            *response.version_mut() = version;
+            response
+        }
+        // already logged for each try
+        Err(err) => status(err.status()),
+    }
+}
+
+/// The parameters of RFC 3875 and the request headers as `HTTP_*`, followed by the
+/// `fastcgi_param` of the location which replace those of the same name
+///
+/// `SCRIPT_FILENAME` and `DOCUMENT_ROOT` are only known with a `root` or `alias`. The body is
+/// streamed, so `CONTENT_LENGTH` is empty unless the client sent `Content-Length`.
+pub fn params(
+    variables: &RequestVariables,
+    root: Option<&Root>,
+    fastcgi_params: &[FastCgiParam],
+) -> Params {
+    let req = variables.req;
+    let conn = variables.conn;
+    let uri = variables
+        .get(&Variable::Uri)
+        .map(|uri| uri.into_owned())
+        .unwrap_or_default();
+    // an internal redirect may have replaced the query
+    let query = match variables.path.total().split_once('?') {
+        Some((_, query)) => Some(query),
+        None => req.uri().query(),
+    };
+    let header = |name: HeaderName| {
+        req.headers()
+            .get(name)
+            .map_or(&b""[..], |value| value.as_bytes())
+    };
+
+    let mut params = Params::default();
+    params.set("GATEWAY_INTERFACE", "CGI/1.1");
+    params.set(
+        "SERVER_SOFTWARE",
+        concat!("bolt/", env!("CARGO_PKG_VERSION")),
+    );
+    params.set("SERVER_PROTOCOL", format!("{:?}", req.version()));
+    params.set("REQUEST_METHOD", req.method().as_str());
+    params.set("REQUEST_SCHEME", if conn.secure { "https" } else { "http" });
+    if conn.secure {
+        params.set("HTTPS", "on");
+    }
+    params.set(
+        "REQUEST_URI",
+        req.uri().path_and_query().map_or("/", |pq| pq.as_str()),
+    );
+    params.set("DOCUMENT_URI", uri.as_str());
+    params.set("SCRIPT_NAME", uri.as_str());
+    params.set("QUERY_STRING", query.unwrap_or_default());
+    params.set("CONTENT_TYPE", header(CONTENT_TYPE));
+    params.set("CONTENT_LENGTH", header(CONTENT_LENGTH));
+    params.set("SERVER_NAME", variables.host.unwrap_or_default());
+    params.set("SERVER_ADDR", conn.local.host());
+    if let Some(port) = conn.local.port() {
+        params.set("SERVER_PORT", port.to_string());
+    }
+    params.set("REMOTE_ADDR", conn.peer.host());
+    if let Some(port) = conn.peer.port() {
+        params.set("REMOTE_PORT", port.to_string());
+    }
+
+    let script = match root {
+        Some(Root::Root(dir)) => Some((dir, dir.join(uri.trim_start_matches('/')))),
+        Some(Root::Alias(dir)) => Some((dir, dir.join(variables.remainder.join("/")))),
+        None => None,
+    };
+    if let Some((dir, script)) = script {
+        params.set("DOCUMENT_ROOT", path_bytes(dir));
+        params.set("SCRIPT_FILENAME", path_bytes(&script));
+    }
+
+    for name in req.headers().keys() {
+        // `X_Real_IP` would pass for `X-Real-IP` once converted, nginx drops those as well
+        if SKIPPED_HEADERS.contains(&name.as_str()) || name.as_str().contains('_') {
+            continue;
+        }
+        let separator = if name == COOKIE { "; " } else { ", " };
+        let value = req
+            .headers()
+            .get_all(name)
+            .iter()
+            .map(|value| value.as_bytes())
+            .collect::<Vec<_>>()
+            .join(separator.as_bytes());
+        let name = name.as_str().to_ascii_uppercase().replace('-', "_");
+        params.set(format!("HTTP_{}", name), value);
+    }
+
+    for param in fastcgi_params {
+        params.set(param.name.as_str(), param.value.render(variables));
+    }
+    params
+}
+
+fn path_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::http::Connection;
+    use crate::layers::raw::Addr;
+    use bolt_config::model::Text;
+    use bolt_config::template::Template;
+    use bolt_url::UrlPath;
+    use std::path::PathBuf;
+
+    fn get(params: &Params, name: &str) -> Option<String> {
+        let encoded = params.encode();
+        fastcgi::record::decode_params(&encoded)
+            .unwrap()
+            .into_iter()
+            .find(|(param, _)| *param == name.as_bytes())
+            .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+    }
+
+    #[test]
+    fn cgi_params() {
+        let req = Request::builder()
+            .uri("/blog/index.php?p=1")
+            .header("host", "example.com")
+            .header("content-type", "text/plain")
+            .header("cookie", "a=1")
+            .header("cookie", "b=2")
+            .header("x-forwarded-for", "192.0.2.7")
+            .header("x_forwarded_for", "203.0.113.9")
+            .header("X-Real-IP", "192.0.2.7")
+            .header("X_Real_IP", "203.0.113.9")
+            .header("proxy", "http://evil")
+            .body(Body::empty())
+            .unwrap();
+        let conn = Connection {
+            secure: true,
+            sni_hostname: None,
+            alpn_protocol: None,
+            peer: Addr::Ip("192.0.2.1:5000".parse().unwrap()),
+            local: Addr::Unix(Some(PathBuf::from("/run/bolt.sock"))),
+        };
+        let path = "/blog/index.php".parse::<UrlPath>().unwrap();
+        let remainder = path.parts()[1..].to_vec();
+        let variables = RequestVariables {
+            req: &req,
+            conn: &conn,
+            host: Some("example.com"),
+            path: &path,
+            remainder: &remainder,
+            site_captures: None,
+            location_captures: None,
+        };
+        let fastcgi_params = [
+            FastCgiParam {
+                name: "SCRIPT_NAME".to_string(),
+                value: Text::Format(Template::parse("/app$uri").unwrap()),
+            },
+            FastCgiParam {
+                name: "APP_ENV".to_string(),
+                value: Text::Plain("test".to_string()),
+            },
+        ];
+        let root = Root::Alias(PathBuf::from("/srv/blog"));
+        let sent = params(&variables, Some(&root), &fastcgi_params);
+
+        let expected = [
+            ("REQUEST_METHOD", Some("GET")),
+            ("REQUEST_URI", Some("/blog/index.php?p=1")),
+            ("DOCUMENT_URI", Some("/blog/index.php")),
+            ("SCRIPT_NAME", Some("/app/blog/index.php")),
+            ("SCRIPT_FILENAME", Some("/srv/blog/index.php")),
+            ("DOCUMENT_ROOT", Some("/srv/blog")),
+            ("QUERY_STRING", Some("p=1")),
+            ("HTTPS", Some("on")),
+            ("CONTENT_TYPE", Some("text/plain")),
+            ("CONTENT_LENGTH", Some("")),
+            ("SERVER_NAME", Some("example.com")),
+            ("SERVER_ADDR", Some("unix:/run/bolt.sock")),
+            ("SERVER_PORT", None),
+            ("REMOTE_ADDR", Some("192.0.2.1")),
+            ("REMOTE_PORT", Some("5000")),
+            ("HTTP_HOST", Some("example.com")),
+            ("HTTP_COOKIE", Some("a=1; b=2")),
+            ("HTTP_X_FORWARDED_FOR", Some("192.0.2.7")),
+            ("HTTP_X_REAL_IP", Some("192.0.2.7")),
+            ("HTTP_CONTENT_TYPE", None),
+            ("HTTP_PROXY", None),
+            ("APP_ENV", Some("test")),
+        ];
+        for (name, value) in expected {
+            assert_eq!(get(&sent, name).as_deref(), value, "{}", name);
+        }
+
+        // `try_files` redirected to `/index.php?page=old`
+        let path = "/index.php?page=old".parse::<UrlPath>().unwrap();
+        let variables = RequestVariables {
+            path: &path,
+            remainder: &[],
+            ..variables
+        };
+        let root = Root::Root(PathBuf::from("/srv/blog"));
+        let sent = params(&variables, Some(&root), &[]);
+        assert_eq!(get(&sent, "QUERY_STRING").as_deref(), Some("page=old"));
+        assert_eq!(
+            get(&sent, "SCRIPT_FILENAME").as_deref(),
+            Some("/srv/blog/index.php")
+        );
+        assert_eq!(
+            get(&sent, "REQUEST_URI").as_deref(),
+            Some("/blog/index.php?p=1")
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use crate::handlers::conditional::{self, http_date, Precondition, Validators};
+use crate::handlers::range::{self, Multipart, Ranges};
+use crate::handlers::status;
+use crate::handlers::{autoindex, encoding};
+use crate::mime::MimeTypes;
+use bolt_config::model::{Autoindex, Encoding, Root};
+use hyper::body::Bytes;
+use hyper::header::{
+    HeaderValue, ACCEPT_RANGES, ALLOW, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, ETAG, LAST_MODIFIED, LOCATION, VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::debug;
+
+/// Used if neither the location nor the site have an `index` directive
+pub const DEFAULT_INDEX: &[&str] = &["index.html"];
+
+/// Settings of the matched location, falling back to those of the site
+pub struct Options<'c> {
+    pub types: &'c MimeTypes,
+    pub index: Option<&'c [String]>,
+    pub precompressed: &'c [Encoding],
+    pub autoindex: Autoindex,
+}
+
+/// Serves static files from `root`
+///
+/// `parts` are the dot resolved segments of the request path and `remainder` the ones following
+/// the location prefix. Directories are served through their first existing `index` file, or
+/// listed with `autoindex`, requests for directories without a trailing slash are redirected to
+/// include it. Files with a sibling for one of the `precompressed` encodings accepted by the
+/// client are served from that sibling.
+pub async fn serve(
+    req: &Request<Body>,
+    root: &Root,
+    parts: &[String],
+    remainder: &[String],
+    options: Options<'_>,
+) -> Response<Body> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(ALLOW, "GET, HEAD")
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    let path = match root {
+        Root::Root(dir) => locate(dir, parts),
+        Root::Alias(dir) => locate(dir, remainder),
+    };
+    let mut path = match path {
+        Some(path) => path,
+        None => return status(StatusCode::NOT_FOUND),
+    };
+
+    let mut metadata = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => metadata,
+        Err(err) => return io_error(err),
+    };
+
+    if metadata.is_dir() {
+        if !req.uri().path().ends_with('/') {
+            let mut location = format!("{}/", req.uri().path());
+            if let Some(query) = req.uri().query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            return Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(LOCATION, location)
+                .body(Body::empty())
+                .unwrap_or_else(|_| status(StatusCode::BAD_REQUEST));
+        }
+
+        let index = match options.index {
+            Some(index) => index.iter().map(String::as_str).collect(),
+            None => DEFAULT_INDEX.to_vec(),
+        };
+        let mut found = None;
+        for index in index {
+            let candidate = path.join(index);
+            if let Ok(metadata) = tokio::fs::metadata(&candidate).await {
+                if metadata.is_file() {
+                    found = Some((candidate, metadata));
+                    break;
+                }
+            }
+        }
+        match found {
+            Some(index) => (path, metadata) = index,
+            None if options.autoindex.enabled => {
+                return autoindex::list(req, &path, parts, options.autoindex, options.types).await
+            }
+            None => return status(StatusCode::FORBIDDEN),
+        }
+    }
+
+    if !metadata.is_file() {
+        return status(StatusCode::NOT_FOUND);
+    }
+
+    let content_type = options.types.for_path(&path);
+
+    let mut encoding = None;
+    for candidate in encoding::acceptable(req.headers(), options.precompressed) {
+        let sibling = sibling(&path, candidate);
+        if let Ok(sibling_metadata) = tokio::fs::metadata(&sibling).await {
+            if sibling_metadata.is_file() {
+                (path, metadata) = (sibling, sibling_metadata);
+                encoding = Some(candidate);
+                break;
+            }
+        }
+    }
+
+    let mut validators = Validators::from_metadata(&metadata);
+    if let Some(encoding) = encoding {
+        // each encoding is a separate representation and needs an etag of its own
+        validators.etag.tag = format!("{}-{}", validators.etag.tag, encoding.extension());
+    }
+    let etag = HeaderValue::from_str(&validators.etag.to_string()).unwrap();
+    let last_modified = http_date(validators.last_modified);
+
+    let mut response = Response::builder()
+        .header(ETAG, etag)
+        .header(LAST_MODIFIED, last_modified);
+    if !options.precompressed.is_empty() {
+        response = response.header(VARY, "Accept-Encoding");
+    }
+
+    match conditional::evaluate(req.method(), req.headers(), &validators) {
+        Precondition::Proceed => {}
+        Precondition::NotModified => {
+            return response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .unwrap()
+        }
+        Precondition::Failed => return status(StatusCode::PRECONDITION_FAILED),
+    }
+
+    let len = metadata.len();
+    let ranges = match req.method() {
+        &Method::GET => range::requested(req.headers(), &validators, len),
+        _ => Ranges::Full,
+    };
+
+    let mut response = response.header(ACCEPT_RANGES, "bytes");
+    if let Some(encoding) = encoding {
+        response = response.header(CONTENT_ENCODING, encoding.token());
+    }
+
+    if ranges == Ranges::Unsatisfiable {
+        return response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    let file = match req.method() {
+        &Method::HEAD => None,
+        _ => match File::open(&path).await {
+            Ok(file) => Some(file),
+            Err(err) => return io_error(err),
+        },
+    };
+
+    let (response, body) = match ranges {
+        Ranges::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0].clone();
+            let response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_TYPE, content_type)
+                .header(CONTENT_RANGE, range::content_range(&range, len))
+                .header(CONTENT_LENGTH, range.end - range.start);
+            let body = match file {
+                Some(mut file) => match file.seek(SeekFrom::Start(range.start)).await {
+                    Ok(_) => Some(Body::wrap_stream(ReaderStream::new(
+                        file.take(range.end - range.start),
+                    ))),
+                    Err(err) => return io_error(err),
+                },
+                None => None,
+            };
+            (response, body)
+        }
+        Ranges::Partial(ranges) => {
+            let multipart = Multipart::new(content_type, &ranges, len);
+            let response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_TYPE, multipart.content_type())
+                .header(CONTENT_LENGTH, multipart.content_length());
+            (response, file.map(|file| multipart_body(file, multipart)))
+        }
+        _ => {
+            let response = response
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, content_type)
+                .header(CONTENT_LENGTH, len);
+            // streamed in chunks instead of reading the whole file up front
+            (
+                response,
+                file.map(|file| Body::wrap_stream(ReaderStream::new(file))),
+            )
+        }
+    };
+
+    response.body(body.unwrap_or_else(Body::empty)).unwrap()
+}
+
+/// Streams the ranges of `file` from a separate task, the connection is aborted on io errors
+fn multipart_body(mut file: File, multipart: Multipart) -> Body {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let result: Result<(), Box<dyn std::error::Error + Send + Sync>> = async {
+            let mut buf = vec![0; 64 * 1024];
+            for (range, head) in multipart.parts {
+                sender.send_data(Bytes::from(head)).await?;
+
+                file.seek(SeekFrom::Start(range.start)).await?;
+                let mut part = (&mut file).take(range.end - range.start);
+                loop {
+                    let read = part.read(&mut buf).await?;
+                    if read == 0 {
+                        break;
+                    }
+                    sender
+                        .send_data(Bytes::copy_from_slice(&buf[..read]))
+                        .await?;
+                }
+            }
+            sender.send_data(Bytes::from(multipart.tail)).await?;
+            Ok(())
+        }
+        .await;
+
+        if let Err(err) = result {
+            debug!("Unable to send multipart body: {}", err);
+            sender.abort();
+        }
+    });
+
+    body
+}
+
+/// `<path>.<extension>`, where a precompressed version of `path` is expected
+fn sibling(path: &Path, encoding: Encoding) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(encoding.extension());
+    PathBuf::from(name)
+}
+
+/// Appends `parts` to `base`, `None` if a segment isn't a plain file name
+///
+/// Dot segments are expected to be resolved already, they are rejected here as a safeguard
+/// against leaving `base`.
+pub fn locate(base: &Path, parts: &[String]) -> Option<PathBuf> {
+    let mut path = base.to_path_buf();
+    for part in parts {
+        if matches!(part.as_str(), "" | "." | "..") || part.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        path.push(part);
+    }
+    Some(path)
+}
+
+fn io_error(err: std::io::Error) -> Response<Body> {
+    status(match err.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => StatusCode::NOT_FOUND,
+        ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parts(path: &str) -> Vec<String> {
+        path.split('/').map(str::to_string).collect()
+    }
+
+    fn www(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bolt_files_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("docs/empty")).unwrap();
+        std::fs::create_dir_all(dir.join("blog")).unwrap();
+        std::fs::write(dir.join("docs/a.txt"), "a").unwrap();
+        std::fs::write(dir.join("docs/index.html"), "docs").unwrap();
+        std::fs::write(dir.join("blog/home.html"), "blog").unwrap();
+        dir
+    }
+
+    async fn request(
+        method: Method,
+        uri: &str,
+        root: &Root,
+        parts: &[String],
+        remainder: &[String],
+    ) -> Response<Body> {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        // `/blog` is configured with `index 'missing.html' 'home.html'`
+        let index = ["missing.html".to_string(), "home.html".to_string()];
+        let index = uri.starts_with("/blog").then_some(&index[..]);
+        let types = MimeTypes::default();
+        let options = Options {
+            types: &types,
+            index,
+            precompressed: &[],
+            autoindex: Autoindex::default(),
+        };
+        serve(&req, root, parts, remainder, options).await
+    }
+
+    async fn get(uri: &str, root: &Root, parts: &[String], remainder: &[String]) -> Response<Body> {
+        request(Method::GET, uri, root, parts, remainder).await
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn paths_stay_inside_the_base() {
+        let base = Path::new("/srv/www");
+        assert_eq!(
+            locate(base, &parts("docs/a.txt")),
+            Some(PathBuf::from("/srv/www/docs/a.txt"))
+        );
+        assert_eq!(locate(base, &[]), Some(PathBuf::from("/srv/www")));
+        for escaping in ["..", "docs/..", ".", "docs/", "a\\..\\..", "a\0b"] {
+            assert_eq!(locate(base, &parts(escaping)), None, "{:?}", escaping);
+        }
+        assert_eq!(locate(base, &["etc/passwd".to_string()]), None);
+        assert_eq!(locate(base, &["/etc".to_string()]), None);
+    }
+
+    #[tokio::test]
+    async fn root_and_alias() {
+        let dir = www("root");
+        let root = Root::Root(dir.clone());
+
+        let response = get("/docs/a.txt", &root, &parts("docs/a.txt"), &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(body(response).await, "a");
+
+        // the location prefix `/files` is replaced by the alias
+        let alias = Root::Alias(dir.join("docs"));
+        let response = get(
+            "/files/a.txt",
+            &alias,
+            &parts("files/a.txt"),
+            &parts("a.txt"),
+        )
+        .await;
+        assert_eq!(body(response).await, "a");
+
+        let response = get("/docs/b.txt", &root, &parts("docs/b.txt"), &[]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // a file in place of a directory
+        let response = get("/docs/a.txt/b", &root, &parts("docs/a.txt/b"), &[]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = get("/x", &root, &["..".to_string()], &[]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = request(
+            Method::POST,
+            "/docs/a.txt",
+            &root,
+            &parts("docs/a.txt"),
+            &[],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "GET, HEAD");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn directories() {
+        let dir = www("dirs");
+        let root = Root::Root(dir.clone());
+
+        let response = get("/docs/", &root, &parts("docs"), &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(body(response).await, "docs");
+
+        // the first existing `index` file
+        let response = get("/blog/", &root, &parts("blog"), &[]).await;
+        assert_eq!(body(response).await, "blog");
+
+        let response = get("/docs?page=2", &root, &parts("docs"), &[]).await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[LOCATION], "/docs/?page=2");
+
+        // neither an index file nor `autoindex`
+        let response = get("/docs/empty/", &root, &parts("docs/empty"), &[]).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+//! Produce the response for a matched location
+
+use hyper::{Body, Response, StatusCode};
+
+pub mod autoindex;
+pub mod conditional;
+pub mod encoding;
+pub mod fastcgi;
+pub mod files;
+pub mod proxy;
+pub mod range;
+pub mod r#return;
+pub mod try_files;
+pub mod tunnel;
+
+#[derive(thiserror::Error, Debug)]
+pub enum HandlerError {
+    /// `return 444`, the connection is dropped without sending a response
+    #[error("Connection closed by `return 444`")]
+    Close,
+}
+
+/// Response without body
+pub fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
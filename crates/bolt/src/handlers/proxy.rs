@@ -0,0 +1,401 @@
+//! `proxy_pass`, forwards requests to an upstream and streams the response back
+
+use crate::handlers::status;
+use crate::handlers::tunnel::Upgrade;
+use crate::layers::http::Connection;
+use crate::layers::raw::Addr;
+use crate::layers::upstream;
+use crate::layers::upstream::timeout::{Timeouts, DEFAULT_CONNECT_TIMEOUT};
+use crate::upstream::Upstream;
+use bolt_config::model::{ProxyPass, ProxyTimeouts};
+use bolt_url::UrlPath;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONNECTION, FORWARDED, HOST, TE};
+use hyper::{Body, Request, Response, StatusCode, Uri, Version};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower::ServiceExt;
+use tracing::debug;
+
+/// Only meaningful for a single connection, never forwarded
+static HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+static X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+static X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// Pooled connections to the upstreams, shared by all sites
+///
+/// Each connect timeout has its own pair of clients since it's a setting of the connector.
+#[derive(Default)]
+pub struct Clients {
+    clients: Mutex<HashMap<(Duration, bool), upstream::Send>>,
+}
+
+/// The upstream a request is forwarded to and how
+pub struct Route {
+    pub upstream: Arc<Upstream>,
+    /// Rendered key of `balance hash`
+    pub key: Option<String>,
+    pub timeouts: ProxyTimeouts,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, connect_timeout: Duration, h2: bool) -> upstream::Send {
+        let mut clients = self.clients.lock().unwrap();
+        clients
+            .entry((connect_timeout, h2))
+            .or_insert_with(|| upstream::Send::new(connect_timeout, h2))
+            .clone()
+    }
+}
+
+/// Sends `req` to the upstream of `route` with `path_and_query` as target
+///
+/// Upstreams which can't be reached or fail to respond result in `502 Bad Gateway`, those which
+/// time out in `504 Gateway Timeout` and servers with an open circuit in
+/// `503 Service Unavailable`. Upgrades accepted by the upstream are tunnelled.
+pub async fn forward(
+    clients: &Clients,
+    proxy_pass: &ProxyPass,
+    route: Route,
+    mut req: Request<Body>,
+    conn: &Connection,
+    path_and_query: &str,
+) -> Response<Body> {
+    // h2 clients send it as part of the uri, which is about to be replaced
+    let host = original_host(&req, conn);
+    // the server is filled in for each try
+    *req.uri_mut() = match path_and_query.parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(err) => {
+            debug!("Unable to forward to {}: {}", path_and_query, err);
+            return status(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    let version = req.version();
+    let upgrade = Upgrade::take(&mut req);
+    let trailers = accepts_trailers(req.headers());
+    let headers = req.headers_mut();
+    strip_hop_by_hop(headers);
+    // filled in from the uri by the client
+    headers.remove(HOST);
+    // gRPC depends on trailers, which HTTP/2 always supports
+    if proxy_pass.h2 && trailers {
+        headers.insert(TE, HeaderValue::from_static("trailers"));
+    }
+    add_forwarded(headers, conn, host.as_deref());
+    if let Some(upgrade) = &upgrade {
+        upgrade.prepare(&mut req, proxy_pass.h2);
+    }
+    *req.version_mut() = match proxy_pass.h2 {
+        true => Version::HTTP_2,
+        false => Version::HTTP_11,
+    };
+
+    let connect_timeout = route.timeouts.connect.unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+    let send = clients.get(connect_timeout, proxy_pass.h2);
+    let timeouts = Timeouts::from(route.timeouts);
+    let stack = upstream::stack(send, route.upstream, route.key, timeouts);
+    match stack.oneshot(req).await {
+        Ok(mut response) => {
+            strip_hop_by_hop(response.headers_mut());
+            if let Some(upgrade) =
+                upgrade.filter(|upgrade| upgrade.accepted(&response, proxy_pass.h2))
+            {
+                response = upgrade.tunnel(response, timeouts.read);
+            }
+            *response.version_mut() = version;
+            response
+        }
+        // already logged for each try
+        Err(err) => status(err.status()),
+    }
+}
+
+/// Path and query sent to the upstream
+///
+/// With a path in `proxy_pass` the prefix matched by the location is replaced by it, everything
+/// following the prefix is kept. Otherwise the request target is passed on as received, unless an
+/// internal redirect changed the `path`.
+pub fn target(
+    proxy_pass: &ProxyPass,
+    uri: &Uri,
+    path: &UrlPath,
+    remainder: &[String],
+    redirected: bool,
+) -> String {
+    if proxy_pass.path.is_none() && !redirected {
+        return uri
+            .path_and_query()
+            .map_or("/", |pq| pq.as_str())
+            .to_string();
+    }
+
+    let mut target = match &proxy_pass.path {
+        Some(base) if remainder.is_empty() => base.clone(),
+        Some(base) => base.trim_end_matches('/').to_string(),
+        None => String::new(),
+    };
+    let segments = match proxy_pass.path {
+        Some(_) => remainder,
+        None => path.parts(),
+    };
+    for segment in segments {
+        target.push('/');
+        target.push_str(&bolt_url::encode_segment(segment));
+    }
+
+    let (total, query) = match path.total().split_once('?') {
+        Some((total, query)) => (total, Some(query)),
+        None => (path.total(), uri.query()),
+    };
+    if (total.ends_with('/') && !target.ends_with('/')) || target.is_empty() {
+        target.push('/');
+    }
+    if let Some(query) = query {
+        target.push('?');
+        target.push_str(query);
+    }
+    target
+}
+
+/// Removes the headers of [`HOP_BY_HOP`] and all listed in `Connection`
+pub fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
+
+fn accepts_trailers(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| coding.trim().eq_ignore_ascii_case("trailers"))
+}
+
+/// `Host` as sent by the client, including the port
+fn original_host(req: &Request<Body>, conn: &Connection) -> Option<String> {
+    req.headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+        .or(conn.sni_hostname.as_deref())
+        .map(str::to_string)
+}
+
+/// Sets `X-Forwarded-Proto` and `X-Forwarded-Host`, appends to `X-Forwarded-For` and
+/// [RFC 7239](https://www.rfc-editor.org/rfc/rfc7239) `Forwarded`
+fn add_forwarded(headers: &mut HeaderMap, conn: &Connection, host: Option<&str>) {
+    let proto = if conn.secure { "https" } else { "http" };
+    let peer = &conn.peer;
+
+    let mut forwarded_for = headers
+        .get_all(&X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !forwarded_for.is_empty() {
+        forwarded_for.push_str(", ");
+    }
+    forwarded_for.push_str(&peer.host());
+
+    let mut element = format!("for={};proto={}", node(peer), proto);
+    if let Some(host) = host {
+        element.push_str(";host=");
+        element.push_str(&quote(host));
+    }
+    let forwarded = headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .chain([element.as_str()])
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let values = [
+        (X_FORWARDED_FOR.clone(), Some(forwarded_for)),
+        (X_FORWARDED_PROTO.clone(), Some(proto.to_string())),
+        (X_FORWARDED_HOST.clone(), host.map(str::to_string)),
+        (FORWARDED, Some(forwarded)),
+    ];
+    for (name, value) in values {
+        headers.remove(&name);
+        if let Some(value) = value.and_then(|value| HeaderValue::try_from(value).ok()) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// IPv6 addresses are bracketed and have to be quoted because of the colons, clients of Unix
+/// domain sockets are `unknown`
+fn node(addr: &Addr) -> String {
+    match addr.ip() {
+        Some(IpAddr::V4(ip)) => ip.to_string(),
+        Some(IpAddr::V6(ip)) => format!("\"[{}]\"", ip),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Quotes `value` unless it's a token
+fn quote(value: &str) -> String {
+    let token = !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if token {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn proxy_pass(path: Option<&str>) -> ProxyPass {
+        ProxyPass {
+            authority: "127.0.0.1:3000".to_string(),
+            path: path.map(str::to_string),
+            h2: false,
+        }
+    }
+
+    fn conn(peer: &str, secure: bool) -> Connection {
+        Connection {
+            secure,
+            sni_hostname: None,
+            alpn_protocol: None,
+            peer: Addr::Ip(peer.parse().unwrap()),
+            local: Addr::Ip("127.0.0.1:443".parse().unwrap()),
+        }
+    }
+
+    /// Target for a request to `uri` routed to `location ^ '/api'`
+    fn target_of(proxy: Option<&str>, uri: &str) -> String {
+        let uri = uri.parse::<Uri>().unwrap();
+        let path = uri.path().parse::<UrlPath>().unwrap();
+        let remainder = path.parts()[1..].to_vec();
+        target(&proxy_pass(proxy), &uri, &path, &remainder, false)
+    }
+
+    #[test]
+    fn targets() {
+        // passed on as received
+        assert_eq!(target_of(None, "/api/x%2Fy/?a=%20"), "/api/x%2Fy/?a=%20");
+        assert_eq!(target_of(Some("/v1"), "/api/users?id=1"), "/v1/users?id=1");
+        assert_eq!(target_of(Some("/v1/"), "/api/users/"), "/v1/users/");
+        assert_eq!(target_of(Some("/v1/"), "/api"), "/v1/");
+        assert_eq!(target_of(Some("/"), "/api/a%20b"), "/a%20b");
+        assert_eq!(target_of(Some("/"), "/api"), "/");
+
+        let uri = "/old?q=1".parse::<Uri>().unwrap();
+        let path = "/index.html".parse::<UrlPath>().unwrap();
+        assert_eq!(
+            target(&proxy_pass(None), &uri, &path, &[], true),
+            "/index.html?q=1"
+        );
+        let path = "/index.php?page=old".parse::<UrlPath>().unwrap();
+        assert_eq!(
+            target(&proxy_pass(None), &uri, &path, &[], true),
+            "/index.php?page=old"
+        );
+    }
+
+    #[test]
+    fn hop_by_hop() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONNECTION, "keep-alive, X-Secret".parse().unwrap());
+        headers.insert("keep-alive", "timeout=5".parse().unwrap());
+        headers.insert("x-secret", "1".parse().unwrap());
+        headers.insert("upgrade", "websocket".parse().unwrap());
+        headers.insert("x-kept", "1".parse().unwrap());
+        strip_hop_by_hop(&mut headers);
+
+        assert_eq!(
+            headers.keys().map(HeaderName::as_str).collect::<Vec<_>>(),
+            vec!["x-kept"]
+        );
+    }
+
+    #[test]
+    fn forwarded() {
+        let mut headers = HeaderMap::new();
+        add_forwarded(
+            &mut headers,
+            &conn("192.0.2.1:5000", false),
+            Some("example.com"),
+        );
+        assert_eq!(headers[&X_FORWARDED_FOR], "192.0.2.1");
+        assert_eq!(headers[&X_FORWARDED_PROTO], "http");
+        assert_eq!(headers[&X_FORWARDED_HOST], "example.com");
+        assert_eq!(
+            headers[FORWARDED],
+            "for=192.0.2.1;proto=http;host=example.com"
+        );
+
+        // appended to what earlier proxies sent
+        let mut headers = HeaderMap::new();
+        headers.insert(&X_FORWARDED_FOR, "198.51.100.7".parse().unwrap());
+        headers.insert(&X_FORWARDED_PROTO, "ftp".parse().unwrap());
+        headers.insert(FORWARDED, "for=198.51.100.7".parse().unwrap());
+        add_forwarded(
+            &mut headers,
+            &conn("[2001:db8::1]:5000", true),
+            Some("example.com:8443"),
+        );
+        assert_eq!(headers[&X_FORWARDED_FOR], "198.51.100.7, 2001:db8::1");
+        assert_eq!(headers[&X_FORWARDED_PROTO], "https");
+        assert_eq!(
+            headers[FORWARDED],
+            "for=198.51.100.7, for=\"[2001:db8::1]\";proto=https;host=\"example.com:8443\""
+        );
+
+        // clients of Unix domain sockets
+        let mut headers = HeaderMap::new();
+        let mut unix = conn("127.0.0.1:5000", false);
+        unix.peer = Addr::Unix(None);
+        add_forwarded(&mut headers, &unix, None);
+        assert_eq!(headers[&X_FORWARDED_FOR], "unix:");
+        assert_eq!(headers[FORWARDED], "for=unknown;proto=http");
+    }
+}
@@ -0,0 +1,312 @@
+//! `Range` and `If-Range` as specified in
+//! [RFC 9110 section 14](https://www.rfc-editor.org/rfc/rfc9110#section-14)
+
+use crate::handlers::conditional::{ETag, Validators};
+use hyper::header::{HeaderMap, IF_RANGE, RANGE};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+
+/// Requests with more ranges are answered with the whole file instead
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ranges {
+    /// No usable `Range` header, the whole file is sent
+    Full,
+    /// `206 Partial Content`, sorted and without overlaps
+    Partial(Vec<Range<u64>>),
+    /// `416 Range Not Satisfiable`
+    Unsatisfiable,
+}
+
+/// Framing of a `multipart/byteranges` body
+pub struct Multipart {
+    boundary: String,
+    /// Each range with the headers preceding it
+    pub parts: Vec<(Range<u64>, String)>,
+    /// Closing delimiter
+    pub tail: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Spec {
+    /// `500-999`
+    Bounded(u64, u64),
+    /// `500-`
+    From(u64),
+    /// `-500`, the last 500 bytes
+    Suffix(u64),
+}
+
+/// Evaluates `Range` and `If-Range` of a `GET` request for a file of `len` bytes
+pub fn requested(headers: &HeaderMap, validators: &Validators, len: u64) -> Ranges {
+    let specs = match headers
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse)
+    {
+        Some(specs) if specs.len() <= MAX_RANGES => specs,
+        // invalid or unsupported ranges are ignored
+        _ => return Ranges::Full,
+    };
+
+    if !if_range(headers, validators) {
+        return Ranges::Full;
+    }
+
+    let mut ranges = specs
+        .into_iter()
+        .filter_map(|spec| resolve(spec, len))
+        .collect::<Vec<_>>();
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    Ranges::Partial(merged)
+}
+
+impl Multipart {
+    pub fn new(content_type: &str, ranges: &[Range<u64>], len: u64) -> Self {
+        // randomly seeded, so the boundary can't be guessed to be placed inside of a file
+        let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+
+        Self {
+            parts: ranges
+                .iter()
+                .map(|range| {
+                    let head = format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                        boundary,
+                        content_type,
+                        content_range(range, len)
+                    );
+                    (range.clone(), head)
+                })
+                .collect(),
+            tail: format!("\r\n--{}--\r\n", boundary),
+            boundary,
+        }
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    pub fn content_length(&self) -> u64 {
+        self.parts
+            .iter()
+            .map(|(range, head)| head.len() as u64 + (range.end - range.start))
+            .sum::<u64>()
+            + self.tail.len() as u64
+    }
+}
+
+/// `Content-Range` of a satisfied range
+pub fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+/// `None` if the header isn't a valid `bytes` range set
+fn parse(value: &str) -> Option<Vec<Spec>> {
+    let (unit, set) = value.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    set.split(',')
+        .map(str::trim)
+        // empty list elements are allowed
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| {
+            let (start, end) = spec.split_once('-')?;
+            let number = |n: &str| match n.bytes().all(|b| b.is_ascii_digit()) {
+                true => n.parse::<u64>().ok(),
+                false => None,
+            };
+            Some(match (start, end) {
+                ("", end) => Spec::Suffix(number(end)?),
+                (start, "") => Spec::From(number(start)?),
+                (start, end) => {
+                    let (start, end) = (number(start)?, number(end)?);
+                    if end < start {
+                        return None;
+                    }
+                    Spec::Bounded(start, end)
+                }
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|specs| !specs.is_empty())
+}
+
+/// The byte range of the file described by `spec`, `None` if it is unsatisfiable
+fn resolve(spec: Spec, len: u64) -> Option<Range<u64>> {
+    let range = match spec {
+        Spec::Bounded(start, end) => start..end.saturating_add(1).min(len),
+        Spec::From(start) => start..len,
+        Spec::Suffix(suffix) => len.saturating_sub(suffix)..len,
+    };
+    (range.start < range.end).then_some(range)
+}
+
+/// Without `If-Range` ranges are always used, otherwise only if the file hasn't changed
+fn if_range(headers: &HeaderMap, validators: &Validators) -> bool {
+    let value = match headers.get(IF_RANGE).map(|value| value.to_str()) {
+        None => return true,
+        Some(Ok(value)) => value.trim(),
+        Some(Err(_)) => return false,
+    };
+
+    match ETag::parse(value) {
+        Some(etag) => etag.strong_eq(&validators.etag),
+        None => httpdate::parse_http_date(value).is_ok_and(|date| date == validators.last_modified),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn validators(weak: bool) -> Validators {
+        Validators {
+            etag: ETag {
+                weak,
+                tag: "abc".to_string(),
+            },
+            last_modified: UNIX_EPOCH + Duration::from_secs(1_000_000),
+        }
+    }
+
+    fn ranges(headers: &[(&'static str, &str)]) -> Ranges {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, value.parse().unwrap());
+        }
+        requested(&map, &validators(false), 1000)
+    }
+
+    fn range(value: &str) -> Ranges {
+        ranges(&[("range", value)])
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            parse("bytes=0-499, 500-, -200"),
+            Some(vec![
+                Spec::Bounded(0, 499),
+                Spec::From(500),
+                Spec::Suffix(200)
+            ])
+        );
+        assert_eq!(parse("Bytes=1-2,,"), Some(vec![Spec::Bounded(1, 2)]));
+        assert_eq!(parse("bytes="), None);
+        assert_eq!(parse("bytes=5-1"), None);
+        assert_eq!(parse("bytes=-"), None);
+        assert_eq!(parse("bytes=+1-2"), None);
+        assert_eq!(parse("items=1-2"), None);
+        assert_eq!(parse("1-2"), None);
+    }
+
+    #[test]
+    fn single() {
+        assert_eq!(range("bytes=0-499"), Ranges::Partial(vec![0..500]));
+        assert_eq!(range("bytes=500-"), Ranges::Partial(vec![500..1000]));
+        assert_eq!(range("bytes=-200"), Ranges::Partial(vec![800..1000]));
+        // clamped to the file
+        assert_eq!(range("bytes=900-5000"), Ranges::Partial(vec![900..1000]));
+        assert_eq!(range("bytes=-5000"), Ranges::Partial(vec![0..1000]));
+    }
+
+    #[test]
+    fn multiple() {
+        assert_eq!(
+            range("bytes=500-599, 0-99"),
+            Ranges::Partial(vec![0..100, 500..600])
+        );
+        // overlapping and adjacent ranges are merged
+        assert_eq!(
+            range("bytes=0-99, 50-149, 150-199, 300-"),
+            Ranges::Partial(vec![0..200, 300..1000])
+        );
+        // unsatisfiable ranges are dropped if others remain
+        assert_eq!(range("bytes=0-9, 2000-3000"), Ranges::Partial(vec![0..10]));
+        let many = (0..17)
+            .map(|i| format!("{}-{}", i * 10, i * 10))
+            .collect::<Vec<_>>();
+        assert_eq!(range(&format!("bytes={}", many.join(","))), Ranges::Full);
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert_eq!(range("bytes=1000-"), Ranges::Unsatisfiable);
+        assert_eq!(range("bytes=1000-2000, 5000-"), Ranges::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), Ranges::Unsatisfiable);
+        assert_eq!(range("bytes=invalid"), Ranges::Full);
+    }
+
+    #[test]
+    fn multipart() {
+        let multipart = Multipart::new("text/plain", &[0..10, 20..25], 100);
+        let boundary = multipart.boundary.clone();
+
+        assert_eq!(
+            multipart.content_type(),
+            format!("multipart/byteranges; boundary={}", boundary)
+        );
+        assert_eq!(
+            multipart.parts[1].1,
+            format!(
+                "\r\n--{}\r\nContent-Type: text/plain\r\nContent-Range: bytes 20-24/100\r\n\r\n",
+                boundary
+            )
+        );
+        assert_eq!(multipart.tail, format!("\r\n--{}--\r\n", boundary));
+        assert_eq!(
+            multipart.content_length() as usize,
+            multipart.parts[0].1.len() + 10 + multipart.parts[1].1.len() + 5 + multipart.tail.len()
+        );
+    }
+
+    #[test]
+    fn if_range() {
+        const DATE: &str = "Mon, 12 Jan 1970 13:46:40 GMT";
+
+        assert_eq!(
+            ranges(&[("range", "bytes=0-0"), ("if-range", "\"abc\"")]),
+            Ranges::Partial(vec![0..1])
+        );
+        assert_eq!(
+            ranges(&[("range", "bytes=0-0"), ("if-range", DATE)]),
+            Ranges::Partial(vec![0..1])
+        );
+        assert_eq!(
+            ranges(&[("range", "bytes=0-0"), ("if-range", "\"x\"")]),
+            Ranges::Full
+        );
+        assert_eq!(
+            ranges(&[("range", "bytes=0-0"), ("if-range", "W/\"abc\"")]),
+            Ranges::Full
+        );
+        assert_eq!(
+            ranges(&[
+                ("range", "bytes=0-0"),
+                ("if-range", "Mon, 12 Jan 1970 13:46:41 GMT")
+            ]),
+            Ranges::Full
+        );
+    }
+}
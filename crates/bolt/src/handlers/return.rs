@@ -0,0 +1,122 @@
+use crate::handlers::HandlerError;
+use crate::mime::{MimeTypes, DEFAULT_TYPE};
+use bolt_config::model::Return;
+use bolt_config::template::Variables;
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::{Body, Response, StatusCode};
+
+/// Non standard status which closes the connection instead of responding, as in nginx
+const CLOSE: u16 = 444;
+
+/// `return <status> [body]`
+///
+/// Redirects (`301`, `302`, `303`, `307` and `308`) use the argument as `Location`, all other
+/// status codes send it as plain text body.
+pub fn respond(
+    Return { status, body }: &Return,
+    variables: &dyn Variables,
+    types: &MimeTypes,
+) -> Result<Response<Body>, HandlerError> {
+    if *status == CLOSE {
+        return Err(HandlerError::Close);
+    }
+
+    let status = StatusCode::from_u16(*status).expect("status codes are validated while loading");
+    let text = body.as_ref().map(|text| text.render(variables));
+
+    let response = Response::builder().status(status);
+    let response = match text {
+        Some(location) if is_redirect(status) => {
+            response.header(LOCATION, location).body(Body::empty())
+        }
+        Some(text) => response
+            .header(
+                CONTENT_TYPE,
+                types.for_extension("txt").unwrap_or(DEFAULT_TYPE),
+            )
+            .body(Body::from(text)),
+        None => response.body(Body::empty()),
+    };
+
+    // rendered locations might not be valid header values
+    Ok(response.unwrap_or_else(|_| {
+        Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty())
+            .unwrap()
+    }))
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bolt_config::model::Text;
+    use bolt_config::template::{Template, Variable};
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    struct Uri(&'static str);
+
+    impl Variables for Uri {
+        fn get(&self, variable: &Variable) -> Option<Cow<'_, str>> {
+            match variable {
+                Variable::Uri => Some(self.0.into()),
+                _ => None,
+            }
+        }
+    }
+
+    fn respond_to(uri: &'static str, status: u16, body: Option<&str>) -> Response<Body> {
+        let r#return = Return {
+            status,
+            body: body.map(|body| Text::Format(Template::parse(body).unwrap())),
+        };
+        respond(&r#return, &Uri(uri), &MimeTypes::default()).unwrap()
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn redirects_and_text() {
+        let response = respond_to("/a", 301, Some("https://example.com$uri"));
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[LOCATION], "https://example.com/a");
+        assert_eq!(body(response).await, "");
+
+        let response = respond_to("/a", 403, Some("no access to $uri"));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(body(response).await, "no access to /a");
+
+        let response = respond_to("/a", 204, None);
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.headers().is_empty());
+        assert_eq!(body(response).await, "");
+
+        // not a valid header value
+        let response = respond_to("/a\nb", 302, Some("$uri"));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn closes_on_444() {
+        let r#return = Return {
+            status: 444,
+            body: None,
+        };
+        assert!(matches!(
+            respond(&r#return, &Uri("/"), &MimeTypes::default()),
+            Err(HandlerError::Close)
+        ));
+    }
+}
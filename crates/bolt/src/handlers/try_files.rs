@@ -0,0 +1,211 @@
+//! `try_files`, serves the first existing candidate or falls back to another uri, a named
+//! location or a status code
+
+use crate::handlers::files;
+use bolt_config::model::{Fallback, Root, TryFiles};
+use bolt_config::template::Variables;
+use bolt_url::UrlPath;
+use hyper::StatusCode;
+use std::future::Future;
+use tracing::debug;
+
+#[derive(Debug)]
+pub enum Tried<'t> {
+    /// Segments of the first candidate which exists, relative to the root
+    Found(Vec<String>),
+    /// The path has to be matched against the locations again
+    Redirect(UrlPath),
+    /// Continues with the named location
+    Named(&'t str),
+    Status(StatusCode),
+}
+
+/// Checks the candidates in order
+///
+/// `prefix` are the segments matched by the location, a candidate outside of it can't be served
+/// from an `alias`. Without any root only the fallback is left.
+pub fn resolve<'t>(
+    try_files: &'t TryFiles,
+    variables: &dyn Variables,
+    root: Option<&'t Root>,
+    prefix: &'t [String],
+) -> impl Future<Output = Tried<'t>> + Send + 't {
+    // rendered up front, the variables aren't `Send` and can't be held across the file system
+    // checks
+    let candidates = match root {
+        Some(_) => try_files
+            .candidates
+            .iter()
+            .map(|candidate| candidate.render(variables))
+            .collect(),
+        None => vec![],
+    };
+    let fallback = match &try_files.fallback {
+        Fallback::Uri(uri) => {
+            let uri = uri.render(variables);
+            match uri.parse::<UrlPath>().ok().and_then(UrlPath::resolve_dots) {
+                Some(path) => Tried::Redirect(path),
+                None => {
+                    debug!("`try_files` fallback `{}` is not a valid path", uri);
+                    Tried::Status(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        }
+        Fallback::Named(name) => Tried::Named(name),
+        Fallback::Status(code) => Tried::Status(
+            StatusCode::from_u16(*code).expect("status codes are validated while loading"),
+        ),
+    };
+
+    async move {
+        for candidate in candidates {
+            // `$uri/` asks for a directory
+            let dir = candidate.ends_with('/');
+            let parts = match segments(&candidate) {
+                Some(parts) => parts,
+                None => continue,
+            };
+
+            let path = match root {
+                Some(Root::Root(base)) => files::locate(base, &parts),
+                Some(Root::Alias(base)) => parts
+                    .strip_prefix(prefix)
+                    .and_then(|remainder| files::locate(base, remainder)),
+                None => None,
+            };
+            let found = match path {
+                Some(path) => tokio::fs::metadata(&path).await,
+                None => continue,
+            };
+            match found {
+                Ok(metadata) if metadata.is_dir() == dir => return Tried::Found(parts),
+                _ => {}
+            }
+        }
+
+        fallback
+    }
+}
+
+/// Decoded segments of a rendered candidate, `None` if it would leave the root
+fn segments(candidate: &str) -> Option<Vec<String>> {
+    let mut parts = vec![];
+    for part in candidate.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(bolt_url::normalize_str(part)),
+        }
+    }
+    Some(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bolt_config::ast::Span;
+    use bolt_config::model::Text;
+    use bolt_config::source::FileId;
+    use bolt_config::template::{Template, Variable};
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+    use std::path::PathBuf;
+
+    struct Uri(&'static str);
+
+    impl Variables for Uri {
+        fn get(&self, variable: &Variable) -> Option<Cow<'_, str>> {
+            match variable {
+                Variable::Uri => Some(self.0.into()),
+                _ => None,
+            }
+        }
+    }
+
+    fn www() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bolt_try_files_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("docs/guide")).unwrap();
+        std::fs::write(dir.join("index.html"), "spa").unwrap();
+        std::fs::write(dir.join("docs/a.txt"), "a").unwrap();
+        dir
+    }
+
+    fn try_files(candidates: &[&str], fallback: Fallback) -> TryFiles {
+        TryFiles {
+            candidates: candidates
+                .iter()
+                .map(|c| Text::Format(Template::parse(c).unwrap()))
+                .collect(),
+            fallback,
+            span: Span {
+                file: FileId(0),
+                offset: 0,
+                len: 0,
+                line: 1,
+                column: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn segments_stay_inside_the_root() {
+        assert_eq!(
+            segments("/a//b/./c/"),
+            Some(vec!["a".into(), "b".into(), "c".into()])
+        );
+        assert_eq!(segments("/a/../b"), Some(vec!["b".to_string()]));
+        assert_eq!(segments("/"), Some(vec![]));
+        assert_eq!(segments("/a/../../etc/passwd"), None);
+    }
+
+    #[tokio::test]
+    async fn candidates() {
+        let dir = www();
+        let root = Root::Root(dir.clone());
+        let spa = try_files(
+            &["$uri", "$uri/"],
+            Fallback::Uri(Text::Plain("/index.html".to_string())),
+        );
+        let tried = |uri| resolve(&spa, &Uri(uri), Some(&root), &[]);
+
+        assert!(matches!(
+            tried("/docs/a.txt").await,
+            Tried::Found(parts) if parts == ["docs", "a.txt"]
+        ));
+        assert!(matches!(
+            tried("/docs/guide").await,
+            Tried::Found(parts) if parts == ["docs", "guide"]
+        ));
+        assert!(matches!(
+            tried("/about").await,
+            Tried::Redirect(path) if path.parts() == ["index.html"]
+        ));
+        // files don't satisfy `$uri/`
+        let dirs = try_files(&["$uri/"], Fallback::Status(404));
+        assert!(matches!(
+            resolve(&dirs, &Uri("/docs/a.txt"), Some(&root), &[]).await,
+            Tried::Status(StatusCode::NOT_FOUND)
+        ));
+
+        let alias = Root::Alias(dir.join("docs"));
+        let named = try_files(&["$uri"], Fallback::Named("app".to_string()));
+        let prefix = ["docs".to_string()];
+        assert!(matches!(
+            resolve(&named, &Uri("/docs/docs/a.txt"), Some(&alias), &prefix).await,
+            Tried::Named("app")
+        ));
+        assert!(matches!(
+            resolve(&named, &Uri("/docs/a.txt"), Some(&alias), &prefix).await,
+            Tried::Found(parts) if parts == ["docs", "a.txt"]
+        ));
+
+        let status = try_files(&["$uri"], Fallback::Status(404));
+        assert!(matches!(
+            resolve(&status, &Uri("/index.html"), None, &[]).await,
+            Tried::Status(StatusCode::NOT_FOUND)
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+//! Upgraded connections through `proxy_pass`, negotiated with `Upgrade` over HTTP/1.1 or as
+//! extended CONNECT over HTTP/2 ([RFC 8441](https://www.rfc-editor.org/rfc/rfc8441))
+//!
+//! Either side may speak either version, WebSocket handshakes are translated between the two.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hyper::ext::Protocol;
+use hyper::header::{HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::upgrade::OnUpgrade;
+use hyper::{Body, Method, Request, Response, StatusCode, Version};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Appended to `Sec-WebSocket-Key` for `Sec-WebSocket-Accept`, RFC 6455 section 4.2.2
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// A request to switch the connection to another protocol
+pub struct Upgrade {
+    /// `Upgrade` or `:protocol`, e.g. `websocket`
+    protocol: HeaderValue,
+    /// Received as extended CONNECT rather than with `Upgrade`
+    extended_connect: bool,
+    /// `Sec-WebSocket-Key` of HTTP/1.1 clients, answered by the proxy for HTTP/2 upstreams
+    key: Option<HeaderValue>,
+    client: OnUpgrade,
+}
+
+impl Upgrade {
+    /// Takes the pending upgrade of the client connection out of `req`, if it asks for one
+    ///
+    /// Has to be called before the hop-by-hop headers are stripped.
+    pub fn take(req: &mut Request<Body>) -> Option<Self> {
+        let (protocol, extended_connect) = if extended_connect(req) {
+            let protocol = match req.extensions().get::<Protocol>() {
+                Some(protocol) => HeaderValue::from_str(protocol.as_str()).ok()?,
+                None => HeaderValue::from_static("websocket"),
+            };
+            (protocol, true)
+        } else if req.version() <= Version::HTTP_11 && connection_upgrade(req) {
+            (req.headers().get(UPGRADE)?.clone(), false)
+        } else {
+            return None;
+        };
+        let key = match extended_connect {
+            true => None,
+            false => req.headers().get(SEC_WEBSOCKET_KEY).cloned(),
+        };
+
+        Some(Self {
+            protocol,
+            extended_connect,
+            key,
+            client: hyper::upgrade::on(req),
+        })
+    }
+
+    fn websocket(&self) -> bool {
+        self.protocol.as_bytes().eq_ignore_ascii_case(b"websocket")
+    }
+
+    /// Asks the upstream for the upgrade, in the form of its version
+    ///
+    /// Called on the request once the hop-by-hop headers are stripped.
+    pub fn prepare(&self, req: &mut Request<Body>, h2: bool) {
+        let headers = req.headers_mut();
+        if h2 {
+            headers.remove(SEC_WEBSOCKET_KEY);
+            let protocol = Protocol::from(self.protocol.to_str().unwrap_or_default());
+            req.extensions_mut().insert(protocol);
+            *req.method_mut() = Method::CONNECT;
+            return;
+        }
+
+        headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+        headers.insert(UPGRADE, self.protocol.clone());
+        if self.websocket() && !headers.contains_key(SEC_WEBSOCKET_KEY) {
+            headers.insert(SEC_WEBSOCKET_KEY, websocket_key());
+        }
+        // RFC 8441 section 5, the handshake is a `GET` over HTTP/1.1
+        if self.extended_connect {
+            *req.method_mut() = Method::GET;
+        }
+    }
+
+    /// Whether an upstream speaking HTTP/2 if `h2` switched protocols
+    pub fn accepted(&self, response: &Response<Body>, h2: bool) -> bool {
+        match h2 {
+            true => response.status().is_success(),
+            false => response.status() == StatusCode::SWITCHING_PROTOCOLS,
+        }
+    }
+
+    /// Answers the client in the form of its version and splices both connections once they're
+    /// upgraded, until either side closes it or it's idle for longer than `idle`
+    pub fn tunnel(self, mut response: Response<Body>, idle: Duration) -> Response<Body> {
+        let upstream = hyper::upgrade::on(&mut response);
+        *response.status_mut() = match self.extended_connect {
+            true => StatusCode::OK,
+            false => StatusCode::SWITCHING_PROTOCOLS,
+        };
+        let headers = response.headers_mut();
+        let accept = headers.remove(SEC_WEBSOCKET_ACCEPT);
+        if !self.extended_connect {
+            headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+            headers.insert(UPGRADE, self.protocol.clone());
+            let accept = match (&self.key, self.websocket()) {
+                // the upstream only saw an extended CONNECT
+                (Some(key), true) if accept.is_none() => Some(websocket_accept(key)),
+                _ => accept,
+            };
+            if let Some(accept) = accept {
+                headers.insert(SEC_WEBSOCKET_ACCEPT, accept);
+            }
+        }
+
+        tokio::spawn(splice(self.client, upstream, idle));
+        *response.body_mut() = Body::empty();
+        response
+    }
+}
+
+/// CONNECT with a `:protocol`, which is the only kind with a `:path`
+///
+/// h2 validates `:protocol` without passing it on, WebSockets are assumed then as the only protocol
+/// registered for it.
+fn extended_connect(req: &Request<Body>) -> bool {
+    req.method() == Method::CONNECT
+        && (req.extensions().get::<Protocol>().is_some()
+            || (req.version() == Version::HTTP_2 && req.uri().path_and_query().is_some()))
+}
+
+/// `Connection` lists `upgrade`
+fn connection_upgrade(req: &Request<Body>) -> bool {
+    req.headers()
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|option| option.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// `Sec-WebSocket-Key` for clients which didn't send one, 16 random bytes
+fn websocket_key() -> HeaderValue {
+    let mut nonce = [0; 16];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .expect("the system has a source of randomness");
+    HeaderValue::from_str(&BASE64.encode(nonce)).expect("base64 is a valid header value")
+}
+
+/// `Sec-WebSocket-Accept` for `key`
+fn websocket_accept(key: &HeaderValue) -> HeaderValue {
+    let mut input = key.as_bytes().to_vec();
+    input.extend_from_slice(WEBSOCKET_GUID.as_bytes());
+    let hash = digest(&SHA1_FOR_LEGACY_USE_ONLY, &input);
+    HeaderValue::from_str(&BASE64.encode(hash)).expect("base64 is a valid header value")
+}
+
+async fn splice(client: OnUpgrade, upstream: OnUpgrade, idle: Duration) {
+    let (client, upstream) = match tokio::try_join!(client, upstream) {
+        Ok(upgraded) => upgraded,
+        Err(err) => {
+            debug!("Unable to upgrade the connection: {}", err);
+            return;
+        }
+    };
+
+    let activity = Arc::new(Mutex::new(Instant::now()));
+    let mut client = Tracked::new(client, activity.clone());
+    let mut upstream = Tracked::new(upstream, activity.clone());
+    let copy = tokio::io::copy_bidirectional(&mut client, &mut upstream);
+    tokio::pin!(copy);
+    loop {
+        let deadline = *activity.lock().unwrap() + idle;
+        tokio::select! {
+            copied = &mut copy => {
+                match copied {
+                    Ok((sent, received)) => debug!(
+                        "Tunnel closed after sending {} and receiving {} bytes",
+                        sent, received
+                    ),
+                    Err(err) => debug!("Tunnel failed: {}", err),
+                }
+                return;
+            }
+            _ = tokio::time::sleep_until(deadline) => {
+                // data may have been read in the meantime
+                if *activity.lock().unwrap() + idle <= Instant::now() {
+                    debug!("Closing the tunnel, it was idle for {:?}", idle);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Records when data was last read from either side of the tunnel
+struct Tracked<T> {
+    io: T,
+    activity: Arc<Mutex<Instant>>,
+}
+
+impl<T> Tracked<T> {
+    fn new(io: T, activity: Arc<Mutex<Instant>>) -> Self {
+        Self { io, activity }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tracked<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let polled = Pin::new(&mut self.io).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            *self.activity.lock().unwrap() = Instant::now();
+        }
+        polled
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tracked<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn request(builder: hyper::http::request::Builder) -> Request<Body> {
+        builder.uri("/chat").body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn websocket_handshake() {
+        // RFC 6455 section 1.3
+        let key = HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ==");
+        assert_eq!(websocket_accept(&key), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(BASE64.decode(websocket_key().as_bytes()).unwrap().len(), 16);
+    }
+
+    #[tokio::test]
+    async fn http1_upgrade() {
+        let mut req = request(
+            Request::get("/")
+                .header(CONNECTION, "keep-alive, Upgrade")
+                .header(UPGRADE, "websocket")
+                .header(SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ=="),
+        );
+        let upgrade = Upgrade::take(&mut req).unwrap();
+        assert_eq!(upgrade.protocol, "websocket");
+        assert!(!upgrade.extended_connect);
+
+        // as extended CONNECT to HTTP/2 upstreams
+        req.headers_mut().remove(CONNECTION);
+        req.headers_mut().remove(UPGRADE);
+        upgrade.prepare(&mut req, true);
+        assert_eq!(req.method(), Method::CONNECT);
+        assert_eq!(
+            req.extensions().get::<Protocol>().unwrap().as_str(),
+            "websocket"
+        );
+        assert!(req.headers().is_empty());
+
+        let upstream = Response::new(Body::empty());
+        assert!(upgrade.accepted(&upstream, true));
+        let response = upgrade.tunnel(upstream, Duration::from_secs(1));
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(response.headers()[UPGRADE], "websocket");
+        assert_eq!(
+            response.headers()[SEC_WEBSOCKET_ACCEPT],
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let mut req = request(Request::get("/").header(UPGRADE, "websocket"));
+        assert!(Upgrade::take(&mut req).is_none());
+    }
+
+    #[tokio::test]
+    async fn extended_connect() {
+        let mut req = Request::connect("example.com:443")
+            .version(Version::HTTP_2)
+            .body(Body::empty())
+            .unwrap();
+        assert!(Upgrade::take(&mut req).is_none());
+        // without the `:protocol` from h2
+        let mut req = request(Request::connect("/").version(Version::HTTP_2));
+        let upgrade = Upgrade::take(&mut req).unwrap();
+        assert!(upgrade.extended_connect);
+        assert_eq!(upgrade.protocol, "websocket");
+
+        // as `Upgrade` to HTTP/1.1 upstreams
+        upgrade.prepare(&mut req, false);
+        assert_eq!(req.method(), Method::GET);
+        assert_eq!(req.headers()[CONNECTION], "upgrade");
+        assert_eq!(req.headers()[UPGRADE], "websocket");
+        let key = req.headers()[SEC_WEBSOCKET_KEY].clone();
+
+        let mut upstream = Response::new(Body::empty());
+        assert!(!upgrade.accepted(&upstream, false));
+        *upstream.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        upstream
+            .headers_mut()
+            .insert(SEC_WEBSOCKET_ACCEPT, websocket_accept(&key));
+        assert!(upgrade.accepted(&upstream, false));
+        let response = upgrade.tunnel(upstream, Duration::from_secs(1));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().is_empty());
+    }
+}
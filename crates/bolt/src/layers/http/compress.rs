@@ -0,0 +1,307 @@
+//! On the fly compression of response bodies, for any handler behind [`WebService`]
+//!
+//! [`WebService`]: super::WebService
+
+use crate::handlers::encoding;
+use crate::util::PinResultFuture;
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use async_compression::Level;
+use bolt_config::model::{Compression, Encoding};
+use futures_util::TryStreamExt;
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, ETAG, VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_util::io::{ReaderStream, StreamReader};
+use tower::{Layer, Service};
+
+#[derive(Clone)]
+pub struct CompressionLayer {
+    config: Arc<Compression>,
+}
+
+pub struct Compress<S> {
+    inner: S,
+    config: Arc<Compression>,
+}
+
+impl CompressionLayer {
+    pub fn new(config: Arc<Compression>) -> Self {
+        Self { config }
+    }
+}
+
+impl<S> Layer<S> for CompressionLayer {
+    type Service = Compress<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Compress {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for Compress<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+    S::Error: 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = PinResultFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let config = self.config.clone();
+        // responses to `HEAD` have no body, they still get `Vary` like the `GET` response would.
+        // Those to `CONNECT` open a tunnel.
+        let accepted = match req.method() {
+            &Method::HEAD | &Method::CONNECT => vec![],
+            _ => encoding::acceptable(req.headers(), &config.encodings),
+        };
+        let response = self.inner.call(req);
+
+        Box::pin(async move { Ok(compress(response.await?, &accepted, &config)) })
+    }
+}
+
+/// Streams the body through the most preferred of the `accepted` encodings, if the response
+/// qualifies for compression at all
+fn compress(
+    response: Response<Body>,
+    accepted: &[Encoding],
+    config: &Compression,
+) -> Response<Body> {
+    if config.encodings.is_empty() || !compressible(&response, config) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    add_vary(&mut parts.headers);
+
+    let encoding = match accepted.first() {
+        Some(encoding) => *encoding,
+        None => return Response::from_parts(parts, body),
+    };
+
+    // the length isn't known until the body has been compressed
+    parts.headers.remove(CONTENT_LENGTH);
+    // ranges would have to address the compressed body, which isn't stable
+    parts.headers.remove(ACCEPT_RANGES);
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.token()));
+    weaken_etag(&mut parts.headers);
+
+    let reader = StreamReader::new(body.map_err(std::io::Error::other));
+    let level = config.level.map_or(Level::Default, Level::Precise);
+    let body = match encoding {
+        Encoding::Brotli => Body::wrap_stream(ReaderStream::new(BrotliEncoder::with_quality(
+            reader, level,
+        ))),
+        Encoding::Gzip => {
+            Body::wrap_stream(ReaderStream::new(GzipEncoder::with_quality(reader, level)))
+        }
+        Encoding::Zstd => {
+            Body::wrap_stream(ReaderStream::new(ZstdEncoder::with_quality(reader, level)))
+        }
+    };
+
+    Response::from_parts(parts, body)
+}
+
+/// Whether the response would be compressed for a client accepting it
+fn compressible(response: &Response<Body>, config: &Compression) -> bool {
+    let status = response.status();
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::PARTIAL_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return false;
+    }
+
+    let headers = response.headers();
+    if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+        return false;
+    }
+
+    let no_transform = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+    if no_transform {
+        return false;
+    }
+
+    let mime = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase()
+        });
+    if !mime.is_some_and(|mime| config.types.contains(&mime)) {
+        return false;
+    }
+
+    // streamed bodies of unknown length are always compressed
+    let len = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    len.is_none_or(|len| len >= config.min_size)
+}
+
+fn add_vary(headers: &mut HeaderMap) {
+    let present = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|name| name == "*" || name.eq_ignore_ascii_case("accept-encoding"));
+    if !present {
+        headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+/// The compressed body isn't byte for byte identical to the original one anymore
+fn weaken_etag(headers: &mut HeaderMap) {
+    if let Some(etag) = headers.get(ETAG) {
+        if !etag.as_bytes().starts_with(b"W/") {
+            let mut weak = b"W/".to_vec();
+            weak.extend_from_slice(etag.as_bytes());
+            if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                headers.insert(ETAG, weak);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::GzipDecoder;
+    use hyper::header::{HeaderName, ACCEPT_ENCODING};
+    use pretty_assertions::{assert_eq, assert_ne};
+    use std::convert::Infallible;
+    use tokio::io::AsyncReadExt;
+    use tower::ServiceExt;
+
+    const HTML: &str = "<!DOCTYPE html><html><body>Hello, World!</body></html>";
+
+    fn config() -> Arc<Compression> {
+        Arc::new(Compression {
+            encodings: vec![Encoding::Brotli, Encoding::Gzip],
+            min_size: 16,
+            ..Compression::default()
+        })
+    }
+
+    /// Sends a request with `accept` as `Accept-Encoding` to a handler responding with `response`
+    async fn respond(
+        accept: Option<&str>,
+        response: impl Fn() -> Response<Body> + Send + 'static,
+    ) -> Response<Body> {
+        let handler = tower::service_fn(move |_: Request<Body>| {
+            let response = response();
+            async move { Ok::<_, Infallible>(response) }
+        });
+        let mut req = Request::builder();
+        if let Some(accept) = accept {
+            req = req.header(ACCEPT_ENCODING, accept);
+        }
+
+        CompressionLayer::new(config())
+            .layer(handler)
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn html() -> Response<Body> {
+        Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CONTENT_LENGTH, HTML.len())
+            .header(ETAG, "\"abc\"")
+            .header(ACCEPT_RANGES, "bytes")
+            .body(Body::from(HTML))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn compresses() {
+        let response = respond(Some("gzip, br;q=0.5"), html).await;
+        let headers = response.headers().clone();
+        assert_eq!(headers.get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(headers.get(VARY).unwrap(), "Accept-Encoding");
+        assert_eq!(headers.get(ETAG).unwrap(), "W/\"abc\"");
+        assert!(!headers.contains_key(CONTENT_LENGTH));
+        assert!(!headers.contains_key(ACCEPT_RANGES));
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut decoded = String::new();
+        GzipDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, HTML);
+    }
+
+    #[tokio::test]
+    async fn varies_without_accept_encoding() {
+        let response = respond(None, html).await;
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(response.headers().get(VARY).unwrap(), "Accept-Encoding");
+        assert_eq!(response.headers().get(ETAG).unwrap(), "\"abc\"");
+    }
+
+    #[tokio::test]
+    async fn skips() {
+        let with = |name: HeaderName, value: &'static str| {
+            move || {
+                let mut response = html();
+                response
+                    .headers_mut()
+                    .insert(name.clone(), HeaderValue::from_static(value));
+                response
+            }
+        };
+
+        let skipped = [
+            respond(Some("br"), with(CONTENT_TYPE, "image/png")).await,
+            respond(Some("br"), with(CONTENT_LENGTH, "15")).await,
+            respond(Some("br"), with(CACHE_CONTROL, "public, no-transform")).await,
+            respond(Some("br"), with(CONTENT_ENCODING, "gzip")).await,
+            respond(Some("br"), with(CONTENT_RANGE, "bytes 0-9/100")).await,
+            respond(Some("br"), || {
+                let mut response = html();
+                *response.status_mut() = StatusCode::NOT_MODIFIED;
+                response
+            })
+            .await,
+        ];
+        for response in skipped {
+            assert_ne!(
+                response.headers().get(CONTENT_ENCODING),
+                Some(&HeaderValue::from_static("br"))
+            );
+            assert!(!response.headers().contains_key(VARY));
+        }
+    }
+}
@@ -1,14 +1,57 @@
-use crate::layers::raw::RawRequest;
+use crate::handlers::fastcgi;
+use crate::handlers::proxy::{self, Route};
+use crate::handlers::try_files::{self, Tried};
+use crate::handlers::{self, files, status, HandlerError};
+use crate::layers::raw::{Addr, RawRequest};
+use crate::sites::{CompiledSite, VHosts};
 use crate::util::PinResultFuture;
-use hyper::{Body, Request, Response};
-use std::convert::Infallible;
+use bolt_config::model::{Compression, Location, Root};
+use bolt_url::UrlPath;
+use compress::CompressionLayer;
+use hyper::{header, Body, Request, Response, StatusCode};
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
-use tower::Service;
+use tower::{Layer, Service};
+use tracing::warn;
+use vars::RequestVariables;
 
-pub struct RawWebService {}
-pub struct WebService {}
+mod compress;
+pub mod vars;
+
+/// Bounds the internal redirects of `try_files`, which could otherwise loop forever
+const MAX_INTERNAL_REDIRECTS: usize = 10;
+
+pub struct RawWebService {
+    vhosts: Arc<VHosts>,
+    h2: bool,
+    compression: CompressionLayer,
+}
+
+pub struct WebService {
+    vhosts: Arc<VHosts>,
+    conn: Arc<Connection>,
+}
+
+/// Information about the connection a request was received on
+pub struct Connection {
+    pub secure: bool,
+    pub sni_hostname: Option<String>,
+    pub alpn_protocol: Option<Vec<u8>>,
+    pub peer: Addr,
+    pub local: Addr,
+}
+
+impl RawWebService {
+    pub fn new(vhosts: Arc<VHosts>, h2: bool, compression: Arc<Compression>) -> Self {
+        Self {
+            vhosts,
+            h2,
+            compression: CompressionLayer::new(compression),
+        }
+    }
+}
 
 impl Service<RawRequest> for RawWebService {
     type Response = ();
@@ -30,17 +73,33 @@ impl Service<RawRequest> for RawWebService {
             local,
         }: RawRequest,
     ) -> Self::Future {
-        Box::pin(async move {
-            hyper::server::conn::Http::new()
-                .serve_connection(stream, WebService {})
-                .await
-        })
+        let mut http = hyper::server::conn::Http::new();
+        if !self.h2 {
+            http.http1_only(true);
+        } else if alpn_protocol.as_deref() == Some(b"h2") {
+            http.http2_only(true);
+        }
+        // WebSockets over HTTP/2, RFC 8441
+        http.http2_enable_connect_protocol();
+
+        let service = self.compression.layer(WebService {
+            vhosts: self.vhosts.clone(),
+            conn: Arc::new(Connection {
+                secure,
+                sni_hostname,
+                alpn_protocol,
+                peer,
+                local,
+            }),
+        });
+
+        Box::pin(async move { http.serve_connection(stream, service).with_upgrades().await })
     }
 }
 
 impl Service<Request<Body>> for WebService {
     type Response = Response<Body>;
-    type Error = Infallible;
+    type Error = HandlerError;
     type Future =
         Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
 
@@ -48,7 +107,189 @@ impl Service<Request<Body>> for WebService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, _req: Request<Body>) -> Self::Future {
-        Box::pin(async move { Ok(Response::builder().status(200).body(Body::empty()).unwrap()) })
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let vhosts = self.vhosts.clone();
+        let conn = self.conn.clone();
+
+        Box::pin(async move { handle(&vhosts, &conn, req).await })
+    }
+}
+
+async fn handle(
+    vhosts: &VHosts,
+    conn: &Connection,
+    req: Request<Body>,
+) -> Result<Response<Body>, HandlerError> {
+    let host = request_host(&req, conn);
+    // requests without any host can only be served by the default site
+    let (site, site_match) = match vhosts.route(host.unwrap_or_default()) {
+        Some(found) => found,
+        None => return Ok(status(StatusCode::MISDIRECTED_REQUEST)),
+    };
+
+    let mut path = match req
+        .uri()
+        .path()
+        .parse::<UrlPath>()
+        .ok()
+        .and_then(UrlPath::resolve_dots)
+    {
+        Some(path) => path,
+        None => return Ok(status(StatusCode::BAD_REQUEST)),
+    };
+    // set by `try_files` falling back to a named location, which skips routing
+    let mut named = None;
+    let mut redirected = false;
+
+    for _ in 0..=MAX_INTERNAL_REDIRECTS {
+        let (location, location_match) = match named.take() {
+            Some(location) => (Some(location), None),
+            None => match site.locations.route(&path) {
+                Some(m) => (Some(&site.site.locations[m.slot.0 as usize]), Some(m)),
+                None => (None, None),
+            },
+        };
+        // without a matching location the whole site is served from its root
+        let remainder = location_match
+            .as_ref()
+            .map_or(path.parts(), |location_match| location_match.remainder);
+        let variables = RequestVariables {
+            req: &req,
+            conn,
+            host,
+            path: &path,
+            remainder,
+            site_captures: site_match.captures(),
+            location_captures: location_match
+                .as_ref()
+                .and_then(|location_match| location_match.captures()),
+        };
+
+        if let Some(r#return) = location.and_then(|location| location.r#return.as_ref()) {
+            return handlers::r#return::respond(r#return, &variables, &site.types);
+        }
+
+        let site_root = site.site.root.clone().map(Root::Root);
+        let root = location
+            .and_then(|location| location.root.as_ref())
+            .or(site_root.as_ref());
+
+        let prefix = &path.parts()[..path.parts().len() - remainder.len()];
+        let found = match location.and_then(|location| location.try_files.as_ref()) {
+            Some(try_files) => {
+                match try_files::resolve(try_files, &variables, root, prefix).await {
+                    Tried::Found(parts) => Some(parts),
+                    Tried::Redirect(uri) => {
+                        path = uri;
+                        redirected = true;
+                        continue;
+                    }
+                    Tried::Named(name) => {
+                        named = Some(
+                            site.named(name)
+                                .expect("named locations are validated while loading"),
+                        );
+                        continue;
+                    }
+                    Tried::Status(code) => return Ok(status(code)),
+                }
+            }
+            None => None,
+        };
+
+        if let (Some(location), None) = (location, &found) {
+            if let Some(proxy_pass) = &location.proxy_pass {
+                let route = route(site, location, &proxy_pass.authority, &variables);
+                let target = proxy::target(proxy_pass, req.uri(), &path, remainder, redirected);
+                return Ok(
+                    proxy::forward(&site.clients, proxy_pass, route, req, conn, &target).await,
+                );
+            }
+            if let Some(fastcgi_pass) = &location.fastcgi_pass {
+                let route = route(site, location, &fastcgi_pass.authority, &variables);
+                let params = fastcgi::params(&variables, root, &location.fastcgi_params);
+                return Ok(fastcgi::forward(&site.fastcgi, route, req, params).await);
+            }
+        }
+
+        let (parts, remainder) = match &found {
+            Some(parts) => (
+                parts.as_slice(),
+                parts.strip_prefix(prefix).unwrap_or_default(),
+            ),
+            None => (path.parts(), remainder),
+        };
+
+        let root = match root {
+            Some(root) => root,
+            None => return Ok(status(StatusCode::NOT_FOUND)),
+        };
+        let options = files::Options {
+            types: &site.types,
+            index: location
+                .and_then(|location| location.index.as_ref())
+                .or(site.site.index.as_ref())
+                .map(Vec::as_slice),
+            precompressed: location
+                .and_then(|location| location.precompressed.as_ref())
+                .or(site.site.precompressed.as_ref())
+                .map_or(&[], Vec::as_slice),
+            autoindex: location
+                .and_then(|location| location.autoindex)
+                .or(site.site.autoindex)
+                .unwrap_or_default(),
+        };
+
+        return Ok(files::serve(&req, root, parts, remainder, options).await);
+    }
+
+    warn!(
+        "Gave up on {} after {} internal redirects",
+        req.uri(),
+        MAX_INTERNAL_REDIRECTS
+    );
+    Ok(status(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+/// The upstream `authority` of a location was resolved to while compiling the site
+fn route(
+    site: &CompiledSite,
+    location: &Location,
+    authority: &str,
+    variables: &RequestVariables,
+) -> Route {
+    let upstream = site
+        .upstreams
+        .get(authority)
+        .expect("upstreams are resolved while compiling")
+        .clone();
+    Route {
+        key: upstream.key().map(|key| key.render(variables)),
+        upstream,
+        timeouts: location.proxy_timeouts.or(site.site.proxy_timeouts),
+    }
+}
+
+/// The host a request is addressed to, without port
+///
+/// HTTP/2 carries it in the `:authority` pseudo header (exposed through the uri),
+/// HTTP/1.1 in the `Host` header and HTTP/1.0 clients might only have sent it via SNI.
+fn request_host<'r>(req: &'r Request<Body>, conn: &'r Connection) -> Option<&'r str> {
+    req.uri()
+        .host()
+        .or_else(|| {
+            req.headers()
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .map(strip_port)
+        })
+        .or(conn.sni_hostname.as_deref())
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        // ipv6 literals contain colons but are enclosed in brackets
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
     }
 }
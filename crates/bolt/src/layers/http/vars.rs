@@ -0,0 +1,79 @@
+use crate::layers::http::Connection;
+use bolt_config::template::{Variable, Variables};
+use bolt_router::RegexMatch;
+use bolt_url::UrlPath;
+use hyper::header::COOKIE;
+use hyper::{Body, Request};
+use std::borrow::Cow;
+
+/// Everything templates can refer to while handling a request
+pub struct RequestVariables<'r> {
+    pub req: &'r Request<Body>,
+    pub conn: &'r Connection,
+    pub host: Option<&'r str>,
+    pub path: &'r UrlPath,
+    /// Segments following the prefix of the matched location
+    pub remainder: &'r [String],
+    pub site_captures: Option<&'r RegexMatch>,
+    pub location_captures: Option<&'r RegexMatch>,
+}
+
+impl Variables for RequestVariables<'_> {
+    fn get(&self, variable: &Variable) -> Option<Cow<'_, str>> {
+        let uri = self.req.uri();
+
+        match variable {
+            Variable::Scheme => Some(if self.conn.secure { "https" } else { "http" }.into()),
+            Variable::Host => self.host.map(Cow::from),
+            Variable::RequestUri => uri.path_and_query().map(|pq| pq.as_str().into()),
+            Variable::Uri => Some(join(self.path.parts()).into()),
+            Variable::RelativeUri => Some(self.remainder.join("/").into()),
+            Variable::Args => uri.query().map(Cow::from),
+            Variable::Arg(name) => uri
+                .query()?
+                .split('&')
+                .find_map(|pair| match pair.split_once('=') {
+                    Some((key, value)) if key == name => Some(value),
+                    None if pair == name => Some(""),
+                    _ => None,
+                })
+                .map(Cow::from),
+            Variable::Cookie(name) => self
+                .req
+                .headers()
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .find_map(|pair| match pair.trim().split_once('=') {
+                    Some((key, value)) if key == name => Some(value),
+                    _ => None,
+                })
+                .map(Cow::from),
+            Variable::RemoteAddr => Some(self.conn.peer.host().into()),
+            Variable::ServerAddr => Some(self.conn.local.host().into()),
+            Variable::Sni => self.conn.sni_hostname.as_deref().map(Cow::from),
+            Variable::Alpn => self
+                .conn
+                .alpn_protocol
+                .as_deref()
+                .map(String::from_utf8_lossy),
+            Variable::Capture(index) => self
+                .location_captures
+                .or(self.site_captures)?
+                .get(*index)
+                .map(Cow::from),
+            Variable::Named(name) => self
+                .location_captures
+                .and_then(|captures| captures.name(name))
+                .or_else(|| self.site_captures?.name(name))
+                .map(Cow::from),
+        }
+    }
+}
+
+fn join(parts: &[String]) -> String {
+    let mut path = String::from("/");
+    path.push_str(&parts.join("/"));
+    path
+}
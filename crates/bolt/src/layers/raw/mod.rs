@@ -1,16 +1,18 @@
-use crate::layers::raw::stream::EitherStream;
+use crate::layers::raw::stream::{EitherStream, Socket};
 use crate::util::PinResultFuture;
-use std::net::SocketAddr;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use tokio::net::TcpStream;
 use tokio_rustls::TlsAcceptor;
 use tower::Service;
 
 pub mod stream;
 
 pub struct UpgradeService {
-    tls_acceptor: Arc<TlsAcceptor>,
+    /// Only set for `tls` listeners, which then refuse plain text connections
+    tls_acceptor: Option<Arc<TlsAcceptor>>,
 }
 
 pub struct RawRequest {
@@ -19,19 +21,61 @@ pub struct RawRequest {
     pub sni_hostname: Option<String>,
     pub alpn_protocol: Option<Vec<u8>>,
 
-    pub peer: SocketAddr,
-    pub local: SocketAddr,
+    pub peer: Addr,
+    pub local: Addr,
+}
+
+/// Address of either end of a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Addr {
+    Ip(SocketAddr),
+    /// Path of a Unix domain socket, clients usually connect from unnamed ones
+    Unix(Option<PathBuf>),
+}
+
+impl Addr {
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Addr::Ip(addr) => Some(addr.ip()),
+            Addr::Unix(_) => None,
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Addr::Ip(addr) => Some(addr.port()),
+            Addr::Unix(_) => None,
+        }
+    }
+
+    /// The ip without the port, or `unix:` followed by the socket path
+    pub fn host(&self) -> String {
+        match self {
+            Addr::Ip(addr) => addr.ip().to_string(),
+            Addr::Unix(_) => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Addr::Ip(addr) => addr.fmt(f),
+            Addr::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            Addr::Unix(None) => f.write_str("unix:"),
+        }
+    }
 }
 
 impl UpgradeService {
-    pub fn new(acceptor: Arc<TlsAcceptor>) -> Self {
+    pub fn new(acceptor: Option<Arc<TlsAcceptor>>) -> Self {
         Self {
             tls_acceptor: acceptor,
         }
     }
 }
 
-impl Service<TcpStream> for UpgradeService {
+impl Service<Socket> for UpgradeService {
     type Response = RawRequest;
     type Error = std::io::Error;
     type Future = PinResultFuture<Self::Response, Self::Error>;
@@ -40,16 +84,31 @@ impl Service<TcpStream> for UpgradeService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, mut stream: TcpStream) -> Self::Future {
+    fn call(&mut self, mut stream: Socket) -> Self::Future {
         let acceptor = self.tls_acceptor.clone();
 
         Box::pin(async move {
-            let tls = check_for_tls(&mut stream).await?;
+            let (peer, local) = match &stream {
+                Socket::Tcp(stream) => (
+                    Addr::Ip(stream.peer_addr()?),
+                    Addr::Ip(stream.local_addr()?),
+                ),
+                Socket::Unix(stream) => {
+                    let path = |addr: tokio::net::unix::SocketAddr| {
+                        Addr::Unix(addr.as_pathname().map(PathBuf::from))
+                    };
+                    (path(stream.peer_addr()?), path(stream.local_addr()?))
+                }
+            };
 
-            let peer = stream.peer_addr()?;
-            let local = stream.local_addr()?;
+            let (stream, sni, alpn) = if let Some(acceptor) = acceptor {
+                if !stream.starts_tls().await? {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Expected a tls handshake",
+                    ));
+                }
 
-            let (stream, sni, alpn) = if tls {
                 let tls_stream = acceptor.accept(stream).await?;
 
                 let info = tls_stream.get_ref().1;
@@ -58,12 +117,12 @@ impl Service<TcpStream> for UpgradeService {
 
                 (EitherStream::Tls(tls_stream), sni, alpn)
             } else {
-                (EitherStream::Tcp(stream), None, None)
+                (EitherStream::Plain(stream), None, None)
             };
 
             Ok(RawRequest {
+                secure: matches!(stream, EitherStream::Tls(_)),
                 stream,
-                secure: tls,
                 sni_hostname: sni,
                 alpn_protocol: alpn,
                 peer,
@@ -72,10 +131,3 @@ impl Service<TcpStream> for UpgradeService {
         })
     }
 }
-
-async fn check_for_tls(stream: &mut TcpStream) -> std::io::Result<bool> {
-    let mut buf = [0; 1];
-    stream.peek(&mut buf[..]).await?;
-
-    Ok(buf[0] == 0x16)
-}
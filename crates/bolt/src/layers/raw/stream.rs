@@ -1,13 +1,45 @@
+use hyper::client::connect::{Connected, Connection};
 use std::io::{Error, IoSlice};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-use tokio::net::TcpStream;
+use tokio::net::{TcpStream, UnixStream};
 use tokio_rustls::server::TlsStream;
 
 pub enum EitherStream {
-    Tls(TlsStream<TcpStream>),
+    Tls(TlsStream<Socket>),
+    Plain(Socket),
+}
+
+/// A connection over TCP or a Unix domain socket, accepted by a listener or made to an upstream
+pub enum Socket {
     Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    /// Whether the client starts with a tls handshake, without consuming anything
+    ///
+    /// Unix domain sockets can't be peeked, their handshake fails instead.
+    pub async fn starts_tls(&mut self) -> std::io::Result<bool> {
+        match self {
+            Socket::Tcp(stream) => {
+                let mut buf = [0; 1];
+                stream.peek(&mut buf[..]).await?;
+                Ok(buf[0] == 0x16)
+            }
+            Socket::Unix(_) => Ok(true),
+        }
+    }
+}
+
+impl Connection for Socket {
+    fn connected(&self) -> Connected {
+        match self {
+            Socket::Tcp(stream) => stream.connected(),
+            Socket::Unix(_) => Connected::new(),
+        }
+    }
 }
 
 impl AsyncRead for EitherStream {
@@ -19,7 +51,7 @@ impl AsyncRead for EitherStream {
     ) -> Poll<std::io::Result<()>> {
         match self.get_mut() {
             EitherStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
-            EitherStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            EitherStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
         }
     }
 }
@@ -33,7 +65,7 @@ impl AsyncWrite for EitherStream {
     ) -> Poll<Result<usize, Error>> {
         match self.get_mut() {
             EitherStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
-            EitherStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            EitherStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
         }
     }
 
@@ -41,7 +73,7 @@ impl AsyncWrite for EitherStream {
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
         match self.get_mut() {
             EitherStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
-            EitherStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            EitherStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
         }
     }
 
@@ -49,7 +81,7 @@ impl AsyncWrite for EitherStream {
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
         match self.get_mut() {
             EitherStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
-            EitherStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            EitherStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
         }
     }
 
@@ -61,7 +93,7 @@ impl AsyncWrite for EitherStream {
     ) -> Poll<Result<usize, Error>> {
         match self.get_mut() {
             EitherStream::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
-            EitherStream::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            EitherStream::Plain(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
         }
     }
 
@@ -69,7 +101,71 @@ impl AsyncWrite for EitherStream {
     fn is_write_vectored(&self) -> bool {
         match self {
             EitherStream::Tls(stream) => Pin::new(stream).is_write_vectored(),
-            EitherStream::Tcp(stream) => Pin::new(stream).is_write_vectored(),
+            EitherStream::Plain(stream) => Pin::new(stream).is_write_vectored(),
+        }
+    }
+}
+
+impl AsyncRead for Socket {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Socket::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Socket::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Socket::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Socket::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+
+    #[inline]
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, Error>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Socket::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Socket::Tcp(stream) => stream.is_write_vectored(),
+            Socket::Unix(stream) => stream.is_write_vectored(),
         }
     }
 }
@@ -0,0 +1,224 @@
+//! Circuit breaking per server, refuses requests to servers failing too many of them
+
+use super::pick::Picked;
+use super::ProxyError;
+use crate::upstream::{Admission, Pick, Transition};
+use crate::util::PinResultFuture;
+use hyper::{Body, Request, Response};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::{info, warn};
+
+#[derive(Copy, Clone)]
+pub struct BreakerLayer;
+
+#[derive(Clone)]
+pub struct CircuitBreaker<S> {
+    inner: S,
+}
+
+impl<S> Layer<S> for BreakerLayer {
+    type Service = CircuitBreaker<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreaker { inner }
+    }
+}
+
+impl<S> Service<Request<Body>> for CircuitBreaker<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = ProxyError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = ProxyError;
+    type Future = PinResultFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let Picked(pick) = req
+            .extensions()
+            .get::<Picked>()
+            .cloned()
+            .expect("requests are picked before reaching the breaker");
+        let admission = match pick.server().circuit.acquire() {
+            Ok((admission, transition)) => {
+                log(&pick, transition);
+                admission
+            }
+            Err(()) => return Box::pin(async { Err(ProxyError::CircuitOpen) }),
+        };
+        // also released if the future is dropped before it's polled
+        let admitted = Admitted {
+            pick: Some(pick),
+            admission,
+        };
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = response.await;
+            let failed = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(_) => true,
+            };
+            admitted.record(failed);
+            result
+        })
+    }
+}
+
+/// Releases the circuit if the request is dropped before its outcome is known
+struct Admitted {
+    pick: Option<Arc<Pick>>,
+    admission: Admission,
+}
+
+impl Admitted {
+    fn record(mut self, failed: bool) {
+        if let Some(pick) = self.pick.take() {
+            let transition = pick.server().circuit.record(self.admission, failed);
+            log(&pick, transition);
+        }
+    }
+}
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        if let Some(pick) = self.pick.take() {
+            pick.server().circuit.release(self.admission);
+        }
+    }
+}
+
+fn log(pick: &Pick, transition: Option<Transition>) {
+    let (addr, upstream) = (&pick.server().addr, &pick.upstream().name);
+    match transition {
+        Some(Transition::Opened) => {
+            warn!("Opening the circuit of {} of upstream {}", addr, upstream)
+        }
+        Some(Transition::HalfOpened) => info!(
+            "Half opening the circuit of {} of upstream {} for a trial request",
+            addr, upstream
+        ),
+        Some(Transition::Closed) => {
+            info!("Closing the circuit of {} of upstream {}", addr, upstream)
+        }
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::{Admission, Upstream};
+    use bolt_config::ast::Span;
+    use bolt_config::model::{self, Balance, Breaker, Eject, Retry, UpstreamServer};
+    use bolt_config::source::FileId;
+    use hyper::StatusCode;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tower::service_fn;
+
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    fn upstream() -> Arc<Upstream> {
+        Arc::new(Upstream::new(&model::Upstream {
+            name: "app".to_string(),
+            servers: vec![UpstreamServer {
+                addr: "a:80".to_string(),
+                weight: 1,
+            }],
+            balance: Balance::RoundRobin,
+            health_check: None,
+            eject: Eject::default(),
+            retry: Retry::default(),
+            breaker: Some(Breaker {
+                ratio: 50,
+                min_requests: 2,
+                window: Duration::from_secs(60),
+                cooldown: COOLDOWN,
+            }),
+            span: Span::start(FileId(0)),
+        }))
+    }
+
+    fn request(upstream: &Arc<Upstream>, path: &str) -> Request<Body> {
+        let mut req = Request::get(path).body(Body::empty()).unwrap();
+        req.extensions_mut()
+            .insert(Picked(Arc::new(upstream.pick(None))));
+        req
+    }
+
+    /// Responds with the status in the path, or never for `/stall`
+    fn breaker(
+        calls: Arc<AtomicUsize>,
+    ) -> impl Service<Request<Body>, Response = Response<Body>, Error = ProxyError> {
+        BreakerLayer.layer(service_fn(move |req: Request<Body>| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            async move {
+                let status = match req.uri().path() {
+                    "/stall" => futures_util::future::pending().await,
+                    path => path[1..].parse::<StatusCode>().unwrap(),
+                };
+                Ok(Response::builder()
+                    .status(status)
+                    .body(Body::empty())
+                    .unwrap())
+            }
+        }))
+    }
+
+    #[tokio::test]
+    async fn refuses_open_circuits() {
+        let upstream = upstream();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut breaker = breaker(calls.clone());
+        let mut status = |path| {
+            let req = request(&upstream, path);
+            let response = breaker.call(req);
+            async move { response.await.map(|response| response.status()) }
+        };
+
+        assert_eq!(status("/500").await.unwrap(), 500);
+        assert_eq!(status("/500").await.unwrap(), 500);
+        assert!(matches!(status("/200").await, Err(ProxyError::CircuitOpen)));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // a successful trial closes it again
+        tokio::time::sleep(COOLDOWN).await;
+        assert_eq!(status("/200").await.unwrap(), 200);
+        assert_eq!(status("/200").await.unwrap(), 200);
+    }
+
+    #[tokio::test]
+    async fn releases_dropped_trials() {
+        let upstream = upstream();
+        let pick = upstream.pick(None);
+        let circuit = &pick.server().circuit;
+        for _ in 0..2 {
+            circuit.record(Admission::Regular, true);
+        }
+        tokio::time::sleep(COOLDOWN).await;
+        let mut breaker = breaker(Arc::new(AtomicUsize::new(0)));
+
+        let trial = breaker.call(request(&upstream, "/stall"));
+        assert!(!circuit.allows());
+        // dropped before it was polled
+        drop(trial);
+        assert!(circuit.allows());
+
+        let trial = breaker.call(request(&upstream, "/stall"));
+        let timeout = tokio::time::timeout(Duration::from_millis(10), trial);
+        assert!(timeout.await.is_err());
+        assert!(circuit.allows());
+
+        let trial = breaker.call(request(&upstream, "/200"));
+        assert_eq!(trial.await.unwrap().status(), 200);
+        assert_eq!(circuit.acquire(), Ok((Admission::Regular, None)));
+    }
+}
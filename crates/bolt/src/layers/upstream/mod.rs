@@ -0,0 +1,165 @@
+//! Services between `proxy_pass` and the servers of an upstream
+//!
+//! Every request passes through [`stack`], outermost first:
+//!
+//! - [`retry`] sends requests which failed before reaching a server, or with `502`, `503` or
+//!   `504`, again
+//! - [`pick`] chooses the server for each try
+//! - [`breaker`] refuses requests to servers whose circuit is open
+//! - [`timeout`] bounds how long the upstream may stall reading the request or sending the
+//!   response
+//! - [`Send`] hands the request to the pooled client, or a dedicated HTTP/2 connection for
+//!   extended CONNECT. `fastcgi_pass` uses [`Gateway`](crate::fastcgi::Gateway) instead.
+
+use crate::fastcgi::FastCgiError;
+use crate::upstream::connect::Connector;
+use crate::upstream::Upstream;
+use crate::util::PinResultFuture;
+use breaker::{BreakerLayer, CircuitBreaker};
+use hyper::ext::Protocol;
+use hyper::{Body, Client, Method, Request, Response, StatusCode, Uri};
+use pick::{Pick, PickLayer};
+use retry::RetryPolicy;
+use std::error::Error;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use timeout::{Timeout, TimeoutLayer, Timeouts};
+use tower::retry::{Retry, RetryLayer};
+use tower::{Service, ServiceBuilder, ServiceExt};
+use tracing::debug;
+
+pub mod breaker;
+pub mod pick;
+pub mod retry;
+pub mod timeout;
+
+pub type Stack<S> = Retry<RetryPolicy, Pick<CircuitBreaker<Timeout<S>>>>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProxyError {
+    #[error("{0}")]
+    Http(#[from] hyper::Error),
+    #[error("error trying to connect: {0}")]
+    Connect(Box<dyn Error + std::marker::Send + Sync>),
+    #[error("invalid request: {0}")]
+    InvalidRequest(#[from] hyper::http::Error),
+    #[error("no response within {0:?}")]
+    ReadTimeout(Duration),
+    #[error("the request body stalled for {0:?}")]
+    SendTimeout(Duration),
+    #[error("the circuit breaker is open")]
+    CircuitOpen,
+    #[error("{0}")]
+    FastCgi(#[from] FastCgiError),
+}
+
+impl ProxyError {
+    /// Responded to the client instead of the response of the upstream
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProxyError::Http(_) | ProxyError::Connect(_) | ProxyError::InvalidRequest(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            ProxyError::ReadTimeout(_) | ProxyError::SendTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::FastCgi(err) => err.status(),
+        }
+    }
+
+    /// The request never reached the server, so it's safe to send it again
+    pub fn unsent(&self) -> bool {
+        match self {
+            ProxyError::Http(err) => err.is_connect(),
+            ProxyError::Connect(_) | ProxyError::CircuitOpen => true,
+            ProxyError::FastCgi(err) => err.unsent(),
+            _ => false,
+        }
+    }
+}
+
+/// Sends requests to the server of their uri
+#[derive(Clone)]
+pub struct Send {
+    client: Client<Connector>,
+    /// The pooled client sends any CONNECT in authority-form, even over HTTP/2, which would drop
+    /// the `:path` of an extended CONNECT
+    connector: Connector,
+}
+
+impl Send {
+    /// `h2` speaks HTTP/2 with prior knowledge, for `proxy_pass ... h2`
+    pub fn new(connect_timeout: Duration, h2: bool) -> Self {
+        let connector = Connector::new(Some(connect_timeout));
+        Self {
+            client: Client::builder().http2_only(h2).build(connector.clone()),
+            connector,
+        }
+    }
+}
+
+impl Service<Request<Body>> for Send {
+    type Response = Response<Body>;
+    type Error = ProxyError;
+    type Future = PinResultFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if req.method() == Method::CONNECT && req.extensions().get::<Protocol>().is_some() {
+            return Box::pin(extended_connect(self.connector.clone(), req));
+        }
+        let response = self.client.request(req);
+        Box::pin(async move { Ok(response.await?) })
+    }
+}
+
+/// Sends `req` over a new HTTP/2 connection, which lasts as long as the tunnel
+async fn extended_connect(
+    connector: Connector,
+    req: Request<Body>,
+) -> Result<Response<Body>, ProxyError> {
+    let mut server = Uri::builder();
+    if let Some(scheme) = req.uri().scheme() {
+        server = server.scheme(scheme.clone());
+    }
+    if let Some(authority) = req.uri().authority() {
+        server = server.authority(authority.clone());
+    }
+    let server = server.path_and_query("/").build()?;
+
+    let stream = connector
+        .oneshot(server)
+        .await
+        .map_err(ProxyError::Connect)?;
+    let (mut sender, conn) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(stream)
+        .await?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            debug!("Extended CONNECT connection failed: {}", err);
+        }
+    });
+    Ok(sender.send_request(req).await?)
+}
+
+/// The services for a single request to `upstream`, `key` is the rendered key of `balance hash`
+pub fn stack<S>(
+    send: S,
+    upstream: Arc<Upstream>,
+    key: Option<String>,
+    timeouts: Timeouts,
+) -> Stack<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = ProxyError>,
+{
+    ServiceBuilder::new()
+        .layer(RetryLayer::new(RetryPolicy::new(upstream.clone())))
+        .layer(PickLayer::new(upstream, key))
+        .layer(BreakerLayer)
+        .layer(TimeoutLayer::new(timeouts))
+        .service(send)
+}
@@ -0,0 +1,184 @@
+//! Balances each try of a request over the servers of the upstream
+
+use super::ProxyError;
+use crate::upstream::{self, connect, Upstream};
+use crate::util::PinResultFuture;
+use hyper::http::uri::PathAndQuery;
+use hyper::{Body, Request, Response};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct PickLayer {
+    upstream: Arc<Upstream>,
+    key: Option<Arc<str>>,
+}
+
+#[derive(Clone)]
+pub struct Pick<S> {
+    inner: S,
+    upstream: Arc<Upstream>,
+    key: Option<Arc<str>>,
+}
+
+/// The server a try is sent to, for the layers below [`Pick`]
+#[derive(Clone)]
+pub struct Picked(pub Arc<upstream::Pick>);
+
+impl PickLayer {
+    pub fn new(upstream: Arc<Upstream>, key: Option<String>) -> Self {
+        Self {
+            upstream,
+            key: key.map(Arc::from),
+        }
+    }
+}
+
+impl<S> Layer<S> for PickLayer {
+    type Service = Pick<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Pick {
+            inner,
+            upstream: self.upstream.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for Pick<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = ProxyError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = ProxyError;
+    type Future = PinResultFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let pick = Arc::new(self.upstream.pick(self.key.as_deref()));
+        let addr = &pick.server().addr;
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .cloned()
+            .unwrap_or_else(|| PathAndQuery::from_static("/"));
+        *req.uri_mut() = match connect::uri(addr, path_and_query) {
+            Ok(uri) => uri,
+            Err(err) => return Box::pin(async move { Err(err.into()) }),
+        };
+        connect::set_host(req.headers_mut(), addr);
+        req.extensions_mut().insert(Picked(pick.clone()));
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = response.await;
+            match &result {
+                Ok(response) => pick.record(response.status().is_server_error()),
+                // not the fault of the server
+                Err(ProxyError::CircuitOpen) => {}
+                Err(err) => {
+                    warn!("Upstream {} failed: {}", pick.server().addr, err);
+                    pick.record(true);
+                }
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bolt_config::ast::Span;
+    use bolt_config::model::{self, Balance, Eject, Retry, UpstreamServer};
+    use bolt_config::source::FileId;
+    use hyper::header::{HeaderValue, HOST};
+    use hyper::Uri;
+    use pretty_assertions::assert_eq;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tower::service_fn;
+
+    fn upstream() -> Arc<Upstream> {
+        Arc::new(Upstream::new(&model::Upstream {
+            name: "app".to_string(),
+            servers: ["a:80", "unix:/run/app.sock"]
+                .iter()
+                .map(|addr| UpstreamServer {
+                    addr: addr.to_string(),
+                    weight: 1,
+                })
+                .collect(),
+            balance: Balance::RoundRobin,
+            health_check: None,
+            eject: Eject {
+                after: 1,
+                backoff: Duration::from_secs(60),
+            },
+            retry: Retry::default(),
+            breaker: None,
+            span: Span::start(FileId(0)),
+        }))
+    }
+
+    #[tokio::test]
+    async fn rewrites_and_records() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        // `a:80` fails with `502`, `/open` as if its circuit was open
+        let send = service_fn({
+            let seen = seen.clone();
+            move |req: Request<Body>| {
+                let host = req.headers().get(HOST).cloned();
+                seen.lock().unwrap().push((req.uri().clone(), host));
+                async move {
+                    let Picked(pick) = req.extensions().get::<Picked>().unwrap();
+                    if req.uri().path() == "/open" {
+                        return Err(ProxyError::CircuitOpen);
+                    }
+                    let status = match pick.server().addr.as_str() {
+                        "a:80" => 502,
+                        _ => 200,
+                    };
+                    Ok(Response::builder()
+                        .status(status)
+                        .body(Body::empty())
+                        .unwrap())
+                }
+            }
+        });
+        let mut pick = PickLayer::new(upstream(), None).layer(send);
+        let mut call = |path| {
+            let req = Request::get(path)
+                .header(HOST, "example.com")
+                .body(Body::empty())
+                .unwrap();
+            pick.call(req)
+        };
+
+        assert!(matches!(call("/open").await, Err(ProxyError::CircuitOpen)));
+        assert_eq!(call("/x?y").await.unwrap().status(), 200);
+        // still in rotation, the open circuit wasn't its failure
+        assert_eq!(call("/x?y").await.unwrap().status(), 502);
+        // ejected after its first failure
+        assert_eq!(call("/x?y").await.unwrap().status(), 200);
+        assert_eq!(call("/x?y").await.unwrap().status(), 200);
+
+        let tcp = (Uri::from_static("http://a:80/x?y"), None);
+        let unix = (
+            connect::uri("unix:/run/app.sock", PathAndQuery::from_static("/x?y")).unwrap(),
+            Some(HeaderValue::from_static("localhost")),
+        );
+        let open = (Uri::from_static("http://a:80/open"), None);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![open, unix.clone(), tcp, unix.clone(), unix]
+        );
+    }
+}
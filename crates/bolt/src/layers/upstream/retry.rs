@@ -0,0 +1,138 @@
+//! `retry.*`, sends requests with idempotent methods again after connect errors, `502`, `503` or
+//! `504`
+
+use super::ProxyError;
+use crate::fastcgi::Params;
+use crate::upstream::Upstream;
+use futures_util::future::{ready, Ready};
+use hyper::body::HttpBody;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::sync::Arc;
+use tower::retry::Policy;
+use tracing::debug;
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    upstream: Arc<Upstream>,
+    /// Tries of the request so far
+    tries: u32,
+}
+
+impl RetryPolicy {
+    pub fn new(upstream: Arc<Upstream>) -> Self {
+        Self { upstream, tries: 1 }
+    }
+}
+
+impl Policy<Request<Body>, Response<Body>, ProxyError> for RetryPolicy {
+    type Future = Ready<Self>;
+
+    fn retry(
+        &self,
+        req: &Request<Body>,
+        result: Result<&Response<Body>, &ProxyError>,
+    ) -> Option<Self::Future> {
+        let budget = &self.upstream.budget;
+        if self.tries == 1 {
+            budget.deposit();
+        }
+
+        let retryable = match result {
+            Ok(response) => matches!(
+                response.status(),
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Err(err) => err.unsent(),
+        };
+        if !retryable || self.tries >= self.upstream.retry.tries {
+            return None;
+        }
+        if budget.withdraw().is_err() {
+            debug!(
+                "Not retrying {}, the retry budget of upstream {} is used up",
+                req.uri(),
+                self.upstream.name
+            );
+            return None;
+        }
+
+        debug!("Retrying {}, try {}", req.uri(), self.tries + 1);
+        Some(ready(Self {
+            upstream: self.upstream.clone(),
+            tries: self.tries + 1,
+        }))
+    }
+
+    /// Only requests without a body can be sent again, it's gone once streamed to the upstream
+    fn clone_request(&self, req: &Request<Body>) -> Option<Request<Body>> {
+        if self.upstream.retry.tries <= 1
+            || !idempotent(req.method())
+            || !req.body().is_end_stream()
+        {
+            return None;
+        }
+
+        let mut clone = Request::new(Body::empty());
+        *clone.method_mut() = req.method().clone();
+        *clone.uri_mut() = req.uri().clone();
+        *clone.version_mut() = req.version();
+        *clone.headers_mut() = req.headers().clone();
+        if let Some(params) = req.extensions().get::<Params>() {
+            clone.extensions_mut().insert(params.clone());
+        }
+        Some(clone)
+    }
+}
+
+/// Methods which have the same effect no matter how often they're sent, RFC 9110 section 9.2.2
+fn idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, body: Body) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri("/a")
+            .body(body)
+            .unwrap()
+    }
+
+    #[test]
+    fn clones_idempotent_requests() {
+        let policy = RetryPolicy::new(Arc::new(Upstream::single("a:80")));
+        let req = request(Method::GET, Body::empty());
+        let clone = policy.clone_request(&req).unwrap();
+        assert_eq!(clone.method(), Method::GET);
+        assert_eq!(clone.uri(), "/a");
+
+        assert!(policy
+            .clone_request(&request(Method::POST, Body::empty()))
+            .is_none());
+        assert!(policy
+            .clone_request(&request(Method::PUT, Body::from("body")))
+            .is_none());
+    }
+
+    #[test]
+    fn bounded_tries() {
+        let policy = RetryPolicy::new(Arc::new(Upstream::single("a:80")));
+        let req = request(Method::GET, Body::empty());
+        let bad_gateway = Response::builder().status(502).body(Body::empty()).unwrap();
+        let ok = Response::new(Body::empty());
+
+        assert!(policy.retry(&req, Ok(&ok)).is_none());
+        let retried = policy.retry(&req, Ok(&bad_gateway)).unwrap().into_inner();
+        assert_eq!(retried.tries, 2);
+        // the default is 2 tries
+        assert!(retried.retry(&req, Ok(&bad_gateway)).is_none());
+    }
+}
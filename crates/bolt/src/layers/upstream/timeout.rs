@@ -0,0 +1,275 @@
+//! `proxy_read_timeout` and `proxy_send_timeout`, the connect timeout is part of the client
+
+use super::ProxyError;
+use crate::util::PinResultFuture;
+use bolt_config::model::ProxyTimeouts;
+use futures_util::Stream;
+use hyper::body::{Bytes, HttpBody, Sender};
+use hyper::{Body, Request, Response};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Instant;
+use tower::{Layer, Service};
+use tracing::{debug, warn};
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    pub read: Duration,
+    pub send: Duration,
+}
+
+impl From<ProxyTimeouts> for Timeouts {
+    fn from(timeouts: ProxyTimeouts) -> Self {
+        Self {
+            read: timeouts.read.unwrap_or(DEFAULT_READ_TIMEOUT),
+            send: timeouts.send.unwrap_or(DEFAULT_SEND_TIMEOUT),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct TimeoutLayer {
+    timeouts: Timeouts,
+}
+
+#[derive(Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    timeouts: Timeouts,
+}
+
+impl TimeoutLayer {
+    pub fn new(timeouts: Timeouts) -> Self {
+        Self { timeouts }
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Timeout {
+            inner,
+            timeouts: self.timeouts,
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for Timeout<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = ProxyError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = ProxyError;
+    type Future = PinResultFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let timeouts = self.timeouts;
+        let progress = Arc::new(Mutex::new(Progress::Sending(Instant::now())));
+        let (parts, body) = req.into_parts();
+        let body = match body.is_end_stream() {
+            true => {
+                *progress.lock().unwrap() = Progress::Sent(Instant::now());
+                body
+            }
+            false => Body::wrap_stream(Tracked {
+                body,
+                progress: progress.clone(),
+            }),
+        };
+        let response = self.inner.call(Request::from_parts(parts, body));
+
+        Box::pin(async move {
+            tokio::pin!(response);
+            let response = loop {
+                let deadline = progress.lock().unwrap().deadline(timeouts);
+                tokio::select! {
+                    response = &mut response => break response?,
+                    _ = tokio::time::sleep_until(deadline) => {
+                        // the body may have made progress in the meantime
+                        let progress = *progress.lock().unwrap();
+                        if progress.deadline(timeouts) <= Instant::now() {
+                            return Err(progress.error(timeouts));
+                        }
+                    }
+                }
+            };
+
+            let (parts, body) = response.into_parts();
+            if body.is_end_stream() {
+                return Ok(Response::from_parts(parts, body));
+            }
+            let (sender, streamed) = Body::channel();
+            tokio::spawn(pump(body, sender, timeouts.read));
+            Ok(Response::from_parts(parts, streamed))
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Progress {
+    /// The last time a chunk of the request body was taken
+    Sending(Instant),
+    /// When the whole request body was taken
+    Sent(Instant),
+}
+
+impl Progress {
+    fn deadline(self, timeouts: Timeouts) -> Instant {
+        match self {
+            Progress::Sending(last) => last + timeouts.send,
+            Progress::Sent(at) => at + timeouts.read,
+        }
+    }
+
+    fn error(self, timeouts: Timeouts) -> ProxyError {
+        match self {
+            Progress::Sending(_) => ProxyError::SendTimeout(timeouts.send),
+            Progress::Sent(_) => ProxyError::ReadTimeout(timeouts.read),
+        }
+    }
+}
+
+/// Records how the request body is taken by the client
+struct Tracked {
+    body: Body,
+    progress: Arc<Mutex<Progress>>,
+}
+
+impl Stream for Tracked {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = Pin::new(&mut self.body).poll_data(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(_))) => {
+                *self.progress.lock().unwrap() = Progress::Sending(Instant::now())
+            }
+            Poll::Ready(None) => *self.progress.lock().unwrap() = Progress::Sent(Instant::now()),
+            _ => {}
+        }
+        polled
+    }
+}
+
+/// Streams the response body to the client, aborting it once the upstream stalls for longer than
+/// `read`. Trailers are passed on as well, unlike with a wrapped stream.
+async fn pump(mut body: Body, mut sender: Sender, read: Duration) {
+    loop {
+        match tokio::time::timeout(read, body.data()).await {
+            Ok(Some(Ok(data))) => {
+                if sender.send_data(data).await.is_err() {
+                    debug!("Client went away while streaming the response");
+                    return;
+                }
+            }
+            Ok(Some(Err(err))) => {
+                debug!("Upstream failed while streaming the response: {}", err);
+                return sender.abort();
+            }
+            Ok(None) => break,
+            Err(_) => {
+                warn!(
+                    "Upstream stalled for {:?} while streaming the response",
+                    read
+                );
+                return sender.abort();
+            }
+        }
+    }
+
+    match tokio::time::timeout(read, body.trailers()).await {
+        Ok(Ok(Some(trailers))) => {
+            let _ = sender.send_trailers(trailers).await;
+        }
+        Ok(Ok(None)) => {}
+        Ok(Err(_)) | Err(_) => sender.abort(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use tower::{service_fn, ServiceExt};
+
+    const TIMEOUTS: Timeouts = Timeouts {
+        read: Duration::from_millis(100),
+        send: Duration::from_millis(50),
+    };
+
+    async fn call<F, R>(respond: F, body: Body) -> Result<Response<Body>, ProxyError>
+    where
+        F: FnMut(Request<Body>) -> R,
+        R: Future<Output = Result<Response<Body>, ProxyError>> + Send + 'static,
+    {
+        let req = Request::post("http://a/").body(body).unwrap();
+        TimeoutLayer::new(TIMEOUTS)
+            .layer(service_fn(respond))
+            .oneshot(req)
+            .await
+    }
+
+    /// Reads the request body and responds once it's complete
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, ProxyError> {
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+        Ok(Response::new(Body::from(body)))
+    }
+
+    #[tokio::test]
+    async fn send_timeout() {
+        let (mut sender, body) = Body::channel();
+        sender.send_data("a".into()).await.unwrap();
+        let stalled = call(echo, body).await;
+        assert!(matches!(stalled, Err(ProxyError::SendTimeout(_))));
+        drop(sender);
+
+        // each chunk extends the deadline
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..4 {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                sender.send_data("a".into()).await.unwrap();
+            }
+        });
+        let response = call(echo, body).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "aaaa");
+    }
+
+    #[tokio::test]
+    async fn read_timeout() {
+        let never = |_| futures_util::future::pending();
+        let result = call(never, Body::empty()).await;
+        assert!(matches!(result, Err(ProxyError::ReadTimeout(_))));
+    }
+
+    #[tokio::test]
+    async fn aborts_stalled_responses() {
+        let (mut sender, streamed) = Body::channel();
+        sender.send_data("head".into()).await.unwrap();
+        let mut streamed = Some(streamed);
+        let respond = move |_| {
+            let body = streamed.take().unwrap();
+            async move { Ok(Response::new(body)) }
+        };
+
+        let mut body = call(respond, Body::empty()).await.unwrap().into_body();
+        assert_eq!(body.data().await.unwrap().unwrap(), "head");
+        let started = Instant::now();
+        assert!(body.data().await.unwrap().is_err());
+        assert!(started.elapsed() >= TIMEOUTS.read);
+        drop(sender);
+    }
+}
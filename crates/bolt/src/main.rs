@@ -1,17 +1,63 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use bolt_config::model::{Compression, ListenAddr};
+use clap::Parser;
+use cli::Command;
+use layers::raw::stream;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::task::JoinHandle;
+use tokio::net::{TcpListener, UnixListener};
 use tokio_rustls::TlsAcceptor;
 use tower::{Service, ServiceExt};
-use tracing::error;
+use tracing::{debug, error, info};
+use tracing_subscriber::EnvFilter;
+use upstream::Upstream;
 
 mod cli;
+mod fastcgi;
+mod handlers;
 mod layers;
+mod mime;
+mod sites;
 mod tls;
+mod upstream;
 mod util;
 
 fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let args = cli::Args::parse();
+    let code = match args.command.unwrap_or(Command::Run) {
+        Command::Run => run(&args),
+        Command::Check => cli::check(&args.config),
+        Command::Dump => cli::dump(&args.config),
+    };
+
+    std::process::exit(code);
+}
+
+fn run(args: &cli::Args) -> i32 {
+    let config = match cli::load(&args.config) {
+        Some(config) => config,
+        None => return 1,
+    };
+    let (listeners, upstreams) = match sites::compile(&config)
+        .and_then(|sites| Ok((sites::listeners(&sites)?, sites::upstreams(&sites))))
+    {
+        Ok(compiled) => compiled,
+        Err(err) => {
+            error!("{}", err);
+            return 1;
+        }
+    };
+
+    let compression = Arc::new(config.compression.clone());
     let cpus = num_cpus::get();
 
     tokio::runtime::Builder::new_multi_thread()
@@ -19,21 +65,51 @@ fn main() {
         .worker_threads(cpus)
         .build()
         .expect("Unable to build tokio runtime")
-        .block_on(start());
+        .block_on(start(listeners, upstreams, compression))
 }
 
-async fn start() {
-    if let Err(err) = listen(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1337)).await {
-        error!("{}", err);
+async fn start(
+    listeners: Vec<sites::Listener>,
+    upstreams: Vec<Arc<Upstream>>,
+    compression: Arc<Compression>,
+) -> i32 {
+    if listeners.is_empty() {
+        error!("No site has a `listen` directive, nothing to do");
+        return 1;
+    }
+    for upstream in &upstreams {
+        upstream::spawn_checks(upstream);
+    }
+
+    let handles = listeners
+        .into_iter()
+        .map(|listener| {
+            let compression = compression.clone();
+            tokio::spawn(async move {
+                let addr = listener.addr.clone();
+                if let Err(err) = listen(listener, compression).await {
+                    error!("{}", err);
+                }
+                addr
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        if let Ok(addr) = handle.await {
+            error!("Stopped listening on {}", addr);
+        }
     }
+
+    1
 }
 
 #[derive(thiserror::Error, Debug)]
 enum ListenError {
-    #[error("Unable to bind to {0}")]
-    BindFailure(std::io::Error, SocketAddr),
-    #[error("Unable to listen on {0}")]
-    AcceptFailure(std::io::Error, SocketAddr),
+    #[error("Unable to bind to {1}: {0}")]
+    BindFailure(std::io::Error, ListenAddr),
+    #[error("Unable to listen on {1}: {0}")]
+    AcceptFailure(std::io::Error, ListenAddr),
     #[error("Io error: {0}")]
     IoError(#[from] std::io::Error),
 }
@@ -46,31 +122,134 @@ enum ConnError {
     HttpError(#[from] hyper::Error),
 }
 
-async fn listen(addr: SocketAddr) -> Result<(), ListenError> {
-    let listener = TcpListener::bind(addr)
-        .await
-        .map_err(|err| ListenError::BindFailure(err, addr))?;
+async fn listen(
+    sites::Listener {
+        addr,
+        tls,
+        h2,
+        vhosts,
+    }: sites::Listener,
+    compression: Arc<Compression>,
+) -> Result<(), ListenError> {
+    let listener = match &addr {
+        ListenAddr::Tcp(tcp) => bind(*tcp).map(Bound::Tcp),
+        ListenAddr::Unix(path) => bind_unix(path).map(Bound::Unix),
+    }
+    .map_err(|err| ListenError::BindFailure(err, addr.clone()))?;
+    info!(%addr, tls, h2, "Listening");
 
-    let config = tls::mk_config();
-    let acceptor = Arc::new(TlsAcceptor::from(config.clone()));
+    let acceptor = tls.then(|| Arc::new(TlsAcceptor::from(tls::mk_config(vhosts.clone(), h2))));
 
     let mut handler = layers::raw::UpgradeService::new(acceptor);
 
     loop {
-        let (stream, _) = listener
+        let stream = listener
             .accept()
             .await
-            .map_err(|err| ListenError::AcceptFailure(err, addr))?;
+            .map_err(|err| ListenError::AcceptFailure(err, addr.clone()))?;
 
         let future = handler.ready().await?.call(stream);
-        let jh: JoinHandle<Result<(), ConnError>> = tokio::task::spawn(async move {
-            let request = future.await?;
-            layers::http::RawWebService {}
-                .ready()
-                .await?
-                .call(request)
-                .await?;
-            Ok(())
+        let mut service = layers::http::RawWebService::new(vhosts.clone(), h2, compression.clone());
+        tokio::task::spawn(async move {
+            let result: Result<(), ConnError> = async move {
+                let request = future.await?;
+                service.ready().await?.call(request).await?;
+                Ok(())
+            }
+            .await;
+
+            if let Err(err) = result {
+                debug!("Connection closed with error: {}", err);
+            }
         });
     }
 }
+
+/// Binds a listener, ipv6 sockets are bound as v6 only so `[::]` and `0.0.0.0` can share a port
+fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds a Unix domain socket, replacing a stale one left behind by an earlier run
+///
+/// A socket is only stale if connecting to it is refused, one that still accepts connections
+/// belongs to a running process and is left alone.
+fn bind_unix(path: &Path) -> std::io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::AddrInUse,
+                        "the socket is still accepting connections",
+                    ))
+                }
+                Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path)?
+                }
+                Err(_) => {}
+            }
+        }
+        _ => {}
+    }
+    UnixListener::bind(path)
+}
+
+enum Bound {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Bound {
+    async fn accept(&self) -> std::io::Result<stream::Socket> {
+        match self {
+            Bound::Tcp(listener) => Ok(stream::Socket::Tcp(listener.accept().await?.0)),
+            Bound::Unix(listener) => Ok(stream::Socket::Unix(listener.accept().await?.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bolt_{}_{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn keeps_live_sockets() {
+        let path = socket_path("live");
+        let _running = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let err = bind_unix(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replaces_stale_sockets() {
+        let path = socket_path("stale");
+        // the socket file outlives its listener
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let listener = bind_unix(&path).unwrap();
+        std::os::unix::net::UnixStream::connect(&path).unwrap();
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+//! Content types of responses, from a built in table extended by `types` and `default_type`
+
+use bolt_config::model::Types;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Used without a `default_type` directive
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// Extensions and their mime type, extensions are lowercase and without the dot
+static BUILTIN: &[(&str, &str)] = &[
+    // text
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("wasm", "application/wasm"),
+    // images
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    // fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    // audio and video
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    // documents and archives
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("zst", "application/zstd"),
+    ("br", "application/x-brotli"),
+    ("7z", "application/x-7z-compressed"),
+    ("deb", "application/vnd.debian.binary-package"),
+    ("rpm", "application/x-rpm"),
+    ("jar", "application/java-archive"),
+    ("exe", "application/vnd.microsoft.portable-executable"),
+    ("iso", "application/x-iso9660-image"),
+];
+
+/// Types which get `charset=utf-8` unless the config specifies parameters itself
+const TEXTUAL: &[&str] = &["application/javascript", "application/xml", "image/svg+xml"];
+
+/// Lookup shared by all handlers of a site
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    extensions: HashMap<String, String>,
+    default: String,
+}
+
+impl MimeTypes {
+    /// The built in types overridden by each of `overrides` in turn, usually those of the main
+    /// config file followed by those of the site
+    pub fn new<'t>(overrides: impl IntoIterator<Item = &'t Types>) -> Self {
+        let mut extensions = BUILTIN
+            .iter()
+            .map(|(ext, mime)| (ext.to_string(), with_charset(mime)))
+            .collect::<HashMap<_, _>>();
+        let mut default = DEFAULT_TYPE.to_string();
+
+        for types in overrides {
+            for (ext, mime) in &types.extensions {
+                extensions.insert(ext.clone(), with_charset(mime));
+            }
+            if let Some(mime) = &types.default {
+                default = with_charset(mime);
+            }
+        }
+
+        Self {
+            extensions,
+            default,
+        }
+    }
+
+    /// `Content-Type` of a file, the default type if the extension isn't known
+    pub fn for_path(&self, path: &Path) -> &str {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.for_extension(ext))
+            .unwrap_or(&self.default)
+    }
+
+    /// `Content-Type` registered for an extension, without the dot
+    pub fn for_extension(&self, ext: &str) -> Option<&str> {
+        self.extensions
+            .get(&ext.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        Self::new([])
+    }
+}
+
+fn with_charset(mime: &str) -> String {
+    if !mime.contains(';') && (mime.starts_with("text/") || TEXTUAL.contains(&mime)) {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn types(entries: &[(&str, &str)], default: Option<&str>) -> Types {
+        Types {
+            extensions: entries
+                .iter()
+                .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+                .collect(),
+            default: default.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn builtin() {
+        let mime = MimeTypes::default();
+        assert_eq!(
+            mime.for_path(Path::new("/a/index.html")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(mime.for_path(Path::new("logo.PNG")), "image/png");
+        assert_eq!(
+            mime.for_path(Path::new("icon.svg")),
+            "image/svg+xml; charset=utf-8"
+        );
+        assert_eq!(
+            mime.for_path(Path::new("Makefile")),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            mime.for_path(Path::new("archive.unknown")),
+            "application/octet-stream"
+        );
+        assert_eq!(mime.for_extension("json"), Some("application/json"));
+    }
+
+    #[test]
+    fn overrides() {
+        let global = types(
+            &[("md", "text/plain"), ("wasm", "application/x-wasm")],
+            Some("text/plain"),
+        );
+        let site = types(&[("md", "text/x-markdown; charset=latin1")], None);
+        let mime = MimeTypes::new([&global, &site]);
+
+        assert_eq!(
+            mime.for_extension("md"),
+            Some("text/x-markdown; charset=latin1")
+        );
+        assert_eq!(mime.for_extension("wasm"), Some("application/x-wasm"));
+        assert_eq!(
+            mime.for_path(Path::new("README")),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(mime.for_extension("css"), Some("text/css; charset=utf-8"));
+    }
+}
@@ -0,0 +1,327 @@
+use crate::fastcgi;
+use crate::handlers::proxy::Clients;
+use crate::mime::MimeTypes;
+use crate::tls::{self, TlsError};
+use crate::upstream::Upstream;
+use bolt_config::model::{self, domain_router, ListenAddr, Location, LocationModifier};
+use bolt_config::{Config, Site};
+use bolt_router::{DomainRouter, PathRouter, Slot, StrategyMatch};
+use bolt_url::UrlPath;
+use rustls::sign::CertifiedKey;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A site with everything resolved that can fail at startup
+pub struct CompiledSite {
+    pub site: Arc<Site>,
+    /// Routes to the index of the location in `site.locations`
+    pub locations: PathRouter,
+    pub cert: Option<Arc<CertifiedKey>>,
+    /// Types of the main config file and the site
+    pub types: MimeTypes,
+    /// Shared by all sites
+    pub clients: Arc<Clients>,
+    /// Shared by all sites
+    pub fastcgi: Arc<fastcgi::Clients>,
+    /// Upstreams of all `proxy_pass` and `fastcgi_pass` directives of the site by their
+    /// authority, groups of the main config file are shared with other sites
+    pub upstreams: HashMap<String, Arc<Upstream>>,
+}
+
+impl CompiledSite {
+    /// `location @ '<name>'`
+    pub fn named(&self, name: &str) -> Option<&Location> {
+        self.site.locations.iter().find(|location| {
+            location.modifier == LocationModifier::Named && location.pattern == name
+        })
+    }
+}
+
+/// The sites reachable through one listener
+pub struct VHosts {
+    router: DomainRouter,
+    sites: Vec<Arc<CompiledSite>>,
+}
+
+/// A unique socket, bound once even if multiple sites listen on it
+pub struct Listener {
+    pub addr: ListenAddr,
+    pub tls: bool,
+    pub h2: bool,
+    pub vhosts: Arc<VHosts>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CompileError {
+    #[error("{}: invalid location pattern: {1}", .0.display())]
+    InvalidLocation(PathBuf, regex::Error),
+    #[error("{0} has an invalid site pattern: {1}")]
+    InvalidPattern(ListenAddr, regex::Error),
+    #[error("{}: {1}", .0.display())]
+    TlsFailure(PathBuf, TlsError),
+    #[error("{0} is declared with conflicting flags, all `listen` directives for the same address must agree on `tls` and `h2`")]
+    ConflictingListen(ListenAddr),
+    #[error("{0} is a tls listener but none of its sites has a certificate")]
+    MissingCertificate(ListenAddr),
+}
+
+pub fn compile(config: &Config) -> Result<Vec<Arc<CompiledSite>>, CompileError> {
+    let clients = Arc::new(Clients::new());
+    let fastcgi = Arc::new(fastcgi::Clients::new());
+    let upstreams = compile_upstreams(&config.upstreams);
+    config
+        .sites
+        .iter()
+        .map(|site| {
+            compile_site(config, site, clients.clone(), fastcgi.clone(), &upstreams).map(Arc::new)
+        })
+        .collect()
+}
+
+fn compile_site(
+    config: &Config,
+    site: &Arc<Site>,
+    clients: Arc<Clients>,
+    fastcgi: Arc<fastcgi::Clients>,
+    global_upstreams: &HashMap<String, Arc<Upstream>>,
+) -> Result<CompiledSite, CompileError> {
+    let locations = path_router(&site.locations)
+        .map_err(|err| CompileError::InvalidLocation(site.path.clone(), err))?;
+
+    let cert = site
+        .tls
+        .as_ref()
+        .map(tls::load_certified_key)
+        .transpose()
+        .map_err(|err| CompileError::TlsFailure(site.path.clone(), err))?
+        .map(Arc::new);
+
+    Ok(CompiledSite {
+        site: site.clone(),
+        locations,
+        cert,
+        types: MimeTypes::new([&config.types, &site.types]),
+        clients,
+        fastcgi,
+        upstreams: site_upstreams(site, global_upstreams),
+    })
+}
+
+fn compile_upstreams(upstreams: &[model::Upstream]) -> HashMap<String, Arc<Upstream>> {
+    upstreams
+        .iter()
+        .map(|upstream| (upstream.name.clone(), Arc::new(Upstream::new(upstream))))
+        .collect()
+}
+
+/// Resolves every `proxy_pass` and `fastcgi_pass` to an `upstream` block, those of the site shadow
+/// those of the main config file. Anything else is a single server.
+fn site_upstreams(
+    site: &Site,
+    global: &HashMap<String, Arc<Upstream>>,
+) -> HashMap<String, Arc<Upstream>> {
+    let own = compile_upstreams(&site.upstreams);
+    let mut resolved = HashMap::new();
+    let authorities = site.locations.iter().flat_map(|location| {
+        let proxy_pass = location.proxy_pass.as_ref().map(|pass| &pass.authority);
+        let fastcgi_pass = location.fastcgi_pass.as_ref().map(|pass| &pass.authority);
+        proxy_pass.into_iter().chain(fastcgi_pass)
+    });
+    for authority in authorities {
+        let upstream = own
+            .get(authority)
+            .or_else(|| global.get(authority))
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Upstream::single(authority)));
+        resolved.insert(authority.clone(), upstream);
+    }
+    resolved
+}
+
+fn path_router(locations: &[Location]) -> Result<PathRouter, regex::Error> {
+    let mut builder = PathRouter::builder();
+    for (i, location) in locations.iter().enumerate() {
+        let slot = Slot(i as u64);
+        let path = || {
+            location
+                .pattern
+                .parse::<UrlPath>()
+                .expect("location paths are validated while loading")
+        };
+        match location.modifier {
+            LocationModifier::Exact => builder.exact(&path(), slot),
+            LocationModifier::Prefix => builder.prefix(&path(), slot),
+            LocationModifier::Regex => builder.regex(&location.pattern, slot),
+            // only reachable through `try_files`
+            LocationModifier::Named => continue,
+        };
+    }
+    builder.build()
+}
+
+/// Every upstream used by the sites once, even if shared between them
+pub fn upstreams(sites: &[Arc<CompiledSite>]) -> Vec<Arc<Upstream>> {
+    let mut upstreams = Vec::<Arc<Upstream>>::new();
+    for upstream in sites.iter().flat_map(|site| site.upstreams.values()) {
+        if !upstreams.iter().any(|known| Arc::ptr_eq(known, upstream)) {
+            upstreams.push(upstream.clone());
+        }
+    }
+    upstreams
+}
+
+/// Groups all `listen` directives by address
+pub fn listeners(sites: &[Arc<CompiledSite>]) -> Result<Vec<Listener>, CompileError> {
+    let mut by_addr = BTreeMap::<ListenAddr, (bool, bool, Vec<Arc<CompiledSite>>)>::new();
+
+    for site in sites {
+        for listen in &site.site.listen {
+            let (tls, h2, sites) = by_addr
+                .entry(listen.addr.clone())
+                .or_insert_with(|| (listen.tls, listen.h2, vec![]));
+
+            if (*tls, *h2) != (listen.tls, listen.h2) {
+                return Err(CompileError::ConflictingListen(listen.addr.clone()));
+            }
+            if !sites.iter().any(|s| Arc::ptr_eq(s, site)) {
+                sites.push(site.clone());
+            }
+        }
+    }
+
+    by_addr
+        .into_iter()
+        .map(|(addr, (tls, h2, sites))| {
+            if tls && sites.iter().all(|site| site.cert.is_none()) {
+                return Err(CompileError::MissingCertificate(addr));
+            }
+
+            let router = domain_router(sites.iter().map(|site| &*site.site))
+                .map_err(|err| CompileError::InvalidPattern(addr.clone(), err))?;
+
+            Ok(Listener {
+                addr,
+                tls,
+                h2,
+                vhosts: Arc::new(VHosts { router, sites }),
+            })
+        })
+        .collect()
+}
+
+impl VHosts {
+    /// Finds the site responsible for `host`, falling back to the default site
+    pub fn lookup(&self, host: &str) -> Option<&Arc<CompiledSite>> {
+        self.route(host).map(|(site, _)| site)
+    }
+
+    /// Like [`VHosts::lookup`], but also returns the match which carries regex captures
+    pub fn route(&self, host: &str) -> Option<(&Arc<CompiledSite>, StrategyMatch)> {
+        self.router
+            .route(host)
+            .map(|m| (&self.sites[m.slot.0 as usize], m))
+    }
+
+    pub fn default(&self) -> Option<&Arc<CompiledSite>> {
+        self.router
+            .default_slot()
+            .map(|slot| &self.sites[slot.0 as usize])
+    }
+
+    pub fn sites(&self) -> &[Arc<CompiledSite>] {
+        &self.sites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bolt_config::model::{Cache, Compression, Types};
+    use bolt_config::parse::parse;
+    use bolt_config::source::FileId;
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+
+    fn compiled(sites: &[&str]) -> Vec<Arc<CompiledSite>> {
+        let sites = sites
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let file = FileId(i as u32);
+                let directives = parse(file, text).unwrap();
+                let path = Path::new("sites").join(format!("{}.conf", i));
+                Arc::new(Site::from_directives(&directives, file, &path).unwrap())
+            })
+            .collect();
+        let config = Config {
+            path: PathBuf::from("bolt.conf"),
+            sites_dir: PathBuf::from("sites"),
+            snippets_dir: PathBuf::from("snippets"),
+            cache: Cache::default(),
+            compression: Compression::default(),
+            types: Types::default(),
+            upstreams: vec![],
+            sites,
+        };
+        compile(&config).unwrap()
+    }
+
+    fn listen_error(sites: &[&str]) -> String {
+        match listeners(&compiled(sites)) {
+            Err(err) => err.to_string(),
+            Ok(_) => panic!("listeners should not compile"),
+        }
+    }
+
+    #[test]
+    fn shared_listeners() {
+        let sites = compiled(&[
+            "site = 'a.example'\nlisten '127.0.0.1' 8080\nlisten '127.0.0.1' 8081 h2\n",
+            "site = 'b.example'\nlisten '127.0.0.1' 8080\n",
+            "site _\nlisten '127.0.0.1' 8080\nlisten 'unix:/run/bolt.sock'\n",
+        ]);
+        let listeners = listeners(&sites).unwrap();
+
+        let bound = listeners
+            .iter()
+            .map(|listener| {
+                let addr = listener.addr.to_string();
+                (addr, listener.h2, listener.vhosts.sites().len())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bound,
+            vec![
+                ("127.0.0.1:8080".to_string(), false, 3),
+                ("127.0.0.1:8081".to_string(), true, 1),
+                ("unix:/run/bolt.sock".to_string(), false, 1),
+            ]
+        );
+
+        let vhosts = &listeners[0].vhosts;
+        let site = |host| vhosts.lookup(host).map(|site| site.site.path.clone());
+        assert_eq!(site("a.example"), Some(PathBuf::from("sites/0.conf")));
+        assert_eq!(site("b.example"), Some(PathBuf::from("sites/1.conf")));
+        assert_eq!(site("c.example"), Some(PathBuf::from("sites/2.conf")));
+        // `a.example` only listens on 8081 itself
+        let vhosts = &listeners[1].vhosts;
+        assert!(Arc::ptr_eq(&vhosts.sites()[0], &sites[0]));
+        assert!(vhosts.default().is_none());
+    }
+
+    #[test]
+    fn conflicting_flags() {
+        assert_eq!(
+            listen_error(&[
+                "site = 'a.example'\nlisten '127.0.0.1' 8080\n",
+                "site = 'b.example'\nlisten '127.0.0.1' 8080 h2\n",
+            ]),
+            CompileError::ConflictingListen("127.0.0.1:8080".parse().unwrap()).to_string()
+        );
+        assert_eq!(
+            listen_error(&["site _\nlisten '127.0.0.1' 8443 tls\n"]),
+            CompileError::MissingCertificate("127.0.0.1:8443".parse().unwrap()).to_string()
+        );
+    }
+}
@@ -1,23 +1,95 @@
+use crate::sites::VHosts;
+use bolt_config::model::Tls;
 use rustls::server::{ClientHello, ResolvesServerCert};
 use rustls::sign::CertifiedKey;
-use rustls::ServerConfig;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-pub struct CertResolver {}
+pub struct CertResolver {
+    vhosts: Arc<VHosts>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TlsError {
+    #[error("Unable to read {}: {1}", .0.display())]
+    IoError(PathBuf, std::io::Error),
+    #[error("No certificates found in {}", .0.display())]
+    NoCertificates(PathBuf),
+    #[error("No private key found in {}", .0.display())]
+    NoPrivateKey(PathBuf),
+    #[error("Unsupported private key in {}", .0.display())]
+    UnsupportedKey(PathBuf),
+}
 
 impl ResolvesServerCert for CertResolver {
     fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
-        None
+        client_hello
+            .server_name()
+            .and_then(|name| self.vhosts.lookup(name))
+            .or_else(|| self.vhosts.default())
+            .and_then(|site| site.cert.clone())
+            // Clients without SNI still get a certificate, even if it might not be valid for them
+            .or_else(|| {
+                self.vhosts
+                    .sites()
+                    .iter()
+                    .find_map(|site| site.cert.clone())
+            })
     }
 }
 
-pub fn mk_config() -> Arc<ServerConfig> {
+pub fn mk_config(vhosts: Arc<VHosts>, h2: bool) -> Arc<ServerConfig> {
     let mut config = ServerConfig::builder()
         .with_safe_defaults()
         .with_no_client_auth()
-        .with_cert_resolver(Arc::new(CertResolver {}));
+        .with_cert_resolver(Arc::new(CertResolver { vhosts }));
 
-    config.alpn_protocols = vec![b"http/1.1".to_vec(), b"h2".to_vec()];
+    config.alpn_protocols = if h2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
 
     Arc::new(config)
 }
+
+/// Loads the PEM encoded certificate chain and private key of a site
+pub fn load_certified_key(tls: &Tls) -> Result<CertifiedKey, TlsError> {
+    let certs = rustls_pemfile::certs(&mut open(&tls.cert)?)
+        .map_err(|err| TlsError::IoError(tls.cert.clone(), err))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(tls.cert.clone()));
+    }
+
+    let mut reader = open(&tls.key)?;
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|err| TlsError::IoError(tls.key.clone(), err))?
+        {
+            Some(
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => break key,
+            Some(_) => continue,
+            None => return Err(TlsError::NoPrivateKey(tls.key.clone())),
+        }
+    };
+
+    let key = rustls::sign::any_supported_type(&PrivateKey(key))
+        .map_err(|_| TlsError::UnsupportedKey(tls.key.clone()))?;
+
+    Ok(CertifiedKey::new(
+        certs.into_iter().map(Certificate).collect(),
+        key,
+    ))
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError::IoError(path.to_path_buf(), err))
+}
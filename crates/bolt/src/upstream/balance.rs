@@ -0,0 +1,278 @@
+//! Policies of `balance`, each picks the index of a server
+
+use super::Server;
+use bolt_config::model::Balance;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Points on the hash ring per unit of weight, more points spread the keys more evenly
+const POINTS_PER_WEIGHT: u32 = 40;
+
+pub(super) enum Balancer {
+    RoundRobin(AtomicUsize),
+    /// Smooth weighted round robin as in nginx, the current weight of every server
+    Weighted(Mutex<Vec<i64>>),
+    /// Rotates the start of the search so ties don't always go to the first server
+    LeastConn(AtomicUsize),
+    RandomTwo(Random),
+    /// Points sorted by their hash, with the index of the server they belong to
+    Hash(Vec<(u64, usize)>),
+}
+
+impl Balancer {
+    pub fn new(balance: &Balance, servers: &[Server]) -> Self {
+        match balance {
+            Balance::RoundRobin => Balancer::RoundRobin(AtomicUsize::new(0)),
+            Balance::Weighted => Balancer::Weighted(Mutex::new(vec![0; servers.len()])),
+            Balance::LeastConn => Balancer::LeastConn(AtomicUsize::new(0)),
+            Balance::RandomTwo => Balancer::RandomTwo(Random::new()),
+            Balance::Hash(_) => Balancer::Hash(ring(servers)),
+        }
+    }
+
+    /// `key` is the rendered key of `balance hash`, only servers which are `available` are
+    /// picked
+    pub fn pick(
+        &self,
+        servers: &[Server],
+        key: Option<&str>,
+        available: impl Fn(usize) -> bool,
+    ) -> Option<usize> {
+        let len = servers.len();
+        match self {
+            Balancer::RoundRobin(next) => {
+                let start = next.fetch_add(1, Ordering::Relaxed);
+                (0..len).map(|i| (start + i) % len).find(|i| available(*i))
+            }
+            Balancer::Weighted(current) => {
+                let mut current = current.lock().unwrap();
+                let mut total = 0;
+                let mut best = None;
+                for (i, server) in servers.iter().enumerate() {
+                    if !available(i) {
+                        continue;
+                    }
+                    current[i] += server.weight as i64;
+                    total += server.weight as i64;
+                    if best.is_none_or(|best| current[i] > current[best]) {
+                        best = Some(i);
+                    }
+                }
+                let best = best?;
+                current[best] -= total;
+                Some(best)
+            }
+            Balancer::LeastConn(start) => {
+                let start = start.fetch_add(1, Ordering::Relaxed);
+                (0..len)
+                    .map(|i| (start + i) % len)
+                    .filter(|i| available(*i))
+                    .reduce(|best, i| match less_busy(&servers[i], &servers[best]) {
+                        true => i,
+                        false => best,
+                    })
+            }
+            Balancer::RandomTwo(random) => {
+                let candidates = (0..len).filter(|i| available(*i)).collect::<Vec<_>>();
+                if candidates.len() < 2 {
+                    return candidates.first().copied();
+                }
+                let a = random.below(candidates.len());
+                // a different server than `a`
+                let b = (a + 1 + random.below(candidates.len() - 1)) % candidates.len();
+                let (a, b) = (candidates[a], candidates[b]);
+                match less_busy(&servers[b], &servers[a]) {
+                    true => Some(b),
+                    false => Some(a),
+                }
+            }
+            Balancer::Hash(ring) => {
+                let hash = hash(key.unwrap_or_default().as_bytes());
+                // the first point at or after the hash, wrapping around, whose server is
+                // available, so only the keys of unavailable servers move
+                let start = ring.partition_point(|(point, _)| *point < hash);
+                (0..ring.len())
+                    .map(|i| ring[(start + i) % ring.len()].1)
+                    .find(|i| available(*i))
+            }
+        }
+    }
+}
+
+/// Compares the pending requests relative to the weight of each server
+fn less_busy(a: &Server, b: &Server) -> bool {
+    (a.pending() as u64 * b.weight as u64) < (b.pending() as u64 * a.weight as u64)
+}
+
+fn ring(servers: &[Server]) -> Vec<(u64, usize)> {
+    let mut ring = vec![];
+    for (i, server) in servers.iter().enumerate() {
+        for point in 0..server.weight * POINTS_PER_WEIGHT {
+            ring.push((hash(format!("{}#{}", server.addr, point).as_bytes()), i));
+        }
+    }
+    ring.sort_unstable();
+    ring
+}
+
+/// FNV-1a followed by the finalizer of splitmix64, stable across restarts so keys keep going to
+/// the same server
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325_u64;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    mix(hash)
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// splitmix64, good enough to spread requests and shared without locking
+pub(super) struct Random(AtomicU64);
+
+impl Random {
+    fn new() -> Self {
+        Self(AtomicU64::new(RandomState::new().build_hasher().finish()))
+    }
+
+    /// Uniform enough below `bound` for the small numbers of servers in an upstream
+    fn below(&self, bound: usize) -> usize {
+        let state = self.0.fetch_add(0x9e3779b97f4a7c15, Ordering::Relaxed);
+        (mix(state) % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bolt_config::model::Text;
+    use pretty_assertions::assert_eq;
+
+    fn servers(weights: &[u32]) -> Vec<Server> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| Server::new(&format!("10.0.0.{}:80", i), *weight, None))
+            .collect()
+    }
+
+    fn picks(balance: Balance, servers: &[Server], n: usize) -> Vec<usize> {
+        let balancer = Balancer::new(&balance, servers);
+        (0..n)
+            .map(|_| balancer.pick(servers, None, |_| true).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let servers = servers(&[1, 5, 1]);
+        assert_eq!(
+            picks(Balance::RoundRobin, &servers, 6),
+            vec![0, 1, 2, 0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn weighted() {
+        // interleaved instead of five times in a row
+        let servers = servers(&[5, 1, 1]);
+        assert_eq!(
+            picks(Balance::Weighted, &servers, 7),
+            vec![0, 0, 1, 0, 2, 0, 0]
+        );
+    }
+
+    #[test]
+    fn least_conn() {
+        let servers = servers(&[1, 2, 1]);
+        servers[0].pending.store(2, Ordering::Relaxed);
+        servers[1].pending.store(3, Ordering::Relaxed);
+        servers[2].pending.store(2, Ordering::Relaxed);
+        // 3 pending with weight 2 is less busy than 2 with weight 1
+        assert_eq!(picks(Balance::LeastConn, &servers, 3), vec![1, 1, 1]);
+
+        servers[1].pending.store(4, Ordering::Relaxed);
+        // ties rotate
+        assert_eq!(picks(Balance::LeastConn, &servers, 3), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn random_two() {
+        let servers = servers(&[1, 1, 1]);
+        servers[1].pending.store(10, Ordering::Relaxed);
+        let picked = picks(Balance::RandomTwo, &servers, 300);
+        // the busy server only wins if it isn't drawn at all, which can't happen with two choices
+        assert!(!picked.contains(&1));
+        assert!(picked.contains(&0) && picked.contains(&2));
+
+        assert_eq!(picks(Balance::RandomTwo, &servers[..1], 3), vec![0; 3]);
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let balance = Balance::Hash(Text::Plain(String::new()));
+        let all = servers(&[1, 1, 1, 1]);
+        let balancer = Balancer::new(&balance, &all);
+        let keys = (0..1000).map(|i| format!("10.1.{}.{}", i / 256, i % 256));
+        let before = keys
+            .clone()
+            .map(|key| balancer.pick(&all, Some(&key), |_| true).unwrap())
+            .collect::<Vec<_>>();
+
+        // every server gets a fair share
+        for server in 0..4 {
+            let share = before.iter().filter(|s| **s == server).count();
+            assert!(
+                (150..350).contains(&share),
+                "server {} got {}",
+                server,
+                share
+            );
+        }
+
+        // removing a server only moves the keys it had, as does skipping it
+        let fewer = servers(&[1, 1, 1]);
+        let removed = Balancer::new(&balance, &fewer);
+        for (key, before) in keys.zip(before) {
+            let after = removed.pick(&fewer, Some(&key), |_| true).unwrap();
+            let skipped = balancer.pick(&all, Some(&key), |i| i != 3).unwrap();
+            if before != 3 {
+                assert_eq!(after, before);
+                assert_eq!(skipped, before);
+            }
+        }
+    }
+
+    #[test]
+    fn skip_unavailable() {
+        let servers = servers(&[1, 3, 1]);
+        let policies = [
+            Balance::RoundRobin,
+            Balance::Weighted,
+            Balance::LeastConn,
+            Balance::RandomTwo,
+            Balance::Hash(Text::Plain(String::new())),
+        ];
+        for balance in policies {
+            let balancer = Balancer::new(&balance, &servers);
+            for i in 0..20 {
+                let key = i.to_string();
+                let picked = balancer.pick(&servers, Some(&key), |i| i != 1);
+                assert!(
+                    matches!(picked, Some(0 | 2)),
+                    "{:?} picked {:?}",
+                    balance,
+                    picked
+                );
+                assert_eq!(balancer.pick(&servers, Some(&key), |_| false), None);
+            }
+        }
+    }
+}
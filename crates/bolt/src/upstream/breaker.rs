@@ -0,0 +1,249 @@
+//! State of the circuit breaker of a server, see [`crate::layers::upstream::breaker`] for the
+//! layer driving it
+
+use bolt_config::model::Breaker;
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub struct Circuit {
+    config: Option<Breaker>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    /// Requests pass, counted within the current window
+    Closed {
+        since: Instant,
+        requests: u32,
+        failures: u32,
+    },
+    /// No requests until the cooldown passed
+    Open { until: Instant },
+    /// A single trial request decides whether the circuit closes or opens again
+    HalfOpen { trial: bool },
+}
+
+/// How a call changed the circuit, for logging by the caller who knows the server
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Transition {
+    Opened,
+    HalfOpened,
+    Closed,
+}
+
+/// How a request was admitted, passed back along with its outcome
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Admission {
+    Regular,
+    /// The trial request of a half open circuit, the only one deciding whether it closes
+    Trial,
+}
+
+impl Circuit {
+    pub fn new(config: Option<Breaker>) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::Closed {
+                since: Instant::now(),
+                requests: 0,
+                failures: 0,
+            }),
+        }
+    }
+
+    /// Whether the balancer may pick the server, without taking the trial of a half open circuit
+    pub fn allows(&self) -> bool {
+        if self.config.is_none() {
+            return true;
+        }
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => true,
+            State::Open { until } => until <= Instant::now(),
+            State::HalfOpen { trial } => !trial,
+        }
+    }
+
+    /// Admits a request to the server, `Err` if the circuit is open or the trial is taken
+    pub fn acquire(&self) -> Result<(Admission, Option<Transition>), ()> {
+        if self.config.is_none() {
+            return Ok((Admission::Regular, None));
+        }
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => Ok((Admission::Regular, None)),
+            State::Open { until } if until <= Instant::now() => {
+                *state = State::HalfOpen { trial: true };
+                Ok((Admission::Trial, Some(Transition::HalfOpened)))
+            }
+            State::HalfOpen { trial: false } => {
+                *state = State::HalfOpen { trial: true };
+                Ok((Admission::Trial, None))
+            }
+            State::Open { .. } | State::HalfOpen { trial: true } => Err(()),
+        }
+    }
+
+    /// An admitted request ended without an outcome, the client went away before the response
+    pub fn release(&self, admission: Admission) {
+        let mut state = self.state.lock().unwrap();
+        if let (Admission::Trial, State::HalfOpen { trial: true }) = (admission, *state) {
+            *state = State::HalfOpen { trial: false };
+        }
+    }
+
+    /// Counts the outcome of an admitted request
+    pub fn record(&self, admission: Admission, failed: bool) -> Option<Transition> {
+        let config = self.config.as_ref()?;
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Closed {
+                since,
+                requests,
+                failures,
+            } => {
+                if now.duration_since(*since) >= config.window {
+                    (*since, *requests, *failures) = (now, 0, 0);
+                }
+                *requests += 1;
+                *failures += failed as u32;
+                let tripped = *requests >= config.min_requests
+                    && *failures as u64 * 100 >= *requests as u64 * config.ratio as u64;
+                if !tripped {
+                    return None;
+                }
+            }
+            // requests admitted before it opened
+            State::Open { .. } => return None,
+            State::HalfOpen { .. } if admission == Admission::Regular => return None,
+            State::HalfOpen { .. } if !failed => {
+                *state = State::Closed {
+                    since: now,
+                    requests: 0,
+                    failures: 0,
+                };
+                return Some(Transition::Closed);
+            }
+            State::HalfOpen { .. } => {}
+        }
+        *state = State::Open {
+            until: now + config.cooldown,
+        };
+        Some(Transition::Opened)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn circuit(cooldown: Duration) -> Circuit {
+        Circuit::new(Some(Breaker {
+            ratio: 50,
+            min_requests: 4,
+            window: Duration::from_secs(60),
+            cooldown,
+        }))
+    }
+
+    #[test]
+    fn opens_on_ratio() {
+        let circuit = circuit(Duration::from_secs(60));
+        for failed in [true, true, true] {
+            assert_eq!(circuit.acquire(), Ok((Admission::Regular, None)));
+            assert_eq!(circuit.record(Admission::Regular, failed), None);
+        }
+        // 4 of 4 requests failed
+        assert_eq!(
+            circuit.record(Admission::Regular, true),
+            Some(Transition::Opened)
+        );
+        assert!(!circuit.allows());
+        assert_eq!(circuit.acquire(), Err(()));
+    }
+
+    #[test]
+    fn half_opens_after_cooldown() {
+        let circuit = circuit(Duration::from_millis(20));
+        for failed in [false, true, false, true] {
+            circuit.record(Admission::Regular, failed);
+        }
+        assert!(!circuit.allows());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(circuit.allows());
+        assert_eq!(
+            circuit.acquire(),
+            Ok((Admission::Trial, Some(Transition::HalfOpened)))
+        );
+        // only a single trial
+        assert!(!circuit.allows());
+        assert_eq!(circuit.acquire(), Err(()));
+
+        assert_eq!(
+            circuit.record(Admission::Trial, true),
+            Some(Transition::Opened)
+        );
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(
+            circuit.acquire(),
+            Ok((Admission::Trial, Some(Transition::HalfOpened)))
+        );
+        // the trial was cancelled
+        circuit.release(Admission::Trial);
+        assert!(circuit.allows());
+        assert_eq!(circuit.acquire(), Ok((Admission::Trial, None)));
+        assert_eq!(
+            circuit.record(Admission::Trial, true),
+            Some(Transition::Opened)
+        );
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(
+            circuit.acquire(),
+            Ok((Admission::Trial, Some(Transition::HalfOpened)))
+        );
+        assert_eq!(
+            circuit.record(Admission::Trial, false),
+            Some(Transition::Closed)
+        );
+        assert_eq!(circuit.acquire(), Ok((Admission::Regular, None)));
+    }
+
+    #[test]
+    fn only_the_trial_decides() {
+        let circuit = circuit(Duration::from_millis(20));
+        for _ in 0..4 {
+            circuit.record(Admission::Regular, true);
+        }
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(
+            circuit.acquire(),
+            Ok((Admission::Trial, Some(Transition::HalfOpened)))
+        );
+
+        // slow requests admitted while the circuit was still closed
+        assert_eq!(circuit.record(Admission::Regular, false), None);
+        assert_eq!(circuit.record(Admission::Regular, true), None);
+        circuit.release(Admission::Regular);
+        assert!(!circuit.allows());
+        assert_eq!(circuit.acquire(), Err(()));
+
+        assert_eq!(
+            circuit.record(Admission::Trial, false),
+            Some(Transition::Closed)
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let circuit = Circuit::new(None);
+        for _ in 0..100 {
+            assert_eq!(circuit.record(Admission::Regular, true), None);
+        }
+        assert!(circuit.allows());
+        assert_eq!(circuit.acquire(), Ok((Admission::Regular, None)));
+    }
+}
@@ -0,0 +1,128 @@
+//! Connections to the servers of upstreams, over TCP or Unix domain sockets
+//!
+//! The pooled client only knows about uris, servers behind a Unix domain socket are addressed as
+//! `unix://<hex encoded path>` and sent `Host: localhost`. Over HTTP/2 that uri still ends up in
+//! `:scheme` and `:authority`.
+
+use crate::layers::raw::stream::Socket;
+use crate::util::PinResultFuture;
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, HeaderValue, HOST};
+use hyper::http::uri::{PathAndQuery, Scheme};
+use hyper::service::Service;
+use hyper::Uri;
+use std::error::Error;
+use std::fmt::Write;
+use std::io;
+use std::path::PathBuf;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::UnixStream;
+
+const UNIX_SCHEME: &str = "unix";
+
+/// Uri of `path_and_query` on the server at `addr`, `host:port` or `unix:<path>`
+pub fn uri(addr: &str, path_and_query: PathAndQuery) -> Result<Uri, hyper::http::Error> {
+    let builder = match addr.strip_prefix("unix:") {
+        Some(path) => Uri::builder().scheme(UNIX_SCHEME).authority(hex(path)),
+        None => Uri::builder().scheme(Scheme::HTTP).authority(addr),
+    };
+    builder.path_and_query(path_and_query).build()
+}
+
+/// Sets `Host` for the server at `addr`, which is left to the client for TCP
+pub fn set_host(headers: &mut HeaderMap, addr: &str) {
+    match addr.starts_with("unix:") {
+        true => headers.insert(HOST, HeaderValue::from_static("localhost")),
+        false => headers.remove(HOST),
+    };
+}
+
+fn hex(path: &str) -> String {
+    path.bytes().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{:02x}", b);
+        hex
+    })
+}
+
+fn unhex(hex: &str) -> Option<PathBuf> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[derive(Clone)]
+pub struct Connector {
+    http: HttpConnector,
+    connect_timeout: Option<Duration>,
+}
+
+impl Connector {
+    pub fn new(connect_timeout: Option<Duration>) -> Self {
+        let mut http = HttpConnector::new();
+        http.set_nodelay(true);
+        http.set_connect_timeout(connect_timeout);
+        Self {
+            http,
+            connect_timeout,
+        }
+    }
+}
+
+impl Service<Uri> for Connector {
+    type Response = Socket;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = PinResultFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        if uri.scheme_str() != Some(UNIX_SCHEME) {
+            let connecting = self.http.call(uri);
+            return Box::pin(async move { Ok(Socket::Tcp(connecting.await?)) });
+        }
+
+        let connect_timeout = self.connect_timeout;
+        Box::pin(async move {
+            let path = uri.host().and_then(unhex).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "invalid socket path")
+            })?;
+            let connecting = UnixStream::connect(&path);
+            let stream = match connect_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, connecting)
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timeout"))?,
+                None => connecting.await,
+            };
+            let stream = stream.map_err(|err| {
+                io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+            })?;
+            Ok(Socket::Unix(stream))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn unix_uris() {
+        let unix = uri("unix:/run/app.sock", PathAndQuery::from_static("/a?b")).unwrap();
+        assert_eq!(unix.scheme_str(), Some(UNIX_SCHEME));
+        assert_eq!(unix.path_and_query().unwrap(), "/a?b");
+        assert_eq!(
+            unix.host().and_then(unhex),
+            Some(PathBuf::from("/run/app.sock"))
+        );
+
+        let tcp = uri("[::1]:80", PathAndQuery::from_static("/")).unwrap();
+        assert_eq!(tcp, "http://[::1]:80/");
+        assert_eq!(unhex("2f6"), None);
+    }
+}
@@ -0,0 +1,285 @@
+//! Active probes of `health_check` and passive ejection of servers failing requests
+
+use super::connect::{self, Connector};
+use super::{Server, Upstream};
+use bolt_config::model::HealthCheck;
+use hyper::{Body, Client, Request};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+/// Ejections in a row double the back-off up to this many times
+const MAX_BACKOFF_DOUBLINGS: u32 = 6;
+
+/// Health of a single server, servers start out healthy
+pub(super) struct Health {
+    /// Cleared after `health_check.fall` failed probes in a row, set again after `health_check.rise`
+    up: AtomicBool,
+    /// Set while ejected, the deadline is only checked if it's set
+    ejected: AtomicBool,
+    ejected_until: Mutex<Option<Instant>>,
+    /// Failed requests since the last successful one
+    failures: AtomicU32,
+    /// Ejections since the last successful request
+    ejections: AtomicU32,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            up: AtomicBool::new(true),
+            ejected: AtomicBool::new(false),
+            ejected_until: Mutex::new(None),
+            failures: AtomicU32::new(0),
+            ejections: AtomicU32::new(0),
+        }
+    }
+}
+
+impl Upstream {
+    /// Whether the balancer may pick `server`, ejected servers return once their back-off passed
+    pub(super) fn available(&self, server: &Server) -> bool {
+        let health = &server.health;
+        if !health.up.load(Ordering::Relaxed) || !server.circuit.allows() {
+            return false;
+        }
+        if !health.ejected.load(Ordering::Acquire) {
+            return true;
+        }
+
+        let mut until = health.ejected_until.lock().unwrap();
+        match *until {
+            Some(deadline) if deadline > Instant::now() => false,
+            _ => {
+                if until.take().is_some() {
+                    health.ejected.store(false, Ordering::Release);
+                    info!(
+                        "Returning {} of upstream {} to rotation after its back-off",
+                        server.addr, self.name
+                    );
+                }
+                true
+            }
+        }
+    }
+
+    /// Counts `5xx` responses and connect failures, enough of them in a row eject the server
+    pub(super) fn record(&self, server: &Server, failed: bool) {
+        let health = &server.health;
+        if !failed {
+            health.failures.store(0, Ordering::Relaxed);
+            health.ejections.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        let failures = health.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.eject.after == 0 || failures < self.eject.after {
+            return;
+        }
+        let mut until = health.ejected_until.lock().unwrap();
+        // requests still in flight while it was ejected
+        if until.is_some() {
+            return;
+        }
+        health.failures.store(0, Ordering::Relaxed);
+        let ejections = health.ejections.fetch_add(1, Ordering::Relaxed);
+        let backoff = self.eject.backoff * 2u32.pow(ejections.min(MAX_BACKOFF_DOUBLINGS));
+        *until = Some(Instant::now() + backoff);
+        health.ejected.store(true, Ordering::Release);
+        warn!(
+            "Ejecting {} of upstream {} for {:?} after {} failed requests in a row",
+            server.addr, self.name, backoff, failures
+        );
+    }
+}
+
+/// Probes every server of the upstream in the background, if it has a `health_check`
+pub fn spawn_checks(upstream: &Arc<Upstream>) {
+    if upstream.health_check.is_none() {
+        return;
+    }
+    // fresh connections, a pooled one could hide that new ones are refused
+    let client = Client::builder()
+        .pool_max_idle_per_host(0)
+        .build(Connector::new(None));
+
+    for index in 0..upstream.servers.len() {
+        tokio::spawn(check(upstream.clone(), index, client.clone()));
+    }
+}
+
+async fn check(upstream: Arc<Upstream>, index: usize, client: Client<Connector>) {
+    let check = upstream
+        .health_check
+        .as_ref()
+        .expect("only spawned with a health check");
+    let server = &upstream.servers[index];
+    let health = &server.health;
+
+    let mut interval = tokio::time::interval(check.interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // probes in a row with the same result
+    let mut streak = 0;
+    let mut last = true;
+
+    loop {
+        interval.tick().await;
+        let healthy = probe(&client, &server.addr, check).await;
+        streak = if healthy == last { streak + 1 } else { 1 };
+        last = healthy;
+
+        let up = health.up.load(Ordering::Relaxed);
+        if up && !healthy && streak == check.fall {
+            health.up.store(false, Ordering::Relaxed);
+            warn!(
+                "Taking {} of upstream {} out of rotation after {} failed health checks",
+                server.addr, upstream.name, streak
+            );
+        } else if !up && healthy && streak == check.rise {
+            health.up.store(true, Ordering::Relaxed);
+            info!(
+                "Returning {} of upstream {} to rotation after {} passed health checks",
+                server.addr, upstream.name, streak
+            );
+        }
+    }
+}
+
+/// `GET`s the path of the check, which has to respond within the interval
+async fn probe(client: &Client<Connector>, addr: &str, check: &HealthCheck) -> bool {
+    let request = check
+        .path
+        .parse()
+        .map_err(hyper::http::Error::from)
+        .and_then(|path| connect::uri(addr, path))
+        .and_then(|uri| {
+            Request::get(uri)
+                .header("user-agent", "bolt-health-check")
+                .body(Body::empty())
+        });
+    let mut request = match request {
+        Ok(request) => request,
+        Err(err) => {
+            debug!("Unable to probe {}: {}", addr, err);
+            return false;
+        }
+    };
+    connect::set_host(request.headers_mut(), addr);
+
+    match tokio::time::timeout(check.interval, client.request(request)).await {
+        Ok(Ok(response)) => {
+            let status = response.status().as_u16();
+            debug!("Health check of {} responded with {}", addr, status);
+            check.status.contains(&status)
+        }
+        Ok(Err(err)) => {
+            debug!("Health check of {} failed: {}", addr, err);
+            false
+        }
+        Err(_) => {
+            debug!("Health check of {} timed out", addr);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bolt_config::ast::Span;
+    use bolt_config::model::{self, Balance, Eject, Retry, UpstreamServer};
+    use bolt_config::source::FileId;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn upstream(eject: Eject) -> Arc<Upstream> {
+        Arc::new(Upstream::new(&model::Upstream {
+            name: "app".to_string(),
+            servers: ["a:80", "b:80"]
+                .iter()
+                .map(|addr| UpstreamServer {
+                    addr: addr.to_string(),
+                    weight: 1,
+                })
+                .collect(),
+            balance: Balance::RoundRobin,
+            health_check: None,
+            eject,
+            retry: Retry::default(),
+            breaker: None,
+            span: Span {
+                file: FileId(0),
+                offset: 0,
+                len: 0,
+                line: 1,
+                column: 1,
+            },
+        }))
+    }
+
+    fn picks(upstream: &Arc<Upstream>, n: usize) -> Vec<String> {
+        (0..n)
+            .map(|_| upstream.pick(None).server().addr.clone())
+            .collect()
+    }
+
+    #[test]
+    fn ejection() {
+        let upstream = upstream(Eject {
+            after: 2,
+            backoff: Duration::from_millis(50),
+        });
+        let a = &upstream.servers[0];
+
+        upstream.record(a, true);
+        upstream.record(a, false);
+        upstream.record(a, true);
+        // not in a row
+        assert_eq!(picks(&upstream, 2), ["a:80", "b:80"]);
+
+        upstream.record(a, true);
+        assert_eq!(picks(&upstream, 2), ["b:80", "b:80"]);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(picks(&upstream, 2), ["a:80", "b:80"]);
+
+        // ejected again before a request succeeded, for twice as long
+        upstream.record(a, true);
+        upstream.record(a, true);
+        let until = a.health.ejected_until.lock().unwrap().unwrap();
+        assert!(until > Instant::now() + Duration::from_millis(60));
+    }
+
+    #[test]
+    fn unavailable_servers() {
+        let upstream = upstream(Eject::default());
+        upstream.servers[1]
+            .health
+            .up
+            .store(false, Ordering::Relaxed);
+        assert_eq!(picks(&upstream, 2), ["a:80", "a:80"]);
+
+        // nothing healthy left, so all of them take turns
+        upstream.servers[0]
+            .health
+            .up
+            .store(false, Ordering::Relaxed);
+        let mut picked = picks(&upstream, 2);
+        picked.sort();
+        assert_eq!(picked, ["a:80", "b:80"]);
+    }
+
+    #[test]
+    fn never_eject() {
+        let upstream = upstream(Eject {
+            after: 0,
+            backoff: Duration::from_secs(10),
+        });
+        for _ in 0..10 {
+            upstream.record(&upstream.servers[0], true);
+        }
+        assert_eq!(picks(&upstream, 2), ["a:80", "b:80"]);
+    }
+}
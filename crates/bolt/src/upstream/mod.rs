@@ -0,0 +1,157 @@
+//! Groups of servers `proxy_pass` balances requests over
+
+use bolt_config::model::{self, Balance, Eject, HealthCheck, Retry, Text};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tower::retry::budget::Budget;
+
+mod balance;
+mod breaker;
+pub mod connect;
+mod health;
+
+use balance::Balancer;
+pub use breaker::{Admission, Circuit, Transition};
+pub use health::spawn_checks;
+use health::Health;
+
+pub struct Upstream {
+    /// Name of the `upstream` block, or the address of its single server
+    pub name: String,
+    servers: Vec<Server>,
+    balancer: Balancer,
+    /// Rendered per request for `balance hash`
+    key: Option<Text>,
+    health_check: Option<HealthCheck>,
+    eject: Eject,
+    pub retry: Retry,
+    /// Shared by all requests to the upstream, limits retries to `retry.budget`
+    pub budget: Arc<Budget>,
+}
+
+pub struct Server {
+    /// `host:port`
+    pub addr: String,
+    pub weight: u32,
+    /// Requests sent to the server which are still waiting for the response head
+    pending: AtomicUsize,
+    health: Health,
+    pub circuit: Circuit,
+}
+
+/// The server chosen for one request, counted as pending until dropped
+pub struct Pick {
+    upstream: Arc<Upstream>,
+    index: usize,
+}
+
+impl Upstream {
+    pub fn new(upstream: &model::Upstream) -> Self {
+        let servers = upstream
+            .servers
+            .iter()
+            .map(|server| Server::new(&server.addr, server.weight, upstream.breaker))
+            .collect::<Vec<_>>();
+        let key = match &upstream.balance {
+            Balance::Hash(key) => Some(key.clone()),
+            _ => None,
+        };
+        Self {
+            name: upstream.name.clone(),
+            balancer: Balancer::new(&upstream.balance, &servers),
+            servers,
+            key,
+            health_check: upstream.health_check.clone(),
+            eject: upstream.eject,
+            retry: upstream.retry,
+            budget: budget(upstream.retry),
+        }
+    }
+
+    /// `proxy_pass` straight to a server instead of an `upstream` block
+    pub fn single(addr: &str) -> Self {
+        let servers = vec![Server::new(addr, 1, None)];
+        Self {
+            name: addr.to_string(),
+            balancer: Balancer::new(&Balance::RoundRobin, &servers),
+            servers,
+            key: None,
+            health_check: None,
+            eject: Eject::default(),
+            retry: Retry::default(),
+            budget: budget(Retry::default()),
+        }
+    }
+
+    /// Template of `balance hash`, its rendering has to be passed to [`Upstream::pick`]
+    pub fn key(&self) -> Option<&Text> {
+        self.key.as_ref()
+    }
+
+    /// Picks one of the healthy servers, or any of them if none is healthy since failing is all
+    /// that's left otherwise
+    pub fn pick(self: &Arc<Self>, key: Option<&str>) -> Pick {
+        let healthy = self.servers.iter().any(|server| self.available(server));
+        let index = self
+            .balancer
+            .pick(&self.servers, key, |i| {
+                !healthy || self.available(&self.servers[i])
+            })
+            // the last healthy server was taken out in the meantime
+            .or_else(|| self.balancer.pick(&self.servers, key, |_| true))
+            .expect("upstreams have at least one server");
+        self.servers[index].pending.fetch_add(1, Ordering::Relaxed);
+        Pick {
+            upstream: self.clone(),
+            index,
+        }
+    }
+}
+
+/// Retries may add `retry.budget` percent to the requests of the last ten seconds, with ten per
+/// second allowed regardless so rarely used upstreams can retry at all
+fn budget(retry: Retry) -> Arc<Budget> {
+    Arc::new(Budget::new(
+        Duration::from_secs(10),
+        10,
+        retry.budget as f32 / 100.0,
+    ))
+}
+
+impl Server {
+    fn new(addr: &str, weight: u32, breaker: Option<model::Breaker>) -> Self {
+        Self {
+            addr: addr.to_string(),
+            weight,
+            pending: AtomicUsize::new(0),
+            health: Health::new(),
+            circuit: Circuit::new(breaker),
+        }
+    }
+
+    fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+impl Pick {
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.upstream.servers[self.index]
+    }
+
+    /// Reports whether the request failed, with a `5xx` or without reaching the server
+    pub fn record(&self, failed: bool) {
+        self.upstream.record(self.server(), failed);
+    }
+}
+
+impl Drop for Pick {
+    fn drop(&mut self) {
+        self.server().pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,182 @@
+//! Syntax tree of the bolt configuration language.
+//!
+//! A config file is a flat list of directives, each directive being a name followed by
+//! arguments and an optional block of nested directives:
+//! ```not_rust
+//! listen '0.0.0.0' 8443 tls h2
+//! location ^ '/hi' {
+//!     return 200 "Hi $relative_uri!"
+//! }
+//! ```
+
+use crate::source::FileId;
+use std::fmt::{Display, Formatter};
+
+/// Location of a node inside of a source file, lines and columns are 1 based.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Directive {
+    pub name: Spanned<String>,
+    pub args: Vec<Spanned<Value>>,
+    pub block: Option<Vec<Directive>>,
+    /// Covers the name and all arguments, but not the block
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// `'string'`, taken literally except for `\'`
+    String(String),
+    /// `"string"`, may contain `$variables`
+    FormatString(String),
+    /// `10`, `-1`, `10M`, `1d`
+    Integer {
+        value: i64,
+        suffix: Option<String>,
+    },
+    /// `1.5`, `-1.1`, `0.5s`
+    Float {
+        value: f64,
+        suffix: Option<String>,
+    },
+    /// Bare words like `tls`, `h2` or `_`
+    Ident(String),
+    Operator(Operator),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Operator {
+    /// `=`
+    Equals,
+    /// `~`
+    Tilde,
+    /// `^`
+    Caret,
+    /// `@`
+    At,
+}
+
+impl Span {
+    /// Empty span at the very beginning of a file
+    pub fn start(file: FileId) -> Self {
+        Self {
+            file,
+            offset: 0,
+            len: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+impl Value {
+    /// Short human readable description, used in error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::FormatString(_) => "format string",
+            Value::Integer { .. } => "integer",
+            Value::Float { .. } => "number",
+            Value::Ident(_) => "identifier",
+            Value::Operator(_) => "operator",
+        }
+    }
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Equals => "=",
+            Operator::Tilde => "~",
+            Operator::Caret => "^",
+            Operator::At => "@",
+        }
+    }
+}
+
+/// Renders directives back into config syntax
+pub struct Printer<'d>(pub &'d [Directive]);
+
+impl Display for Printer<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fn write(
+            f: &mut Formatter<'_>,
+            directives: &[Directive],
+            depth: usize,
+        ) -> std::fmt::Result {
+            for directive in directives {
+                write!(
+                    f,
+                    "{:indent$}{}",
+                    "",
+                    directive.name.node,
+                    indent = depth * 4
+                )?;
+                for arg in &directive.args {
+                    write!(f, " {}", arg.node)?;
+                }
+                match &directive.block {
+                    Some(block) => {
+                        writeln!(f, " {{")?;
+                        write(f, block, depth + 1)?;
+                        writeln!(f, "{:indent$}}}", "", indent = depth * 4)?;
+                    }
+                    None => writeln!(f)?,
+                }
+            }
+            Ok(())
+        }
+
+        write(f, self.0, 0)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(str) => write!(f, "'{}'", str.replace('\'', "\\'")),
+            Value::FormatString(str) => {
+                f.write_str("\"")?;
+                for c in str.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Value::Integer { value, suffix } => {
+                write!(f, "{}{}", value, suffix.as_deref().unwrap_or_default())
+            }
+            // Debug formatting keeps the decimal point for whole numbers
+            Value::Float { value, suffix } => {
+                write!(f, "{:?}{}", value, suffix.as_deref().unwrap_or_default())
+            }
+            Value::Ident(ident) => f.write_str(ident),
+            Value::Operator(op) => f.write_str(op.as_str()),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::error::Invalid;
+use crate::source::SourceMap;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+/// A config error resolved against its source file, renders similar to rustc errors:
+/// ```not_rust
+/// error: unknown directive `tls.sesion.timeout`
+///  --> config/snippets/tls.snip:1:1
+///   |
+/// 1 | tls.sesion.timeout 1d
+///   | ^^^^^^^^^^^^^^^^^^
+///   = help: did you mean `tls.session.timeout`?
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    /// Width of the highlighted token in characters
+    pub width: usize,
+    /// The full source line the error points into
+    pub source_line: String,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(err: Invalid, sources: &SourceMap) -> Self {
+        let file = sources.get(err.span.file);
+        let offset = err.span.offset.min(file.text.len());
+        let line_start = file.text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let source_line = file.text[line_start..]
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        // Highlights may not run past the end of the line
+        let end = (offset + err.span.len).min(line_start + source_line.len());
+        let width = file
+            .text
+            .get(offset..end)
+            .map_or(0, |token| token.chars().count())
+            .max(1);
+
+        Self {
+            path: file.path.clone(),
+            line: err.span.line,
+            column: err.span.column,
+            width,
+            source_line,
+            message: err.message,
+            help: err.help,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let gutter = self.line.to_string().len();
+
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(
+            f,
+            "{:gutter$}--> {}:{}:{}",
+            "",
+            self.path.display(),
+            self.line,
+            self.column
+        )?;
+        writeln!(f, "{:gutter$} |", "")?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        write!(
+            f,
+            "{:gutter$} | {:indent$}{}",
+            "",
+            "",
+            "^".repeat(self.width),
+            indent = self.column - 1
+        )?;
+        if let Some(help) = &self.help {
+            write!(f, "\n{:gutter$} = help: {}", "", help)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Span;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn render() {
+        let mut sources = SourceMap::default();
+        let file = sources.add(
+            PathBuf::from("sites/example.conf"),
+            "site _\n\ntls.sesion.timeout 1d\n".to_string(),
+        );
+        let err = Invalid::new(
+            Span {
+                file,
+                offset: 8,
+                len: 18,
+                line: 3,
+                column: 1,
+            },
+            "unknown directive `tls.sesion.timeout`",
+        )
+        .with_help("did you mean `tls.session.timeout`?");
+
+        assert_eq!(
+            Diagnostic::new(err, &sources).to_string(),
+            "error: unknown directive `tls.sesion.timeout`
+ --> sites/example.conf:3:1
+  |
+3 | tls.sesion.timeout 1d
+  | ^^^^^^^^^^^^^^^^^^
+  = help: did you mean `tls.session.timeout`?"
+        );
+    }
+
+    #[test]
+    fn render_end_of_line() {
+        let mut sources = SourceMap::default();
+        let file = sources.add(PathBuf::from("a.conf"), "listen 8443".to_string());
+        let err = Invalid::new(
+            Span {
+                file,
+                offset: 11,
+                len: 1,
+                line: 1,
+                column: 12,
+            },
+            "`listen` expects 2 to 4 arguments, found 1",
+        );
+
+        assert_eq!(
+            Diagnostic::new(err, &sources).to_string(),
+            "error: `listen` expects 2 to 4 arguments, found 1
+ --> a.conf:1:12
+  |
+1 | listen 8443
+  |            ^"
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+use crate::parse::ParseError;
+use std::path::PathBuf;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Unable to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{0}")]
+    Invalid(Box<Diagnostic>),
+}
+
+/// A syntactic or semantic error which still has to be resolved into a [`Diagnostic`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invalid {
+    pub span: Span,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl Invalid {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl From<ParseError> for Invalid {
+    fn from(err: ParseError) -> Self {
+        Invalid::new(err.span, err.to_string())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::ast::{Directive, Value};
+use crate::diagnostic::Diagnostic;
+use crate::error::{Error, Invalid};
+use crate::model::{domain_router, Config, Global, Site};
+use crate::parse::parse;
+use crate::schema::{self, Context};
+use crate::source::{FileId, SourceMap};
+use crate::{ConfigProvider, VHost, VHostsProvider};
+use bolt_router::DomainRouter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Reads the configuration from a main config file (usually `bolt.conf`) and the
+/// `sites` and `snips` directories it points to.
+pub struct FileConfigProvider {
+    config: Arc<Config>,
+}
+
+struct FileVHosts {
+    sites: Vec<Arc<Site>>,
+}
+
+#[derive(Default)]
+struct Loader {
+    sources: SourceMap,
+}
+
+impl FileConfigProvider {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let config = Loader::default().load(path.as_ref())?;
+
+        Ok(Self {
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+}
+
+impl ConfigProvider for FileConfigProvider {
+    fn life_reloads(&self) -> bool {
+        false
+    }
+
+    fn hosts(&self) -> Box<dyn VHostsProvider> {
+        Box::new(FileVHosts {
+            sites: self.config.sites.clone(),
+        })
+    }
+}
+
+impl VHostsProvider for FileVHosts {
+    fn vhosts(&self) -> DomainRouter {
+        domain_router(self.sites.iter().map(|site| &**site))
+            .expect("site patterns are validated while loading")
+    }
+
+    fn default(&self) -> Option<Box<dyn VHost>> {
+        self.sites
+            .iter()
+            .find(|site| site.is_default())
+            .map(|site| Box::new(site.clone()) as Box<dyn VHost>)
+    }
+}
+
+impl VHost for Arc<Site> {
+    fn site(&self) -> &Site {
+        self
+    }
+}
+
+impl Loader {
+    fn load(&mut self, path: &Path) -> Result<Config, Error> {
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+        let (_, directives) = self.read(path)?;
+        schema::validate(&directives, Context::Global).map_err(|err| self.error(err))?;
+        let global = Global::from_directives(&directives, base).map_err(|err| self.error(err))?;
+
+        let mut sites = vec![];
+        for path in site_files(&global.sites_dir)? {
+            let (file, directives) = self.read(&path)?;
+            let directives = self.expand(directives, &global.snippets_dir, &mut vec![])?;
+            schema::validate(&directives, Context::Site).map_err(|err| self.error(err))?;
+            let site =
+                Site::from_directives(&directives, file, &path).map_err(|err| self.error(err))?;
+            sites.push(Arc::new(site));
+        }
+
+        Ok(Config {
+            path: path.to_path_buf(),
+            sites_dir: global.sites_dir,
+            snippets_dir: global.snippets_dir,
+            cache: global.cache,
+            compression: global.compression,
+            types: global.types,
+            upstreams: global.upstreams,
+            sites,
+        })
+    }
+
+    fn read(&mut self, path: &Path) -> Result<(FileId, Vec<Directive>), Error> {
+        let text = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let file = self.sources.add(path.to_path_buf(), text);
+
+        let directives =
+            parse(file, &self.sources.get(file).text).map_err(|err| self.error(err.into()))?;
+
+        Ok((file, directives))
+    }
+
+    /// Replaces all `snip <name>` directives with the contents of `<name>.snip`
+    fn expand(
+        &mut self,
+        directives: Vec<Directive>,
+        dir: &Path,
+        stack: &mut Vec<String>,
+    ) -> Result<Vec<Directive>, Error> {
+        let mut expanded = Vec::with_capacity(directives.len());
+
+        for mut directive in directives {
+            if directive.name.node != "snip" {
+                if let Some(block) = directive.block.take() {
+                    directive.block = Some(self.expand(block, dir, stack)?);
+                }
+                expanded.push(directive);
+                continue;
+            }
+
+            let name = match directive.args.as_slice() {
+                [arg] => match &arg.node {
+                    Value::Ident(name) | Value::String(name) => name.clone(),
+                    other => {
+                        return Err(self.error(Invalid::new(
+                            arg.span,
+                            format!("`snip` expected a snippet name, found {}", other.kind()),
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(self.error(Invalid::new(
+                        directive.span,
+                        "`snip` expects a snippet name",
+                    )))
+                }
+            };
+
+            if stack.contains(&name) {
+                return Err(self.error(Invalid::new(
+                    directive.span,
+                    format!("snippet `{}` includes itself", name),
+                )));
+            }
+
+            let path = dir.join(format!("{}.snip", name));
+            if !path.is_file() {
+                return Err(self.error(Invalid::new(
+                    directive.args[0].span,
+                    format!(
+                        "unknown snippet `{}`, {} does not exist",
+                        name,
+                        path.display()
+                    ),
+                )));
+            }
+
+            let (_, snippet) = self.read(&path)?;
+
+            stack.push(name);
+            expanded.extend(self.expand(snippet, dir, stack)?);
+            stack.pop();
+        }
+
+        Ok(expanded)
+    }
+
+    fn error(&self, err: Invalid) -> Error {
+        Error::Invalid(Box::new(Diagnostic::new(err, &self.sources)))
+    }
+}
+
+/// All `*.conf` files of the sites directory, sorted by name
+fn site_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let io_err = |source| Error::Io {
+        path: dir.to_path_buf(),
+        source,
+    };
+
+    let mut files = std::fs::read_dir(dir)
+        .map_err(io_err)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_err)?;
+    files.retain(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "conf"));
+    files.sort();
+
+    Ok(files)
+}
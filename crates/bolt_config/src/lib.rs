@@ -1,5 +1,19 @@
+pub mod ast;
+mod diagnostic;
+mod error;
+mod file;
+pub mod model;
+pub mod parse;
+pub mod schema;
+pub mod source;
+pub mod template;
+
 use bolt_router::DomainRouter;
-use std::collections::HashMap;
+
+pub use diagnostic::Diagnostic;
+pub use error::{Error, Invalid};
+pub use file::FileConfigProvider;
+pub use model::{Config, Site};
 
 pub trait ConfigProvider: 'static + Send + Sync {
     fn life_reloads(&self) -> bool;
@@ -12,4 +26,6 @@ pub trait VHostsProvider: 'static + Send + Sync {
     fn default(&self) -> Option<Box<dyn VHost>>;
 }
 
-pub trait VHost: 'static + Send + Sync {}
+pub trait VHost: 'static + Send + Sync {
+    fn site(&self) -> &Site;
+}
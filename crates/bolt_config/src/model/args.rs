@@ -0,0 +1,99 @@
+use crate::ast::{Directive, Operator, Span, Spanned, Value};
+use crate::error::Invalid;
+
+/// Cursor over the arguments of a directive which produces readable errors
+pub(crate) struct Args<'d> {
+    directive: &'d Directive,
+    index: usize,
+}
+
+impl<'d> Args<'d> {
+    pub fn new(directive: &'d Directive) -> Self {
+        Self {
+            directive,
+            index: 0,
+        }
+    }
+
+    pub fn name(&self) -> &'d str {
+        &self.directive.name.node
+    }
+
+    pub fn peek(&self) -> Option<&'d Spanned<Value>> {
+        self.directive.args.get(self.index)
+    }
+
+    pub fn next(&mut self, expected: &str) -> Result<&'d Spanned<Value>, Invalid> {
+        let arg = self.directive.args.get(self.index).ok_or_else(|| {
+            let span = self.directive.span;
+            Invalid::new(
+                Span {
+                    offset: span.offset + span.len,
+                    len: 0,
+                    column: span.column + span.len,
+                    ..span
+                },
+                format!("`{}` is missing {}", self.name(), expected),
+            )
+        })?;
+        self.index += 1;
+        Ok(arg)
+    }
+
+    pub fn string(&mut self, expected: &str) -> Result<Spanned<&'d str>, Invalid> {
+        let arg = self.next(expected)?;
+        match &arg.node {
+            Value::String(str) => Ok(Spanned::new(str.as_str(), arg.span)),
+            other => Err(self.mismatch(arg.span, expected, other)),
+        }
+    }
+
+    pub fn integer(&mut self, expected: &str) -> Result<Spanned<(i64, Option<&'d str>)>, Invalid> {
+        let arg = self.next(expected)?;
+        match &arg.node {
+            Value::Integer { value, suffix } => {
+                Ok(Spanned::new((*value, suffix.as_deref()), arg.span))
+            }
+            other => Err(self.mismatch(arg.span, expected, other)),
+        }
+    }
+
+    pub fn ident(&mut self, expected: &str) -> Result<Spanned<&'d str>, Invalid> {
+        let arg = self.next(expected)?;
+        match &arg.node {
+            Value::Ident(ident) => Ok(Spanned::new(ident.as_str(), arg.span)),
+            other => Err(self.mismatch(arg.span, expected, other)),
+        }
+    }
+
+    pub fn operator(&mut self, expected: &str) -> Result<Spanned<Operator>, Invalid> {
+        let arg = self.next(expected)?;
+        match &arg.node {
+            Value::Operator(op) => Ok(Spanned::new(*op, arg.span)),
+            other => Err(self.mismatch(arg.span, expected, other)),
+        }
+    }
+
+    /// Makes sure all arguments were consumed
+    pub fn finish(self) -> Result<(), Invalid> {
+        match self.peek() {
+            Some(arg) => Err(Invalid::new(
+                arg.span,
+                format!("unexpected argument for `{}`", self.name()),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn mismatch(&self, span: Span, expected: &str, found: &Value) -> Invalid {
+        Invalid::new(
+            span,
+            format!(
+                "`{}` expected {}, found {}",
+                self.name(),
+                expected,
+                found.kind()
+            ),
+        )
+    }
+}
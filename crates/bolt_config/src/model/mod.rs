@@ -0,0 +1,1572 @@
+//! Typed representation of a loaded configuration.
+
+use crate::ast::{Directive, Operator, Span, Spanned, Value};
+use crate::error::Invalid;
+use crate::schema;
+use crate::source::FileId;
+use crate::template::{self, Template, TemplateError, Variables};
+use args::Args;
+use bolt_router::{DomainRouter, Slot};
+use bolt_url::UrlPath;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{AddrParseError, IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod args;
+mod units;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Path of the main config file
+    pub path: PathBuf,
+    pub sites_dir: PathBuf,
+    pub snippets_dir: PathBuf,
+    pub cache: Cache,
+    pub compression: Compression,
+    pub types: Types,
+    /// `upstream` blocks of the main config file, available to all sites
+    pub upstreams: Vec<Upstream>,
+    pub sites: Vec<Arc<Site>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cache {
+    pub mode: Option<String>,
+    pub size: Option<CacheSize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheSize {
+    pub limit: String,
+    pub bytes: u64,
+}
+
+/// `types { ... }` and `default_type`, applied over the built in types
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Types {
+    /// Lowercase extensions without the dot, mapped to their mime type
+    pub extensions: HashMap<String, String>,
+    /// `default_type`, for files without a known extension
+    pub default: Option<String>,
+}
+
+/// On the fly compression of responses
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compression {
+    /// `compress`, in order of preference, empty if responses aren't compressed
+    pub encodings: Vec<Encoding>,
+    /// `compress.types`, mime types without parameters
+    pub types: Vec<String>,
+    /// `compress.min_size`, responses known to be smaller are sent as is
+    pub min_size: u64,
+    /// `compress.level`, clamped to the levels each encoding supports
+    pub level: Option<u32>,
+}
+
+impl Compression {
+    pub const DEFAULT_TYPES: &'static [&'static str] = &[
+        "text/html",
+        "text/css",
+        "text/plain",
+        "text/javascript",
+        "application/javascript",
+        "application/json",
+        "application/xml",
+        "image/svg+xml",
+    ];
+    pub const DEFAULT_MIN_SIZE: u64 = 256;
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            encodings: vec![],
+            types: Self::DEFAULT_TYPES.iter().map(|t| t.to_string()).collect(),
+            min_size: Self::DEFAULT_MIN_SIZE,
+            level: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Site {
+    /// File the site was loaded from
+    pub path: PathBuf,
+    pub listen: Vec<Listen>,
+    pub names: Vec<Spanned<SiteName>>,
+    pub tls: Option<Tls>,
+    pub headers: Vec<Header>,
+    /// `root`, used by locations without `root` or `alias` of their own
+    pub root: Option<PathBuf>,
+    /// `index`, used by locations without `index` of their own
+    pub index: Option<Vec<String>>,
+    /// `precompressed`, used by locations without `precompressed` of their own
+    pub precompressed: Option<Vec<Encoding>>,
+    /// `autoindex`, used by locations without `autoindex` of their own
+    pub autoindex: Option<Autoindex>,
+    /// `types` and `default_type`, override those of the main config file
+    pub types: Types,
+    /// `proxy_*_timeout`, used by locations without timeouts of their own
+    pub proxy_timeouts: ProxyTimeouts,
+    /// `upstream` blocks only available to this site, they shadow those of the main config file
+    pub upstreams: Vec<Upstream>,
+    pub locations: Vec<Location>,
+    /// The directives the site was built from, with all snippets expanded
+    pub directives: Vec<Directive>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Listen {
+    pub addr: ListenAddr,
+    pub tls: bool,
+    pub h2: bool,
+}
+
+/// `listen '<address>' <port>` or `listen 'unix:<path>'`
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// Path of a Unix domain socket
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => addr.fmt(f),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+            None => s.parse().map(ListenAddr::Tcp),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum SiteName {
+    /// `site _`, used when no other site matches
+    Default,
+    /// `site = 'example.com'`
+    Exact(String),
+    /// `alias ^ 'example.com'`, matches the domain and all of its subdomains,
+    /// `alias ^ '*.example.com'` only matches the subdomains
+    Levels(String),
+    /// `alias ~ '^[a-z]+\.example\.com$'`
+    Regex(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub session_timeout: Option<Duration>,
+    pub session_cache: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+    /// Overwrites the header if a handler already set it
+    pub enforce: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub modifier: LocationModifier,
+    pub pattern: String,
+    pub span: Span,
+    pub headers: Vec<Header>,
+    pub r#return: Option<Return>,
+    pub root: Option<Root>,
+    pub index: Option<Vec<String>>,
+    pub precompressed: Option<Vec<Encoding>>,
+    pub autoindex: Option<Autoindex>,
+    pub try_files: Option<TryFiles>,
+    pub proxy_pass: Option<ProxyPass>,
+    pub proxy_timeouts: ProxyTimeouts,
+    pub fastcgi_pass: Option<FastCgiPass>,
+    /// `fastcgi_param` in order, they replace the default parameters of the same name
+    pub fastcgi_params: Vec<FastCgiParam>,
+}
+
+/// `proxy_connect_timeout`, `proxy_read_timeout` and `proxy_send_timeout`, unset ones fall back
+/// to those of the site
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ProxyTimeouts {
+    /// Establishing the connection to the upstream
+    pub connect: Option<Duration>,
+    /// Between two reads of the response, starting once the request has been sent
+    pub read: Option<Duration>,
+    /// Between two writes of the request body
+    pub send: Option<Duration>,
+}
+
+impl ProxyTimeouts {
+    pub fn or(self, fallback: ProxyTimeouts) -> ProxyTimeouts {
+        ProxyTimeouts {
+            connect: self.connect.or(fallback.connect),
+            read: self.read.or(fallback.read),
+            send: self.send.or(fallback.send),
+        }
+    }
+}
+
+/// `upstream '<name>' { ... }`, servers `proxy_pass 'http://<name>'` or `fastcgi_pass '<name>'`
+/// balances requests over
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upstream {
+    pub name: String,
+    pub servers: Vec<UpstreamServer>,
+    pub balance: Balance,
+    /// `health_check`, probes the servers in the background
+    pub health_check: Option<HealthCheck>,
+    /// `eject.*`, takes servers out of rotation after failed requests
+    pub eject: Eject,
+    /// `retry.*`, sends failed requests to another server
+    pub retry: Retry,
+    /// `breaker`, stops sending requests to servers failing too many of them
+    pub breaker: Option<Breaker>,
+    pub span: Span,
+}
+
+/// `health_check '<path>'` and its `health_check.*` settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// Requested with `GET` over HTTP/1.1
+    pub path: String,
+    /// `health_check.status`, the status codes of healthy responses
+    pub status: Vec<u16>,
+    /// `health_check.interval`, also the timeout of each probe
+    pub interval: Duration,
+    /// `health_check.rise`, consecutive successful probes before an unhealthy server is used again
+    pub rise: u32,
+    /// `health_check.fall`, consecutive failed probes before a healthy server is taken out
+    pub fall: u32,
+}
+
+impl HealthCheck {
+    fn new(path: String) -> Self {
+        Self {
+            path,
+            status: vec![200],
+            interval: Duration::from_secs(5),
+            rise: 2,
+            fall: 3,
+        }
+    }
+}
+
+/// Passive health checking, based on the responses to proxied requests
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Eject {
+    /// `eject.after`, consecutive `5xx` responses or connect failures which eject a server, `0`
+    /// never ejects
+    pub after: u32,
+    /// `eject.backoff`, how long the server is ejected, doubled each time it's ejected again
+    /// before a request succeeded
+    pub backoff: Duration,
+}
+
+impl Default for Eject {
+    fn default() -> Self {
+        Self {
+            after: 5,
+            backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Retries of requests with idempotent methods after connect errors, `502`, `503` or `504`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Retry {
+    /// `retry.tries`, attempts per request including the first one, `1` never retries
+    pub tries: u32,
+    /// `retry.budget`, retries in percent of the requests, so failing servers aren't flooded
+    pub budget: u32,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            tries: 2,
+            budget: 20,
+        }
+    }
+}
+
+/// `breaker <percent>` and its `breaker.*` settings, a circuit breaker per server
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Breaker {
+    /// Percentage of failed requests within a window which opens the circuit
+    pub ratio: u32,
+    /// `breaker.min_requests`, fewer requests within a window never open the circuit
+    pub min_requests: u32,
+    /// `breaker.window`
+    pub window: Duration,
+    /// `breaker.cooldown`, after which a single request may try the server again
+    pub cooldown: Duration,
+}
+
+impl Breaker {
+    fn new(ratio: u32) -> Self {
+        Self {
+            ratio,
+            min_requests: 20,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// `server '<host>[:port]' [weight <n>]` or `server 'unix:<path>' [weight <n>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamServer {
+    /// `host:port` or `unix:<path>` of the server
+    pub addr: String,
+    pub weight: u32,
+}
+
+/// `balance <policy> [key]`, how an upstream picks the server for a request
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Balance {
+    /// `round_robin`, the servers take turns
+    #[default]
+    RoundRobin,
+    /// `weighted`, the servers take turns in proportion to their weight
+    Weighted,
+    /// `least_conn`, the server with the fewest requests in flight relative to its weight
+    LeastConn,
+    /// `random_two`, the less busy one of two randomly chosen servers
+    RandomTwo,
+    /// `hash "<key>"`, consistent hashing of the rendered key, requests with the same key go to
+    /// the same server as long as it is part of the upstream
+    Hash(Text),
+}
+
+/// `proxy_pass 'http://<host>[:port][/path]' [h2]` or `proxy_pass 'unix:<path>[:/path]' [h2]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyPass {
+    /// `host:port`, `unix:<path>` or the name of an upstream block
+    pub authority: String,
+    /// Replaces the prefix matched by the location, without a path the request path is passed
+    /// on unchanged
+    pub path: Option<String>,
+    /// Speaks HTTP/2 with prior knowledge instead of HTTP/1.1
+    pub h2: bool,
+}
+
+/// `fastcgi_pass '<host>:<port>'`, `fastcgi_pass 'unix:<path>'` or the name of an upstream block
+///
+/// The `proxy_*_timeout` settings apply. Health checks of upstream blocks speak HTTP, so they
+/// shouldn't be enabled for FastCGI servers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FastCgiPass {
+    /// `host:port`, `unix:<path>` or the name of an upstream block
+    pub authority: String,
+}
+
+/// `fastcgi_param '<name>' <value>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FastCgiParam {
+    pub name: String,
+    pub value: Text,
+}
+
+/// Where static files are served from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Root {
+    /// `root '<directory>'`, the whole request path is appended to the directory
+    Root(PathBuf),
+    /// `alias '<directory>'`, only the part following the location prefix is appended
+    Alias(PathBuf),
+}
+
+/// `autoindex <on|off> [dotfiles]`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Autoindex {
+    /// Lists the contents of directories without an index file
+    pub enabled: bool,
+    /// Includes entries whose name starts with a dot
+    pub dotfiles: bool,
+}
+
+/// Content codings, `precompressed` and `compress` list them in order of preference
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Encoding {
+    /// `br`, served from `<file>.br`
+    Brotli,
+    /// `gzip`, served from `<file>.gz`
+    Gzip,
+    /// `zstd`, served from `<file>.zst`
+    Zstd,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Zstd];
+
+    /// Name used in the config, `Accept-Encoding` and `Content-Encoding`
+    pub fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// Extension of precompressed siblings, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+            Encoding::Zstd => "zst",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum LocationModifier {
+    /// `location = '/path'`
+    Exact,
+    /// `location ^ '/path'`
+    Prefix,
+    /// `location ~ '\.php$'`
+    Regex,
+    /// `location @ 'name'`, never matched against requests, only entered through `try_files`
+    Named,
+}
+
+/// `try_files <file> ... <uri|@ name|status>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryFiles {
+    /// Paths checked in order, relative to the root of the location, a trailing `/` checks for
+    /// a directory instead of a file
+    pub candidates: Vec<Text>,
+    pub fallback: Fallback,
+    pub span: Span,
+}
+
+/// What `try_files` does if none of the candidates exist
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fallback {
+    /// Internal redirect, the path is matched against the locations again
+    Uri(Text),
+    /// Continues with the named location
+    Named(String),
+    /// Responds with the status code
+    Status(u16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Return {
+    pub status: u16,
+    pub body: Option<Text>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Text {
+    Plain(String),
+    /// Double quoted string which may contain variables
+    Format(Template),
+}
+
+impl Text {
+    pub fn render(&self, variables: &dyn Variables) -> String {
+        match self {
+            Text::Plain(text) => text.clone(),
+            Text::Format(template) => template.render(variables),
+        }
+    }
+}
+
+/// Settings of the main config file
+#[derive(Debug, Clone)]
+pub(crate) struct Global {
+    pub sites_dir: PathBuf,
+    pub snippets_dir: PathBuf,
+    pub cache: Cache,
+    pub compression: Compression,
+    pub types: Types,
+    pub upstreams: Vec<Upstream>,
+}
+
+impl Global {
+    pub fn from_directives(directives: &[Directive], base: &Path) -> Result<Self, Invalid> {
+        let mut global = Global {
+            sites_dir: base.join("sites"),
+            snippets_dir: base.join("snippets"),
+            cache: Cache::default(),
+            compression: Compression::default(),
+            types: Types::default(),
+            upstreams: vec![],
+        };
+
+        for directive in directives {
+            let mut args = Args::new(directive);
+            match args.name() {
+                "sites" => global.sites_dir = resolve(base, args.string("a directory")?.node),
+                "snips" => global.snippets_dir = resolve(base, args.string("a directory")?.node),
+                "cache.mode" => global.cache.mode = Some(args.string("a mode")?.node.to_string()),
+                "cache.size" => {
+                    let limit = args.string("a limit")?.node.to_string();
+                    let bytes = units::size(&args.integer("a size")?)?;
+                    global.cache.size = Some(CacheSize { limit, bytes });
+                }
+                "types" => {
+                    types(directive, &mut global.types)?;
+                    continue;
+                }
+                "upstream" => {
+                    global.upstreams.push(upstream(directive)?);
+                    continue;
+                }
+                "default_type" => global.types.default = Some(mime_type(&mut args)?),
+                "compress" => global.compression.encodings = encodings(&mut args)?,
+                "compress.types" => {
+                    let mut types = vec![args.string("a mime type")?.node.to_lowercase()];
+                    while args.peek().is_some() {
+                        types.push(args.string("a mime type")?.node.to_lowercase());
+                    }
+                    global.compression.types = types;
+                }
+                "compress.min_size" => {
+                    global.compression.min_size = units::size(&args.integer("a size")?)?
+                }
+                "compress.level" => {
+                    let level = args.integer("a level")?;
+                    global.compression.level = match level.node {
+                        (level @ 1..=22, None) => Some(level as u32),
+                        _ => {
+                            return Err(Invalid::new(level.span, "invalid compression level")
+                                .with_help("levels range from 1 to 22"))
+                        }
+                    };
+                }
+                _ => return Err(unknown(directive)),
+            }
+            args.finish()?;
+            no_block(directive)?;
+        }
+        check_upstreams(&global.upstreams)?;
+
+        Ok(global)
+    }
+}
+
+impl Site {
+    pub fn from_directives(
+        directives: &[Directive],
+        file: FileId,
+        path: &Path,
+    ) -> Result<Self, Invalid> {
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+        let mut site = Site {
+            path: path.to_path_buf(),
+            listen: vec![],
+            names: vec![],
+            tls: None,
+            headers: vec![],
+            root: None,
+            index: None,
+            precompressed: None,
+            autoindex: None,
+            types: Types::default(),
+            proxy_timeouts: ProxyTimeouts::default(),
+            upstreams: vec![],
+            locations: vec![],
+            directives: directives.to_vec(),
+        };
+        let mut cert = None;
+        let mut key = None;
+        let mut session_timeout = None;
+        let mut session_cache = None;
+        let mut locations = vec![];
+
+        for directive in directives {
+            let mut args = Args::new(directive);
+            match args.name() {
+                "listen" => site.listen.push(listen(&mut args)?),
+                "site" | "alias" => site.names.push(site_name(&mut args)?),
+                "tls.cert" => cert = Some(args.string("a certificate path")?),
+                "tls.key" => key = Some(args.string("a key path")?),
+                "tls.session.timeout" => {
+                    session_timeout = Some(units::duration(&args.integer("a duration")?)?)
+                }
+                "tls.session.cache" => session_cache = Some(units::size(&args.integer("a size")?)?),
+                "header" => site.headers.push(header(&mut args)?),
+                "root" => site.root = Some(resolve(base, args.string("a directory")?.node)),
+                "index" => site.index = Some(index(&mut args)?),
+                "precompressed" => site.precompressed = Some(encodings(&mut args)?),
+                "proxy_connect_timeout" => site.proxy_timeouts.connect = Some(timeout(&mut args)?),
+                "proxy_read_timeout" => site.proxy_timeouts.read = Some(timeout(&mut args)?),
+                "proxy_send_timeout" => site.proxy_timeouts.send = Some(timeout(&mut args)?),
+                "autoindex" => site.autoindex = Some(autoindex(&mut args)?),
+                "types" => {
+                    types(directive, &mut site.types)?;
+                    continue;
+                }
+                "upstream" => {
+                    site.upstreams.push(upstream(directive)?);
+                    continue;
+                }
+                "default_type" => site.types.default = Some(mime_type(&mut args)?),
+                "location" => {
+                    // parsed once all names are known, their captures may be used in templates
+                    locations.push(directive);
+                    continue;
+                }
+                _ => return Err(unknown(directive)),
+            }
+            args.finish()?;
+            no_block(directive)?;
+        }
+
+        if site.names.is_empty() {
+            return Err(Invalid::new(
+                Span::start(file),
+                "site has no `site` directive",
+            ));
+        }
+
+        let captures = site
+            .names
+            .iter()
+            .filter_map(|name| match &name.node {
+                SiteName::Regex(pattern) => Some(capture_names(pattern)),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        site.locations = locations
+            .into_iter()
+            .map(|directive| Location::from_directive(directive, base, &captures))
+            .collect::<Result<_, _>>()?;
+        check_named(&site.locations)?;
+        check_upstreams(&site.upstreams)?;
+
+        site.tls = match (cert, key) {
+            (Some(cert), Some(key)) => Some(Tls {
+                cert: resolve(base, cert.node),
+                key: resolve(base, key.node),
+                session_timeout,
+                session_cache,
+            }),
+            (None, None) => None,
+            (Some(cert), None) => {
+                return Err(Invalid::new(cert.span, "`tls.cert` requires `tls.key`"))
+            }
+            (None, Some(key)) => {
+                return Err(Invalid::new(key.span, "`tls.key` requires `tls.cert`"))
+            }
+        };
+
+        Ok(site)
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.names.iter().any(|n| n.node == SiteName::Default)
+    }
+}
+
+/// Builds a router over the names of `sites`, each name routes to the index of its site
+pub fn domain_router<'s>(
+    sites: impl IntoIterator<Item = &'s Site>,
+) -> Result<DomainRouter, regex::Error> {
+    let mut builder = DomainRouter::builder();
+    for (i, site) in sites.into_iter().enumerate() {
+        let slot = Slot(i as u64);
+        for name in &site.names {
+            match &name.node {
+                SiteName::Default => builder.default_slot(slot),
+                SiteName::Exact(domain) => builder.exact(domain, slot),
+                SiteName::Levels(domain) => builder.levels(domain, slot),
+                SiteName::Regex(pattern) => builder.regex(pattern, slot),
+            };
+        }
+    }
+    builder.build()
+}
+
+impl Location {
+    /// `base` is the directory of the site file, `captures` are the names of the capture groups
+    /// of the site regexes
+    pub fn from_directive(
+        directive: &Directive,
+        base: &Path,
+        captures: &[String],
+    ) -> Result<Self, Invalid> {
+        let mut args = Args::new(directive);
+        let op = args.operator("a modifier (`=`, `^`, `~` or `@`)")?;
+        let modifier = match op.node {
+            Operator::Equals => LocationModifier::Exact,
+            Operator::Caret => LocationModifier::Prefix,
+            Operator::Tilde => LocationModifier::Regex,
+            Operator::At => LocationModifier::Named,
+        };
+        let pattern = args.string("a path")?;
+        match modifier {
+            LocationModifier::Named => {
+                if pattern.node.is_empty() {
+                    return Err(Invalid::new(pattern.span, "empty location name"));
+                }
+            }
+            LocationModifier::Regex => {
+                if let Err(err) = regex::Regex::new(pattern.node) {
+                    return Err(Invalid::new(
+                        pattern.span,
+                        format!("invalid location pattern: {}", regex_error(&err)),
+                    ));
+                }
+            }
+            _ => {
+                if pattern.node.parse::<UrlPath>().is_err() {
+                    return Err(
+                        Invalid::new(pattern.span, "invalid location path").with_help(
+                            "characters outside of the url path syntax must be percent-encoded",
+                        ),
+                    );
+                }
+            }
+        }
+        let mut captures = captures.to_vec();
+        if modifier == LocationModifier::Regex {
+            captures.extend(capture_names(pattern.node));
+        }
+        let pattern = pattern.node.to_string();
+        args.finish()?;
+
+        let block = directive
+            .block
+            .as_ref()
+            .ok_or_else(|| Invalid::new(directive.span, "`location` requires a block"))?;
+
+        let mut location = Location {
+            modifier,
+            pattern,
+            span: directive.span,
+            headers: vec![],
+            r#return: None,
+            root: None,
+            index: None,
+            precompressed: None,
+            autoindex: None,
+            try_files: None,
+            proxy_pass: None,
+            proxy_timeouts: ProxyTimeouts::default(),
+            fastcgi_pass: None,
+            fastcgi_params: vec![],
+        };
+
+        for directive in block {
+            let mut args = Args::new(directive);
+            match args.name() {
+                "header" => location.headers.push(header(&mut args)?),
+                "return" => location.r#return = Some(r#return(&mut args, &captures)?),
+                name @ ("root" | "alias") => {
+                    if location.root.is_some() {
+                        return Err(Invalid::new(
+                            directive.span,
+                            "a location can only have one `root` or `alias`",
+                        ));
+                    }
+                    let dir = resolve(base, args.string("a directory")?.node);
+                    location.root = Some(match name {
+                        "root" => Root::Root(dir),
+                        _ => Root::Alias(dir),
+                    });
+                }
+                "index" => location.index = Some(index(&mut args)?),
+                "precompressed" => location.precompressed = Some(encodings(&mut args)?),
+                "proxy_connect_timeout" => {
+                    location.proxy_timeouts.connect = Some(timeout(&mut args)?)
+                }
+                "proxy_read_timeout" => location.proxy_timeouts.read = Some(timeout(&mut args)?),
+                "proxy_send_timeout" => location.proxy_timeouts.send = Some(timeout(&mut args)?),
+                "autoindex" => location.autoindex = Some(autoindex(&mut args)?),
+                "try_files" => {
+                    location.try_files = Some(try_files(directive.span, &mut args, &captures)?)
+                }
+                "proxy_pass" => {
+                    let proxy_pass = proxy_pass(&mut args)?;
+                    let whole =
+                        matches!(modifier, LocationModifier::Regex | LocationModifier::Named);
+                    if whole && proxy_pass.path.is_some() {
+                        return Err(Invalid::new(
+                            directive.span,
+                            "`proxy_pass` can't replace a path in regex or named locations",
+                        )
+                        .with_help("remove the path, the request path is passed on unchanged"));
+                    }
+                    location.proxy_pass = Some(proxy_pass);
+                }
+                "fastcgi_pass" => location.fastcgi_pass = Some(fastcgi_pass(&mut args)?),
+                "fastcgi_param" => location
+                    .fastcgi_params
+                    .push(fastcgi_param(&mut args, &captures)?),
+                _ => return Err(unknown(directive)),
+            }
+            args.finish()?;
+            no_block(directive)?;
+        }
+
+        if location.proxy_pass.is_some() && location.fastcgi_pass.is_some() {
+            return Err(Invalid::new(
+                directive.span,
+                "a location can only have one `proxy_pass` or `fastcgi_pass`",
+            ));
+        }
+
+        Ok(location)
+    }
+}
+
+/// Named locations have to be unique and exist if `try_files` falls back to them
+fn check_named(locations: &[Location]) -> Result<(), Invalid> {
+    let named = locations
+        .iter()
+        .filter(|location| location.modifier == LocationModifier::Named)
+        .collect::<Vec<_>>();
+    for (i, location) in named.iter().enumerate() {
+        if named[..i]
+            .iter()
+            .any(|other| other.pattern == location.pattern)
+        {
+            return Err(Invalid::new(
+                location.span,
+                format!("named location `{}` is defined twice", location.pattern),
+            ));
+        }
+    }
+
+    for try_files in locations.iter().filter_map(|l| l.try_files.as_ref()) {
+        if let Fallback::Named(name) = &try_files.fallback {
+            if !named.iter().any(|location| &location.pattern == name) {
+                let invalid =
+                    Invalid::new(try_files.span, format!("unknown named location `{}`", name));
+                let known = named.iter().map(|location| location.pattern.as_str());
+                return Err(match closest(name, known) {
+                    Some(known) => invalid.with_help(format!("did you mean `@ '{}'`?", known)),
+                    None => invalid,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Joins a path from the config onto the directory of the file it was found in
+fn resolve(base: &Path, path: &str) -> PathBuf {
+    base.join(path)
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+fn unknown(directive: &Directive) -> Invalid {
+    Invalid::new(
+        directive.name.span,
+        format!("unknown directive `{}`", directive.name.node),
+    )
+}
+
+fn no_block(directive: &Directive) -> Result<(), Invalid> {
+    match directive.block {
+        Some(_) => Err(Invalid::new(
+            directive.span,
+            format!("`{}` does not take a block", directive.name.node),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn listen(args: &mut Args) -> Result<Listen, Invalid> {
+    let addr = args.string("an address")?;
+    let addr = match addr.node.strip_prefix("unix:") {
+        Some("") => return Err(Invalid::new(addr.span, "missing socket path")),
+        Some(path) => ListenAddr::Unix(PathBuf::from(path)),
+        None => ListenAddr::Tcp(tcp_listen(addr, args)?),
+    };
+
+    let mut listen = Listen {
+        addr,
+        tls: false,
+        h2: false,
+    };
+    while args.peek().is_some() {
+        let flag = args.ident("a flag")?;
+        match flag.node {
+            "tls" => listen.tls = true,
+            "h2" => listen.h2 = true,
+            other => {
+                return Err(Invalid::new(
+                    flag.span,
+                    format!("unknown listen flag `{}`", other),
+                ))
+            }
+        }
+    }
+
+    Ok(listen)
+}
+
+/// `'<address>' <port>`
+fn tcp_listen(addr: Spanned<&str>, args: &mut Args) -> Result<SocketAddr, Invalid> {
+    let ip = addr
+        .node
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map_err(|_| Invalid::new(addr.span, format!("invalid ip address `{}`", addr.node)))?;
+
+    let port = args.integer("a port")?;
+    let port = match port.node {
+        (port @ 1..=65535, None) => port as u16,
+        _ => return Err(Invalid::new(port.span, "invalid port")),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+fn format(src: &str, span: Span, captures: &[String]) -> Result<Template, Invalid> {
+    let template = Template::parse(src).and_then(|template| {
+        template.check(captures)?;
+        Ok(template)
+    });
+
+    template.map_err(|err| {
+        let invalid = Invalid::new(span, err.to_string());
+        match err {
+            TemplateError::Unknown(name) => {
+                let known = template::BUILTINS
+                    .iter()
+                    .copied()
+                    .chain(captures.iter().map(String::as_str));
+                match closest(&name, known) {
+                    Some(known) => invalid.with_help(format!("did you mean `${}`?", known)),
+                    None => invalid,
+                }
+            }
+            _ => invalid.with_help("use `$$` for a literal `$`"),
+        }
+    })
+}
+
+fn closest<'k>(name: &str, known: impl Iterator<Item = &'k str>) -> Option<&'k str> {
+    let threshold = (name.chars().count() / 3).max(1);
+
+    known
+        .map(|known| (known, schema::distance(name, known)))
+        .filter(|(_, distance)| *distance <= threshold)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(known, _)| known)
+}
+
+/// Names of the named capture groups of a pattern validated by [`site_name`] or a location
+fn capture_names(pattern: &str) -> Vec<String> {
+    regex::Regex::new(pattern)
+        .map(|regex| {
+            regex
+                .capture_names()
+                .flatten()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The regex crate renders syntax errors over multiple lines including the pattern, the
+/// diagnostic already points at the pattern so only the actual error is kept
+fn regex_error(err: &regex::Error) -> String {
+    let text = err.to_string();
+    text.lines()
+        .rev()
+        .find_map(|line| line.strip_prefix("error: "))
+        .unwrap_or(&text)
+        .to_string()
+}
+
+fn site_name(args: &mut Args) -> Result<Spanned<SiteName>, Invalid> {
+    let first = args.next("a name")?;
+    let name = match &first.node {
+        Value::Ident(ident) if ident == "_" => SiteName::Default,
+        Value::Operator(Operator::Equals) => {
+            SiteName::Exact(args.string("a domain")?.node.to_lowercase())
+        }
+        Value::Operator(Operator::Caret) => {
+            SiteName::Levels(args.string("a domain")?.node.to_lowercase())
+        }
+        Value::Operator(Operator::Tilde) => {
+            let pattern = args.string("a pattern")?;
+            if let Err(err) = regex::Regex::new(pattern.node) {
+                return Err(Invalid::new(
+                    pattern.span,
+                    format!("invalid site pattern: {}", regex_error(&err)),
+                ));
+            }
+            SiteName::Regex(pattern.node.to_string())
+        }
+        other => return Err(args.mismatch(first.span, "`_`, `=`, `^` or `~`", other)),
+    };
+
+    Ok(Spanned::new(name, first.span))
+}
+
+fn header(args: &mut Args) -> Result<Header, Invalid> {
+    let name = args.string("a header name")?.node.to_string();
+    let value = args.string("a header value")?.node.to_string();
+    let enforce = match args.peek() {
+        Some(_) => {
+            let flag = args.ident("`enforce`")?;
+            if flag.node != "enforce" {
+                return Err(Invalid::new(flag.span, "expected `enforce`"));
+            }
+            true
+        }
+        None => false,
+    };
+
+    Ok(Header {
+        name,
+        value,
+        enforce,
+    })
+}
+
+fn index(args: &mut Args) -> Result<Vec<String>, Invalid> {
+    let mut files = vec![args.string("a file name")?.node.to_string()];
+    while args.peek().is_some() {
+        files.push(args.string("a file name")?.node.to_string());
+    }
+    Ok(files)
+}
+
+/// Adds the entries of a `types` block, later entries win for the same extension
+fn types(directive: &Directive, types: &mut Types) -> Result<(), Invalid> {
+    for entry in directive.block.iter().flatten() {
+        let mime = entry.name.node.to_lowercase();
+        for arg in &entry.args {
+            let extension = match &arg.node {
+                Value::Ident(ext) | Value::String(ext) => ext.trim_start_matches('.'),
+                other => return Err(Args::new(entry).mismatch(arg.span, "an extension", other)),
+            };
+            types
+                .extensions
+                .insert(extension.to_lowercase(), mime.clone());
+        }
+    }
+    Ok(())
+}
+
+fn mime_type(args: &mut Args) -> Result<String, Invalid> {
+    let mime = args.string("a mime type")?;
+    if !mime.node.contains('/') {
+        return Err(
+            Invalid::new(mime.span, format!("invalid mime type `{}`", mime.node))
+                .with_help("mime types look like `application/octet-stream`"),
+        );
+    }
+    Ok(mime.node.to_string())
+}
+
+fn autoindex(args: &mut Args) -> Result<Autoindex, Invalid> {
+    let switch = args.ident("`on` or `off`")?;
+    let enabled = match switch.node {
+        "on" => true,
+        "off" => false,
+        _ => return Err(Invalid::new(switch.span, "expected `on` or `off`")),
+    };
+    let dotfiles = match args.peek() {
+        Some(_) => {
+            let flag = args.ident("`dotfiles`")?;
+            if flag.node != "dotfiles" {
+                return Err(Invalid::new(flag.span, "expected `dotfiles`"));
+            }
+            true
+        }
+        None => false,
+    };
+
+    Ok(Autoindex { enabled, dotfiles })
+}
+
+fn encodings(args: &mut Args) -> Result<Vec<Encoding>, Invalid> {
+    let mut encodings = vec![];
+    while encodings.is_empty() || args.peek().is_some() {
+        let name = args.ident("an encoding")?;
+        let encoding = Encoding::ALL
+            .into_iter()
+            .find(|encoding| encoding.token() == name.node)
+            .ok_or_else(|| {
+                Invalid::new(name.span, format!("unknown encoding `{}`", name.node))
+                    .with_help("supported encodings are `br`, `gzip` and `zstd`")
+            })?;
+        if encodings.contains(&encoding) {
+            return Err(Invalid::new(
+                name.span,
+                format!("`{}` is listed twice", name.node),
+            ));
+        }
+        encodings.push(encoding);
+    }
+    Ok(encodings)
+}
+
+/// `<host>[:port]`, the host being a name, an ipv4 address or a bracketed ipv6 address
+fn is_authority(authority: &str) -> bool {
+    // `:port` or nothing
+    let valid_port = |port: &str| {
+        port.is_empty()
+            || port
+                .strip_prefix(':')
+                .is_some_and(|port| port.parse::<u16>().is_ok())
+    };
+    match authority.strip_prefix('[') {
+        // ipv6 literals contain colons but are enclosed in brackets
+        Some(rest) => rest
+            .split_once(']')
+            .is_some_and(|(ip, port)| ip.parse::<Ipv6Addr>().is_ok() && valid_port(port)),
+        None => {
+            let (host, port) = authority.split_at(authority.find(':').unwrap_or(authority.len()));
+            is_host_name(host) && valid_port(port)
+        }
+    }
+}
+
+/// `unix:<path>`, a Unix domain socket
+fn is_unix(addr: &str) -> bool {
+    addr.strip_prefix("unix:")
+        .is_some_and(|path| !path.is_empty() && !path.contains(':'))
+}
+
+fn is_host_name(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'))
+}
+
+fn upstream(directive: &Directive) -> Result<Upstream, Invalid> {
+    let mut args = Args::new(directive);
+    let name = args.string("a name")?;
+    if !is_host_name(name.node) {
+        return Err(
+            Invalid::new(name.span, format!("invalid upstream name `{}`", name.node))
+                .with_help("names may only contain letters, digits, `-`, `.` and `_`"),
+        );
+    }
+    args.finish()?;
+
+    let mut upstream = Upstream {
+        name: name.node.to_string(),
+        servers: vec![],
+        balance: Balance::default(),
+        health_check: None,
+        eject: Eject::default(),
+        retry: Retry::default(),
+        breaker: None,
+        span: directive.span,
+    };
+    // `health_check.*` and `breaker.*` may come before `health_check` and `breaker`
+    let mut health_check = None;
+    let mut settings = HealthCheck::new(String::new());
+    let mut breaker = None;
+    let mut breaker_settings = Breaker::new(0);
+    // the first setting of each, reported if the directive enabling it is missing
+    let mut first_setting = None;
+    let mut first_breaker_setting = None;
+    for directive in directive.block.iter().flatten() {
+        let mut args = Args::new(directive);
+        if args.name().starts_with("health_check.") {
+            first_setting.get_or_insert(directive.name.span);
+        }
+        if args.name().starts_with("breaker.") {
+            first_breaker_setting.get_or_insert(directive.name.span);
+        }
+        match args.name() {
+            "server" => {
+                let addr = args.string("an address")?;
+                if !is_authority(addr.node) && !is_unix(addr.node) {
+                    return Err(Invalid::new(
+                        addr.span,
+                        format!("invalid upstream address `{}`", addr.node),
+                    ));
+                }
+                let mut weight = 1;
+                if args.peek().is_some() {
+                    let option = args.ident("`weight`")?;
+                    if option.node != "weight" {
+                        return Err(Invalid::new(
+                            option.span,
+                            format!("unknown server option `{}`", option.node),
+                        ));
+                    }
+                    let value = args.integer("a weight")?;
+                    weight = match value.node {
+                        (weight @ 1..=1000, None) => weight as u32,
+                        _ => {
+                            return Err(Invalid::new(value.span, "invalid weight")
+                                .with_help("weights range from 1 to 1000"))
+                        }
+                    };
+                }
+                upstream.servers.push(UpstreamServer {
+                    addr: addr.node.to_string(),
+                    weight,
+                });
+            }
+            "balance" => {
+                let policy = args.ident("a policy")?;
+                upstream.balance = match policy.node {
+                    "round_robin" => Balance::RoundRobin,
+                    "weighted" => Balance::Weighted,
+                    "least_conn" => Balance::LeastConn,
+                    "random_two" => Balance::RandomTwo,
+                    "hash" => {
+                        let key = args.next("a key")?;
+                        Balance::Hash(match &key.node {
+                            Value::String(str) => Text::Plain(str.clone()),
+                            Value::FormatString(str) => Text::Format(format(str, key.span, &[])?),
+                            other => return Err(args.mismatch(key.span, "a key", other)),
+                        })
+                    }
+                    other => {
+                        return Err(Invalid::new(
+                            policy.span,
+                            format!("unknown balance policy `{}`", other),
+                        )
+                        .with_help(
+                            "policies are `round_robin`, `weighted`, `least_conn`, `random_two` \
+                             and `hash`",
+                        ))
+                    }
+                };
+            }
+            "health_check" => {
+                let path = args.string("a path")?;
+                if !path.node.starts_with('/') {
+                    return Err(Invalid::new(path.span, "invalid health check path")
+                        .with_help("paths start with `/`"));
+                }
+                health_check = Some(path.node.to_string());
+            }
+            "health_check.status" => {
+                settings.status.clear();
+                while settings.status.is_empty() || args.peek().is_some() {
+                    let status = args.integer("a status code")?;
+                    settings.status.push(match status.node {
+                        (code @ 100..=999, None) => code as u16,
+                        _ => return Err(Invalid::new(status.span, "invalid status code")),
+                    });
+                }
+            }
+            "health_check.interval" => {
+                let interval = args.integer("a duration")?;
+                settings.interval = units::duration(&interval)?;
+                if settings.interval.is_zero() {
+                    return Err(Invalid::new(interval.span, "invalid interval"));
+                }
+            }
+            "health_check.rise" => settings.rise = count(&mut args)?,
+            "health_check.fall" => settings.fall = count(&mut args)?,
+            "eject.after" => {
+                let after = args.integer("a number of failures")?;
+                upstream.eject.after = match after.node {
+                    (after @ 0..=1000, None) => after as u32,
+                    _ => {
+                        return Err(Invalid::new(after.span, "invalid number of failures")
+                            .with_help("ranges from 1 to 1000, or 0 to never eject"))
+                    }
+                };
+            }
+            "retry.tries" => {
+                let tries = args.integer("a number of tries")?;
+                upstream.retry.tries = match tries.node {
+                    (tries @ 1..=10, None) => tries as u32,
+                    _ => {
+                        return Err(Invalid::new(tries.span, "invalid number of tries")
+                            .with_help("ranges from 1 to 10, 1 never retries"))
+                    }
+                };
+            }
+            "retry.budget" => upstream.retry.budget = percent(&mut args, 0)?,
+            "breaker" => breaker = Some(percent(&mut args, 1)?),
+            "breaker.min_requests" => {
+                let requests = args.integer("a number of requests")?;
+                breaker_settings.min_requests = match requests.node {
+                    (requests @ 1..=100_000, None) => requests as u32,
+                    _ => return Err(Invalid::new(requests.span, "invalid number of requests")),
+                };
+            }
+            "breaker.window" => breaker_settings.window = timeout(&mut args)?,
+            "breaker.cooldown" => breaker_settings.cooldown = timeout(&mut args)?,
+            "eject.backoff" => {
+                upstream.eject.backoff = units::duration(&args.integer("a duration")?)?
+            }
+            _ => return Err(unknown(directive)),
+        }
+        args.finish()?;
+        no_block(directive)?;
+    }
+
+    upstream.health_check = match (health_check, first_setting) {
+        (Some(path), _) => Some(HealthCheck { path, ..settings }),
+        (None, Some(span)) => {
+            return Err(
+                Invalid::new(span, "health check settings without `health_check`")
+                    .with_help("add `health_check '<path>'` to probe the servers"),
+            )
+        }
+        (None, None) => None,
+    };
+    upstream.breaker = match (breaker, first_breaker_setting) {
+        (Some(ratio), _) => Some(Breaker {
+            ratio,
+            ..breaker_settings
+        }),
+        (None, Some(span)) => {
+            return Err(Invalid::new(span, "breaker settings without `breaker`")
+                .with_help("add `breaker <percent>` to enable the circuit breaker"))
+        }
+        (None, None) => None,
+    };
+    if upstream.servers.is_empty() {
+        return Err(Invalid::new(
+            directive.span,
+            format!("upstream `{}` has no servers", upstream.name),
+        ));
+    }
+    Ok(upstream)
+}
+
+/// A percentage from `min` to 100
+fn percent(args: &mut Args, min: i64) -> Result<u32, Invalid> {
+    let percent = args.integer("a percentage")?;
+    match percent.node {
+        (percent, None) if (min..=100).contains(&percent) => Ok(percent as u32),
+        _ => Err(Invalid::new(percent.span, "invalid percentage")
+            .with_help(format!("ranges from {} to 100", min))),
+    }
+}
+
+/// A duration which isn't zero
+fn timeout(args: &mut Args) -> Result<Duration, Invalid> {
+    let timeout = args.integer("a duration")?;
+    match units::duration(&timeout)? {
+        duration if duration.is_zero() => {
+            Err(Invalid::new(timeout.span, "invalid duration")
+                .with_help("has to be longer than zero"))
+        }
+        duration => Ok(duration),
+    }
+}
+
+/// Consecutive probes of `health_check.rise` and `health_check.fall`
+fn count(args: &mut Args) -> Result<u32, Invalid> {
+    let count = args.integer("a number of probes")?;
+    match count.node {
+        (count @ 1..=100, None) => Ok(count as u32),
+        _ => {
+            Err(Invalid::new(count.span, "invalid number of probes")
+                .with_help("ranges from 1 to 100"))
+        }
+    }
+}
+
+/// Upstreams of the same file have to be unique
+fn check_upstreams(upstreams: &[Upstream]) -> Result<(), Invalid> {
+    for (i, upstream) in upstreams.iter().enumerate() {
+        if upstreams[..i]
+            .iter()
+            .any(|other| other.name == upstream.name)
+        {
+            return Err(Invalid::new(
+                upstream.span,
+                format!("upstream `{}` is defined twice", upstream.name),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn proxy_pass(args: &mut Args) -> Result<ProxyPass, Invalid> {
+    let url = args.string("an upstream url")?;
+    if let Some(rest) = url.node.strip_prefix("unix:") {
+        return unix_proxy_pass(url.span, rest, args);
+    }
+    let rest = match url.node.split_once("://") {
+        Some(("http", rest)) => rest,
+        Some((scheme, _)) => {
+            return Err(Invalid::new(
+                url.span,
+                format!("unsupported upstream scheme `{}`", scheme),
+            )
+            .with_help("upstreams are reached through `http://` or `unix:`, add `h2` for HTTP/2"))
+        }
+        None => {
+            return Err(Invalid::new(url.span, "invalid upstream url")
+                .with_help("expected `http://<host>[:port][/path]`"))
+        }
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], Some(&rest[i..])),
+        None => (rest, None),
+    };
+
+    if !is_authority(authority) {
+        return Err(Invalid::new(
+            url.span,
+            format!("invalid upstream address `{}`", authority),
+        ));
+    }
+    proxy_pass_flags(url.span, authority.to_string(), path, args)
+}
+
+/// `unix:<socket>[:/path]`, the socket path is followed by the optional path of the upstream
+fn unix_proxy_pass(span: Span, rest: &str, args: &mut Args) -> Result<ProxyPass, Invalid> {
+    let (socket, path) = match rest.split_once(':') {
+        Some((socket, path)) => (socket, Some(path)),
+        None => (rest, None),
+    };
+    if socket.is_empty() {
+        return Err(Invalid::new(span, "missing socket path"));
+    }
+    proxy_pass_flags(span, format!("unix:{}", socket), path, args)
+}
+
+fn proxy_pass_flags(
+    span: Span,
+    authority: String,
+    path: Option<&str>,
+    args: &mut Args,
+) -> Result<ProxyPass, Invalid> {
+    let valid = |path: &str| {
+        path.starts_with('/') && !path.contains(['?', '#']) && path.parse::<UrlPath>().is_ok()
+    };
+    if path.is_some_and(|path| !valid(path)) {
+        return Err(Invalid::new(span, "invalid upstream path")
+            .with_help("characters outside of the url path syntax must be percent-encoded"));
+    }
+
+    let mut proxy_pass = ProxyPass {
+        authority,
+        path: path.map(str::to_string),
+        h2: false,
+    };
+    while args.peek().is_some() {
+        let flag = args.ident("a flag")?;
+        match flag.node {
+            "h2" => proxy_pass.h2 = true,
+            other => {
+                return Err(Invalid::new(
+                    flag.span,
+                    format!("unknown proxy_pass flag `{}`", other),
+                ))
+            }
+        }
+    }
+
+    Ok(proxy_pass)
+}
+
+fn fastcgi_pass(args: &mut Args) -> Result<FastCgiPass, Invalid> {
+    let addr = args.string("a FastCGI address")?;
+    if !is_authority(addr.node) && !is_unix(addr.node) {
+        return Err(Invalid::new(
+            addr.span,
+            format!("invalid FastCGI address `{}`", addr.node),
+        )
+        .with_help("expected `<host>:<port>`, `unix:<path>` or the name of an upstream"));
+    }
+    Ok(FastCgiPass {
+        authority: addr.node.to_string(),
+    })
+}
+
+fn fastcgi_param(args: &mut Args, captures: &[String]) -> Result<FastCgiParam, Invalid> {
+    let name = args.string("a parameter name")?;
+    if name.node.is_empty() || name.node.contains('=') {
+        return Err(Invalid::new(
+            name.span,
+            format!("invalid FastCGI parameter name `{}`", name.node),
+        ));
+    }
+
+    let value = args.next("a value")?;
+    let value = match &value.node {
+        Value::String(str) => Text::Plain(str.clone()),
+        Value::FormatString(str) => Text::Format(format(str, value.span, captures)?),
+        other => return Err(args.mismatch(value.span, "a string", other)),
+    };
+
+    Ok(FastCgiParam {
+        name: name.node.to_string(),
+        value,
+    })
+}
+
+fn try_files(span: Span, args: &mut Args, captures: &[String]) -> Result<TryFiles, Invalid> {
+    let mut candidates = vec![];
+    let fallback = loop {
+        let arg = args.next("a fallback")?;
+        let last = args.peek().is_none();
+        let text = match &arg.node {
+            Value::String(str) => Text::Plain(str.clone()),
+            Value::FormatString(str) => Text::Format(format(str, arg.span, captures)?),
+            Value::Integer { value, suffix } if last => {
+                break match (value, suffix) {
+                    (code @ 100..=999, None) => Fallback::Status(*code as u16),
+                    _ => return Err(Invalid::new(arg.span, "invalid status code")),
+                };
+            }
+            Value::Operator(Operator::At) => {
+                let name = args.string("a location name")?;
+                break Fallback::Named(name.node.to_string());
+            }
+            other => return Err(args.mismatch(arg.span, "a path", other)),
+        };
+        match last {
+            true => break Fallback::Uri(text),
+            false => candidates.push(text),
+        }
+    };
+
+    Ok(TryFiles {
+        candidates,
+        fallback,
+        span,
+    })
+}
+
+fn r#return(args: &mut Args, captures: &[String]) -> Result<Return, Invalid> {
+    let status = args.integer("a status code")?;
+    let status = match status.node {
+        (code @ 100..=999, None) => code as u16,
+        _ => return Err(Invalid::new(status.span, "invalid status code")),
+    };
+
+    let body = match args.peek() {
+        Some(arg) => {
+            args.next("a body")?;
+            match &arg.node {
+                Value::String(str) => Some(Text::Plain(str.clone())),
+                Value::FormatString(str) => Some(Text::Format(format(str, arg.span, captures)?)),
+                other => return Err(args.mismatch(arg.span, "a string", other)),
+            }
+        }
+        None => None,
+    };
+
+    Ok(Return { status, body })
+}
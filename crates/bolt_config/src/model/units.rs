@@ -0,0 +1,50 @@
+use crate::ast::Spanned;
+use crate::error::Invalid;
+use std::time::Duration;
+
+/// `10` bytes, `10K`, `10M` or `10G` (powers of 1024)
+pub fn size(value: &Spanned<(i64, Option<&str>)>) -> Result<u64, Invalid> {
+    let (num, suffix) = value.node;
+    let factor = match suffix {
+        None => 1,
+        Some("K" | "k") => 1 << 10,
+        Some("M" | "m") => 1 << 20,
+        Some("G" | "g") => 1 << 30,
+        Some(other) => {
+            return Err(Invalid::new(
+                value.span,
+                format!("unknown size unit `{}`, expected K, M or G", other),
+            ))
+        }
+    };
+
+    u64::try_from(num)
+        .ok()
+        .and_then(|num| num.checked_mul(factor))
+        .ok_or_else(|| Invalid::new(value.span, "invalid size"))
+}
+
+/// `10` seconds, `10ms`, `10s`, `10m`, `10h` or `10d`
+pub fn duration(value: &Spanned<(i64, Option<&str>)>) -> Result<Duration, Invalid> {
+    let (num, suffix) = value.node;
+    let num = u64::try_from(num).map_err(|_| Invalid::new(value.span, "invalid duration"))?;
+
+    let duration = match suffix {
+        Some("ms") => Some(Duration::from_millis(num)),
+        None | Some("s") => Some(Duration::from_secs(num)),
+        Some("m") => num.checked_mul(60).map(Duration::from_secs),
+        Some("h") => num.checked_mul(60 * 60).map(Duration::from_secs),
+        Some("d") => num.checked_mul(60 * 60 * 24).map(Duration::from_secs),
+        Some(other) => {
+            return Err(Invalid::new(
+                value.span,
+                format!(
+                    "unknown duration unit `{}`, expected ms, s, m, h or d",
+                    other
+                ),
+            ))
+        }
+    };
+
+    duration.ok_or_else(|| Invalid::new(value.span, "invalid duration"))
+}
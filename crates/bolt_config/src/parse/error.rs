@@ -0,0 +1,42 @@
+use crate::ast::Span;
+use nom::error::ErrorKind;
+use std::fmt::{Display, Formatter};
+
+/// Internal nom error, only remembers what we were looking for
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError<'a> {
+    pub input: &'a str,
+    pub expected: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub span: Span,
+    pub expected: &'static str,
+    pub found: Option<char>,
+}
+
+impl<'a> nom::error::ParseError<&'a str> for SyntaxError<'a> {
+    fn from_error_kind(input: &'a str, _: ErrorKind) -> Self {
+        Self {
+            input,
+            expected: "valid syntax",
+        }
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.found {
+            Some('\n' | '\r') => write!(f, "expected {}, found end of line", self.expected),
+            Some(c) => write!(f, "expected {}, found {:?}", self.expected, c),
+            None => write!(f, "expected {}, found end of file", self.expected),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -0,0 +1,407 @@
+//! nom based parser for the bolt configuration language, see [`crate::ast`] for an overview.
+
+use crate::ast::{Directive, Operator, Span, Spanned, Value};
+use crate::source::FileId;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_till, take_while};
+use nom::character::complete::{
+    alpha0, char, digit1, multispace1, not_line_ending, satisfy, space0,
+};
+use nom::combinator::{opt, recognize};
+use nom::multi::many0_count;
+use nom::sequence::{pair, tuple};
+use nom::IResult;
+
+mod error;
+
+pub use error::{ParseError, SyntaxError};
+
+type PResult<'a, T> = IResult<&'a str, T, SyntaxError<'a>>;
+
+/// Parses a whole config file into its directives.
+pub fn parse(file: FileId, src: &str) -> Result<Vec<Directive>, ParseError> {
+    let parser = Parser::new(file, src);
+
+    match parser.directives(src, false) {
+        Ok((_, directives)) => Ok(directives),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(ParseError {
+            span: parser.span(err.input, err.input),
+            expected: err.expected,
+            found: err.input.chars().next(),
+        }),
+        Err(nom::Err::Incomplete(_)) => unreachable!("only complete parsers are used"),
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    file: FileId,
+    // byte offsets at which each line starts
+    lines: Vec<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(file: FileId, src: &'a str) -> Self {
+        let lines = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self { src, file, lines }
+    }
+
+    /// Creates a span between two remainders of the source
+    fn span(&self, start: &'a str, end: &'a str) -> Span {
+        let offset = self.src.len() - start.len();
+        let len = start.len() - end.len();
+
+        let line = self.lines.partition_point(|&start| start <= offset);
+        let column = self.src[self.lines[line - 1]..offset].chars().count() + 1;
+
+        Span {
+            file: self.file,
+            offset,
+            len,
+            line,
+            column,
+        }
+    }
+
+    fn directives(&self, mut i: &'a str, in_block: bool) -> PResult<'a, Vec<Directive>> {
+        let mut directives = vec![];
+
+        loop {
+            i = separators(i)?.0;
+
+            if i.is_empty() {
+                return if in_block {
+                    fail(i, "a closing '}'")
+                } else {
+                    Ok((i, directives))
+                };
+            }
+            if in_block && i.starts_with('}') {
+                return Ok((i, directives));
+            }
+
+            let (rest, directive) = self.directive(i)?;
+            directives.push(directive);
+            i = rest;
+        }
+    }
+
+    fn directive(&self, start: &'a str) -> PResult<'a, Directive> {
+        let (mut i, name) = match name(start) {
+            Ok(ok) => ok,
+            Err(_) => return fail(start, "a directive name"),
+        };
+        let name = Spanned::new(name.to_string(), self.span(start, i));
+
+        let mut args = vec![];
+        let mut end = i;
+        let mut block = None;
+
+        loop {
+            let (rest, space) = space0(i)?;
+
+            if let Some(rest) = rest.strip_prefix('{') {
+                let (rest, directives) = self.directives(rest, true)?;
+                i = &rest[1..];
+                block = Some(directives);
+                break;
+            }
+            if at_end(rest) {
+                i = rest;
+                break;
+            }
+            if space.is_empty() {
+                return fail(rest, "whitespace or the end of the line");
+            }
+
+            let (rest, value) = self.value(rest)?;
+            args.push(value);
+            end = rest;
+            i = rest;
+        }
+
+        let (i, _) = space0(i)?;
+        if !at_end(i) {
+            return fail(i, "the end of the line");
+        }
+
+        Ok((
+            i,
+            Directive {
+                name,
+                args,
+                block,
+                span: self.span(start, end),
+            },
+        ))
+    }
+
+    fn value(&self, i: &'a str) -> PResult<'a, Spanned<Value>> {
+        let (rest, value) = match i.chars().next() {
+            Some('\'') => string(i)?,
+            Some('"') => format_string(i)?,
+            Some('=') => (&i[1..], Value::Operator(Operator::Equals)),
+            Some('~') => (&i[1..], Value::Operator(Operator::Tilde)),
+            Some('^') => (&i[1..], Value::Operator(Operator::Caret)),
+            Some('@') => (&i[1..], Value::Operator(Operator::At)),
+            Some(c) if c == '-' || c.is_ascii_digit() => number(i)?,
+            Some(c) if is_ident_start(c) => {
+                let (rest, ident) = name(i)?;
+                (rest, Value::Ident(ident.to_string()))
+            }
+            _ => return fail(i, "a value"),
+        };
+
+        Ok((rest, Spanned::new(value, self.span(i, rest))))
+    }
+}
+
+fn fail<'a, T>(input: &'a str, expected: &'static str) -> PResult<'a, T> {
+    Err(nom::Err::Failure(SyntaxError { input, expected }))
+}
+
+fn at_end(i: &str) -> bool {
+    i.is_empty() || i.starts_with(['\n', '\r', ';', '#', '}'])
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/' | '+')
+}
+
+fn comment(i: &str) -> PResult<'_, &str> {
+    recognize(pair(char('#'), not_line_ending))(i)
+}
+
+/// Skips whitespace, newlines, semicolons and comments
+fn separators(i: &str) -> PResult<'_, usize> {
+    many0_count(alt((multispace1, tag(";"), comment)))(i)
+}
+
+fn name(i: &str) -> PResult<'_, &str> {
+    recognize(pair(satisfy(is_ident_start), take_while(is_ident_char)))(i)
+}
+
+fn string(i: &str) -> PResult<'_, Value> {
+    let (mut i, _) = char('\'')(i)?;
+    let mut out = String::new();
+
+    loop {
+        let (rest, chunk) = take_till(|c| matches!(c, '\'' | '\\' | '\n'))(i)?;
+        out.push_str(chunk);
+
+        match rest.chars().next() {
+            Some('\'') => return Ok((&rest[1..], Value::String(out))),
+            // Only quotes can be escaped, everything else is taken as is so regexes stay readable
+            Some('\\') if rest[1..].starts_with('\'') => {
+                out.push('\'');
+                i = &rest[2..];
+            }
+            Some('\\') => {
+                out.push('\\');
+                i = &rest[1..];
+            }
+            _ => return fail(rest, "a closing '"),
+        }
+    }
+}
+
+fn format_string(i: &str) -> PResult<'_, Value> {
+    let (mut i, _) = char('"')(i)?;
+    let mut out = String::new();
+
+    loop {
+        let (rest, chunk) = take_till(|c| matches!(c, '"' | '\\' | '\n'))(i)?;
+        out.push_str(chunk);
+
+        match rest.chars().next() {
+            Some('"') => return Ok((&rest[1..], Value::FormatString(out))),
+            Some('\\') => {
+                let escaped = match rest[1..].chars().next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    _ => return fail(&rest[1..], "an escape sequence (\\\", \\\\, \\n or \\t)"),
+                };
+                out.push(escaped);
+                i = &rest[2..];
+            }
+            _ => return fail(rest, "a closing \""),
+        }
+    }
+}
+
+fn digits(i: &str) -> PResult<'_, &str> {
+    recognize(tuple((
+        opt(char('-')),
+        digit1,
+        opt(pair(char('.'), digit1)),
+    )))(i)
+}
+
+fn number(i: &str) -> PResult<'_, Value> {
+    let (rest, digits) = match digits(i) {
+        Ok(ok) => ok,
+        Err(_) => return fail(i, "a number"),
+    };
+    let (rest, suffix) = alpha0(rest)?;
+    let suffix = (!suffix.is_empty()).then(|| suffix.to_string());
+
+    let value = if digits.contains('.') {
+        Value::Float {
+            value: digits.parse().expect("digits are valid floats"),
+            suffix,
+        }
+    } else {
+        match digits.parse() {
+            Ok(value) => Value::Integer { value, suffix },
+            Err(_) => return fail(i, "an integer that fits into 64 bits"),
+        }
+    };
+
+    Ok((rest, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(src: &str) -> Vec<Directive> {
+        super::parse(FileId(0), src).unwrap()
+    }
+
+    fn args(src: &str) -> Vec<Value> {
+        parse(src)
+            .remove(0)
+            .args
+            .into_iter()
+            .map(|arg| arg.node)
+            .collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(parse(""), vec![]);
+        assert_eq!(parse("\n  # comment\n;\n"), vec![]);
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            args(r#"x 'a b' 'it\'s' '^[a-z]\.com$' "$host\"\\""#),
+            vec![
+                Value::String("a b".to_string()),
+                Value::String("it's".to_string()),
+                Value::String(r"^[a-z]\.com$".to_string()),
+                Value::FormatString("$host\"\\".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            args("x 1 -1 1.5 10M 1d"),
+            vec![
+                Value::Integer {
+                    value: 1,
+                    suffix: None
+                },
+                Value::Integer {
+                    value: -1,
+                    suffix: None
+                },
+                Value::Float {
+                    value: 1.5,
+                    suffix: None
+                },
+                Value::Integer {
+                    value: 10,
+                    suffix: Some("M".to_string())
+                },
+                Value::Integer {
+                    value: 1,
+                    suffix: Some("d".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn idents_and_operators() {
+        assert_eq!(
+            args("x tls h2 _ = ~ ^ @"),
+            vec![
+                Value::Ident("tls".to_string()),
+                Value::Ident("h2".to_string()),
+                Value::Ident("_".to_string()),
+                Value::Operator(Operator::Equals),
+                Value::Operator(Operator::Tilde),
+                Value::Operator(Operator::Caret),
+                Value::Operator(Operator::At),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let directives = parse("a 1\n\n  tls.cert './x' # cert\n");
+
+        assert_eq!(directives.len(), 2);
+        let cert = &directives[1];
+        assert_eq!(cert.name.node, "tls.cert");
+        assert_eq!((cert.name.span.line, cert.name.span.column), (3, 3));
+        assert_eq!((cert.args[0].span.line, cert.args[0].span.column), (3, 12));
+        assert_eq!(cert.span.len, "tls.cert './x'".len());
+    }
+
+    #[test]
+    fn blocks() {
+        let directives = parse("location = '/' {\n    return 200 'hi'\n}\nlocation ^ '/a' { return 404; header 'a' 'b' }");
+
+        assert_eq!(directives.len(), 2);
+        assert_eq!(directives[0].block.as_ref().map(Vec::len), Some(1));
+        assert_eq!(directives[1].block.as_ref().map(Vec::len), Some(2));
+        assert_eq!(directives[1].block.as_ref().unwrap()[1].name.node, "header");
+    }
+
+    #[test]
+    fn semicolons() {
+        assert_eq!(parse("a 1; b 2;c").len(), 3);
+    }
+
+    #[test]
+    fn print_roundtrip() {
+        let src = "listen '::' 80 h2\nlocation ^ '/it\\'s' {\n    return 200 \"a \\\"$b\\\"\\n\"\n}\nx 1.0 -2 10M\n";
+        let directives = parse(src);
+
+        assert_eq!(crate::ast::Printer(&directives).to_string(), src);
+    }
+
+    #[test]
+    fn errors() {
+        let err = super::parse(FileId(0), "a 'open\n").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (1, 8));
+        assert_eq!(err.expected, "a closing '");
+
+        let err = super::parse(FileId(0), "a {\n b 1\n").unwrap_err();
+        assert_eq!(err.expected, "a closing '}'");
+
+        let err = super::parse(FileId(0), "a 'x''y'").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (1, 6));
+
+        let err = super::parse(FileId(0), "}").unwrap_err();
+        assert_eq!(err.expected, "a directive name");
+
+        let err = super::parse(FileId(0), "a 1 {} 2").unwrap_err();
+        assert_eq!(err.expected, "the end of the line");
+    }
+}
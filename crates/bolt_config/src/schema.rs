@@ -0,0 +1,662 @@
+//! The set of known directives, used to validate the shape of a config before it is
+//! converted into the model and to suggest fixes for typos.
+
+use crate::ast::{Directive, Span};
+use crate::error::Invalid;
+use std::ops::RangeInclusive;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Context {
+    /// The main config file
+    Global,
+    /// Top level of a site file
+    Site,
+    /// Inside of a `location` block
+    Location,
+    /// Inside of a `types` block, whose entries are named after mime types
+    Types,
+    /// Inside of an `upstream` block
+    Upstream,
+}
+
+pub struct DirectiveSpec {
+    pub name: &'static str,
+    pub contexts: &'static [Context],
+    pub args: RangeInclusive<usize>,
+    /// `Some(context)` if the directive requires a block whose contents live in `context`
+    pub block: Option<Context>,
+    pub usage: &'static str,
+}
+
+use Context::*;
+
+pub static DIRECTIVES: &[DirectiveSpec] = &[
+    DirectiveSpec {
+        name: "sites",
+        contexts: &[Global],
+        args: 1..=1,
+        block: None,
+        usage: "sites '<directory>'",
+    },
+    DirectiveSpec {
+        name: "snips",
+        contexts: &[Global],
+        args: 1..=1,
+        block: None,
+        usage: "snips '<directory>'",
+    },
+    DirectiveSpec {
+        name: "cache.mode",
+        contexts: &[Global],
+        args: 1..=1,
+        block: None,
+        usage: "cache.mode '<mode>'",
+    },
+    DirectiveSpec {
+        name: "cache.size",
+        contexts: &[Global],
+        args: 2..=2,
+        block: None,
+        usage: "cache.size '<limit>' <size>",
+    },
+    DirectiveSpec {
+        name: "compress",
+        contexts: &[Global],
+        args: 1..=3,
+        block: None,
+        usage: "compress <br|gzip|zstd> ...",
+    },
+    DirectiveSpec {
+        name: "compress.types",
+        contexts: &[Global],
+        args: 1..=usize::MAX,
+        block: None,
+        usage: "compress.types '<mime type>' ...",
+    },
+    DirectiveSpec {
+        name: "compress.min_size",
+        contexts: &[Global],
+        args: 1..=1,
+        block: None,
+        usage: "compress.min_size <size>",
+    },
+    DirectiveSpec {
+        name: "compress.level",
+        contexts: &[Global],
+        args: 1..=1,
+        block: None,
+        usage: "compress.level <level>",
+    },
+    DirectiveSpec {
+        name: "upstream",
+        contexts: &[Global, Site],
+        args: 1..=1,
+        block: Some(Upstream),
+        usage: "upstream '<name>' { server '<host>[:port]' ... }",
+    },
+    DirectiveSpec {
+        name: "server",
+        contexts: &[Upstream],
+        args: 1..=3,
+        block: None,
+        usage: "server '<host>[:port]' [weight <n>] | server 'unix:<path>' [weight <n>]",
+    },
+    DirectiveSpec {
+        name: "balance",
+        contexts: &[Upstream],
+        args: 1..=2,
+        block: None,
+        usage: "balance <round_robin|weighted|least_conn|random_two|hash> [key]",
+    },
+    DirectiveSpec {
+        name: "health_check",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "health_check '<path>'",
+    },
+    DirectiveSpec {
+        name: "health_check.status",
+        contexts: &[Upstream],
+        args: 1..=usize::MAX,
+        block: None,
+        usage: "health_check.status <status> ...",
+    },
+    DirectiveSpec {
+        name: "health_check.interval",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "health_check.interval <duration>",
+    },
+    DirectiveSpec {
+        name: "health_check.rise",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "health_check.rise <probes>",
+    },
+    DirectiveSpec {
+        name: "health_check.fall",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "health_check.fall <probes>",
+    },
+    DirectiveSpec {
+        name: "eject.after",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "eject.after <failures>",
+    },
+    DirectiveSpec {
+        name: "eject.backoff",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "eject.backoff <duration>",
+    },
+    DirectiveSpec {
+        name: "retry.tries",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "retry.tries <tries>",
+    },
+    DirectiveSpec {
+        name: "retry.budget",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "retry.budget <percent>",
+    },
+    DirectiveSpec {
+        name: "breaker",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "breaker <percent>",
+    },
+    DirectiveSpec {
+        name: "breaker.min_requests",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "breaker.min_requests <requests>",
+    },
+    DirectiveSpec {
+        name: "breaker.window",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "breaker.window <duration>",
+    },
+    DirectiveSpec {
+        name: "breaker.cooldown",
+        contexts: &[Upstream],
+        args: 1..=1,
+        block: None,
+        usage: "breaker.cooldown <duration>",
+    },
+    DirectiveSpec {
+        name: "listen",
+        contexts: &[Site],
+        args: 1..=4,
+        block: None,
+        usage: "listen '<address>' <port> [tls] [h2] | listen 'unix:<path>' [tls] [h2]",
+    },
+    DirectiveSpec {
+        name: "site",
+        contexts: &[Site],
+        args: 1..=2,
+        block: None,
+        usage: "site _ | site <=|^> '<domain>' | site ~ '<regex>'",
+    },
+    DirectiveSpec {
+        name: "alias",
+        contexts: &[Site],
+        args: 2..=2,
+        block: None,
+        usage: "alias <=|^> '<domain>' | alias ~ '<regex>'",
+    },
+    DirectiveSpec {
+        name: "alias",
+        contexts: &[Location],
+        args: 1..=1,
+        block: None,
+        usage: "alias '<directory>'",
+    },
+    DirectiveSpec {
+        name: "root",
+        contexts: &[Site, Location],
+        args: 1..=1,
+        block: None,
+        usage: "root '<directory>'",
+    },
+    DirectiveSpec {
+        name: "index",
+        contexts: &[Site, Location],
+        args: 1..=usize::MAX,
+        block: None,
+        usage: "index '<file>' ...",
+    },
+    DirectiveSpec {
+        name: "precompressed",
+        contexts: &[Site, Location],
+        args: 1..=3,
+        block: None,
+        usage: "precompressed <br|gzip|zstd> ...",
+    },
+    DirectiveSpec {
+        name: "autoindex",
+        contexts: &[Site, Location],
+        args: 1..=2,
+        block: None,
+        usage: "autoindex <on|off> [dotfiles]",
+    },
+    DirectiveSpec {
+        name: "types",
+        contexts: &[Global, Site],
+        args: 0..=0,
+        block: Some(Types),
+        usage: "types { <mime type> <extension> ... }",
+    },
+    DirectiveSpec {
+        name: "default_type",
+        contexts: &[Global, Site],
+        args: 1..=1,
+        block: None,
+        usage: "default_type '<mime type>'",
+    },
+    DirectiveSpec {
+        name: "tls.cert",
+        contexts: &[Site],
+        args: 1..=1,
+        block: None,
+        usage: "tls.cert '<path>'",
+    },
+    DirectiveSpec {
+        name: "tls.key",
+        contexts: &[Site],
+        args: 1..=1,
+        block: None,
+        usage: "tls.key '<path>'",
+    },
+    DirectiveSpec {
+        name: "tls.session.timeout",
+        contexts: &[Site],
+        args: 1..=1,
+        block: None,
+        usage: "tls.session.timeout <duration>",
+    },
+    DirectiveSpec {
+        name: "tls.session.cache",
+        contexts: &[Site],
+        args: 1..=1,
+        block: None,
+        usage: "tls.session.cache <size>",
+    },
+    DirectiveSpec {
+        name: "header",
+        contexts: &[Site, Location],
+        args: 2..=3,
+        block: None,
+        usage: "header '<name>' '<value>' [enforce]",
+    },
+    DirectiveSpec {
+        name: "location",
+        contexts: &[Site],
+        args: 2..=2,
+        block: Some(Location),
+        usage: "location <=|^|~> '<path>' { ... }, location @ '<name>' { ... }",
+    },
+    DirectiveSpec {
+        name: "proxy_pass",
+        contexts: &[Location],
+        args: 1..=2,
+        block: None,
+        usage:
+            "proxy_pass 'http://<host>[:port][/path]' [h2] | proxy_pass 'unix:<path>[:/path]' [h2]",
+    },
+    DirectiveSpec {
+        name: "fastcgi_pass",
+        contexts: &[Location],
+        args: 1..=1,
+        block: None,
+        usage: "fastcgi_pass '<host>:<port>' | fastcgi_pass 'unix:<path>'",
+    },
+    DirectiveSpec {
+        name: "fastcgi_param",
+        contexts: &[Location],
+        args: 2..=2,
+        block: None,
+        usage: "fastcgi_param '<name>' <value>",
+    },
+    DirectiveSpec {
+        name: "proxy_connect_timeout",
+        contexts: &[Site, Location],
+        args: 1..=1,
+        block: None,
+        usage: "proxy_connect_timeout <duration>",
+    },
+    DirectiveSpec {
+        name: "proxy_read_timeout",
+        contexts: &[Site, Location],
+        args: 1..=1,
+        block: None,
+        usage: "proxy_read_timeout <duration>",
+    },
+    DirectiveSpec {
+        name: "proxy_send_timeout",
+        contexts: &[Site, Location],
+        args: 1..=1,
+        block: None,
+        usage: "proxy_send_timeout <duration>",
+    },
+    DirectiveSpec {
+        name: "try_files",
+        contexts: &[Location],
+        args: 2..=usize::MAX,
+        block: None,
+        usage: "try_files <file> ... <uri|@ '<name>'|status>",
+    },
+    DirectiveSpec {
+        name: "return",
+        contexts: &[Location],
+        args: 1..=2,
+        block: None,
+        usage: "return <status> [body]",
+    },
+];
+
+/// Finds the spec of `name` in `context`, or any spec of `name` if it isn't allowed there
+pub fn lookup(name: &str, context: Context) -> Option<&'static DirectiveSpec> {
+    DIRECTIVES
+        .iter()
+        .find(|spec| spec.name == name && spec.contexts.contains(&context))
+        .or_else(|| DIRECTIVES.iter().find(|spec| spec.name == name))
+}
+
+/// Checks that every directive is known, allowed where it is used and has the right number of
+/// arguments.
+pub fn validate(directives: &[Directive], context: Context) -> Result<(), Invalid> {
+    if context == Types {
+        return validate_types(directives);
+    }
+
+    for directive in directives {
+        let name = directive.name.node.as_str();
+        let spec = match lookup(name, context) {
+            Some(spec) if spec.contexts.contains(&context) => spec,
+            Some(_) => {
+                return Err(Invalid::new(
+                    directive.name.span,
+                    format!("`{}` is not allowed {}", name, context.describe()),
+                ))
+            }
+            None => {
+                let mut err =
+                    Invalid::new(directive.name.span, format!("unknown directive `{}`", name));
+                if let Some(suggestion) = suggest(name, context) {
+                    err = err.with_help(format!("did you mean `{}`?", suggestion));
+                }
+                return Err(err);
+            }
+        };
+
+        let count = directive.args.len();
+        if !spec.args.contains(&count) {
+            let span = match directive.args.get(*spec.args.end()) {
+                // point at the first argument that is too much
+                Some(arg) => arg.span,
+                // point right behind the directive
+                None => Span {
+                    offset: directive.span.offset + directive.span.len,
+                    column: directive.span.column + directive.span.len,
+                    len: 1,
+                    ..directive.span
+                },
+            };
+            return Err(Invalid::new(
+                span,
+                format!(
+                    "`{}` expects {}, found {}",
+                    name,
+                    describe_count(&spec.args),
+                    count
+                ),
+            )
+            .with_help(format!("usage: {}", spec.usage)));
+        }
+
+        match (&directive.block, spec.block) {
+            (Some(block), Some(context)) => validate(block, context)?,
+            (None, Some(_)) => {
+                return Err(
+                    Invalid::new(directive.span, format!("`{}` requires a block", name))
+                        .with_help(format!("usage: {}", spec.usage)),
+                )
+            }
+            (Some(_), None) => {
+                return Err(Invalid::new(
+                    directive.span,
+                    format!("`{}` does not take a block", name),
+                ))
+            }
+            (None, None) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Entries of a `types` block can't be looked up, any mime type is allowed
+fn validate_types(directives: &[Directive]) -> Result<(), Invalid> {
+    for directive in directives {
+        if directive.args.is_empty() {
+            return Err(Invalid::new(
+                directive.span,
+                format!(
+                    "`{}` expects at least 1 extension, found 0",
+                    directive.name.node
+                ),
+            )
+            .with_help("usage: <mime type> <extension> ..."));
+        }
+        if directive.block.is_some() {
+            return Err(Invalid::new(
+                directive.span,
+                "entries of a `types` block do not take a block",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Finds the known directive closest to `name`, if it is close enough to be a typo
+pub fn suggest(name: &str, context: Context) -> Option<&'static str> {
+    let threshold = (name.chars().count() / 3).max(1);
+
+    DIRECTIVES
+        .iter()
+        .filter(|spec| spec.contexts.contains(&context))
+        .map(|spec| (spec.name, distance(name, spec.name)))
+        .filter(|(_, distance)| *distance <= threshold)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(name, _)| name)
+}
+
+/// Damerau-Levenshtein distance (optimal string alignment)
+pub(crate) fn distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+fn describe_count(range: &RangeInclusive<usize>) -> String {
+    let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+    match (*range.start(), *range.end()) {
+        (start, usize::MAX) => format!("at least {} {}", start, plural(start)),
+        (start, end) if start == end => format!("{} {}", start, plural(start)),
+        (start, end) => format!("{} to {} arguments", start, end),
+    }
+}
+
+impl Context {
+    fn describe(&self) -> &'static str {
+        match self {
+            Global => "in the main config file",
+            Site => "at the top level of a site",
+            Location => "inside of a location block",
+            Types => "inside of a types block",
+            Upstream => "inside of an upstream block",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+    use crate::source::FileId;
+    use pretty_assertions::assert_eq;
+
+    fn validate_site(src: &str) -> Result<(), Invalid> {
+        validate(&parse(FileId(0), src).unwrap(), Site)
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("listen", "listen"), 0);
+        assert_eq!(distance("lisen", "listen"), 1);
+        assert_eq!(distance("lsiten", "listen"), 1);
+        assert_eq!(distance("abc", "xyz"), 3);
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(
+            suggest("tls.sesion.timeout", Site),
+            Some("tls.session.timeout")
+        );
+        assert_eq!(suggest("lisen", Site), Some("listen"));
+        assert_eq!(suggest("retrun", Location), Some("return"));
+        assert_eq!(suggest("retrun", Site), None);
+        assert_eq!(suggest("completely_different", Site), None);
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(
+            validate_site(
+                "listen '::' 80\nsite _\nlocation = '/' { return 204 }\nlocation ~ '[.]php$' { return 403 }"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn unknown_directive() {
+        let err = validate_site("site _\ntls.sesion.timeout 1d").unwrap_err();
+
+        assert_eq!(err.message, "unknown directive `tls.sesion.timeout`");
+        assert_eq!(
+            err.help.as_deref(),
+            Some("did you mean `tls.session.timeout`?")
+        );
+        assert_eq!((err.span.line, err.span.column, err.span.len), (2, 1, 18));
+    }
+
+    #[test]
+    fn wrong_context() {
+        let err = validate_site("return 200").unwrap_err();
+        assert_eq!(
+            err.message,
+            "`return` is not allowed at the top level of a site"
+        );
+    }
+
+    #[test]
+    fn too_few_arguments() {
+        let err = validate_site("listen").unwrap_err();
+
+        assert_eq!(err.message, "`listen` expects 1 to 4 arguments, found 0");
+        assert_eq!(
+            err.help.as_deref(),
+            Some("usage: listen '<address>' <port> [tls] [h2] | listen 'unix:<path>' [tls] [h2]")
+        );
+        assert_eq!((err.span.line, err.span.column), (1, 7));
+    }
+
+    #[test]
+    fn too_many_arguments() {
+        let err = validate_site("tls.cert 'a' 'b'").unwrap_err();
+
+        assert_eq!(err.message, "`tls.cert` expects 1 argument, found 2");
+        assert_eq!((err.span.column, err.span.len), (14, 3));
+    }
+
+    #[test]
+    fn per_context() {
+        assert_eq!(validate_site("alias = 'example.com'"), Ok(()));
+        assert_eq!(
+            validate_site("location ^ '/' { alias '/srv' }\nsite _"),
+            Ok(())
+        );
+
+        let err = validate_site("location ^ '/' { alias = '/srv' }").unwrap_err();
+        assert_eq!(err.message, "`alias` expects 1 argument, found 2");
+        assert_eq!(err.help.as_deref(), Some("usage: alias '<directory>'"));
+
+        let err = validate_site("index").unwrap_err();
+        assert_eq!(err.message, "`index` expects at least 1 argument, found 0");
+    }
+
+    #[test]
+    fn blocks() {
+        let err = validate_site("location = '/'").unwrap_err();
+        assert_eq!(err.message, "`location` requires a block");
+
+        let err = validate_site("location = '/' { retrun 200 }").unwrap_err();
+        assert_eq!(err.help.as_deref(), Some("did you mean `return`?"));
+    }
+
+    #[test]
+    fn types_block() {
+        assert_eq!(
+            validate_site("types {\n    text/html html htm\n    application/wasm wasm\n}"),
+            Ok(())
+        );
+
+        let err = validate_site("types {\n    text/html\n}").unwrap_err();
+        assert_eq!(
+            err.message,
+            "`text/html` expects at least 1 extension, found 0"
+        );
+        assert_eq!(err.span.line, 2);
+    }
+}
@@ -0,0 +1,31 @@
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct FileId(pub u32);
+
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// Keeps every loaded config file around so spans can be resolved back to their origin.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, path: PathBuf, text: String) -> FileId {
+        self.files.push(SourceFile { path, text });
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    pub fn get(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    pub fn path(&self, id: FileId) -> &Path {
+        &self.get(id).path
+    }
+}
@@ -0,0 +1,300 @@
+//! Double quoted strings are templates which may reference variables, they are parsed once while
+//! loading the config and rendered for every request.
+//!
+//! Variables are written as `$name` or `${name}` when followed by characters that would otherwise
+//! be part of the name, `$$` is a literal dollar sign. The following variables are built in:
+//!
+//! | Variable          | Value                                                                  |
+//! |-------------------|------------------------------------------------------------------------|
+//! | `$scheme`         | `http` or `https`                                                      |
+//! | `$host`           | Host the request is addressed to, without port                         |
+//! | `$request_uri`    | Path and query as sent by the client                                   |
+//! | `$uri`            | Normalized path of the request                                         |
+//! | `$relative_uri`   | Path following the prefix of the matched `location ^`                  |
+//! | `$args`           | Query string                                                           |
+//! | `$arg_<name>`     | Value of the query argument `<name>`                                   |
+//! | `$cookie_<name>`  | Value of the cookie `<name>`                                           |
+//! | `$remote_addr`    | Address of the client                                                  |
+//! | `$server_addr`    | Address the request was received on                                    |
+//! | `$sni`            | Server name requested during the tls handshake                         |
+//! | `$alpn`           | Protocol negotiated during the tls handshake                           |
+//! | `$1`, `$2`, ...   | Positional capture of the location regex, or the site regex otherwise  |
+//!
+//! Any other name refers to a named capture group (`(?P<name>...)`) of the site or location
+//! regex and must be defined by one of them. Variables without a value render as empty strings.
+
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Variable {
+    Scheme,
+    Host,
+    RequestUri,
+    Uri,
+    RelativeUri,
+    Args,
+    Arg(String),
+    Cookie(String),
+    RemoteAddr,
+    ServerAddr,
+    Sni,
+    Alpn,
+    /// `$1`, positional capture group
+    Capture(usize),
+    /// `$name`, named capture group
+    Named(String),
+}
+
+/// Provides the values of variables while rendering a template
+pub trait Variables {
+    fn get(&self, variable: &Variable) -> Option<Cow<'_, str>>;
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("expected a variable name after `$`")]
+    MissingName,
+    #[error("unclosed `${{`")]
+    Unclosed,
+    #[error("unknown variable `${0}`")]
+    Unknown(String),
+}
+
+/// Names of the built in variables, without `$`
+pub const BUILTINS: &[&str] = &[
+    "scheme",
+    "host",
+    "request_uri",
+    "uri",
+    "relative_uri",
+    "args",
+    "remote_addr",
+    "server_addr",
+    "sni",
+    "alpn",
+];
+
+impl Template {
+    pub fn parse(src: &str) -> Result<Self, TemplateError> {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut rest = src;
+
+        while let Some(i) = rest.find('$') {
+            literal.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+
+            if let Some(tail) = rest.strip_prefix('$') {
+                literal.push('$');
+                rest = tail;
+                continue;
+            }
+
+            let (name, tail) = match rest.strip_prefix('{') {
+                Some(braced) => {
+                    let end = braced.find('}').ok_or(TemplateError::Unclosed)?;
+                    (&braced[..end], &braced[end + 1..])
+                }
+                None => {
+                    // `$1abc` is the first capture followed by `abc`
+                    let end = match rest.starts_with(|c: char| c.is_ascii_digit()) {
+                        true => rest.find(|c: char| !c.is_ascii_digit()),
+                        false => rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')),
+                    }
+                    .unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+            if name.is_empty() {
+                return Err(TemplateError::MissingName);
+            }
+
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(Segment::Variable(Variable::from_name(name)?));
+            rest = tail;
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { segments })
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = &Variable> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Variable(variable) => Some(variable),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// Checks that every named capture is one of `captures`
+    pub fn check<S: AsRef<str>>(&self, captures: &[S]) -> Result<(), TemplateError> {
+        for variable in self.variables() {
+            if let Variable::Named(name) = variable {
+                if !captures.iter().any(|c| c.as_ref() == name) {
+                    return Err(TemplateError::Unknown(name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, variables: &dyn Variables) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => out.push_str(literal),
+                Segment::Variable(variable) => {
+                    if let Some(value) = variables.get(variable) {
+                        out.push_str(&value);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Result<Self, TemplateError> {
+        Ok(match name {
+            "scheme" => Self::Scheme,
+            "host" => Self::Host,
+            "request_uri" => Self::RequestUri,
+            "uri" => Self::Uri,
+            "relative_uri" => Self::RelativeUri,
+            "args" => Self::Args,
+            "remote_addr" => Self::RemoteAddr,
+            "server_addr" => Self::ServerAddr,
+            "sni" => Self::Sni,
+            "alpn" => Self::Alpn,
+            _ => {
+                if let Some(arg) = name.strip_prefix("arg_") {
+                    Self::Arg(arg.to_string())
+                } else if let Some(cookie) = name.strip_prefix("cookie_") {
+                    Self::Cookie(cookie.to_string())
+                } else if name.starts_with(|c: char| c.is_ascii_digit()) {
+                    let index = name
+                        .parse()
+                        .map_err(|_| TemplateError::Unknown(name.to_string()))?;
+                    Self::Capture(index)
+                } else {
+                    Self::Named(name.to_string())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    struct Vars;
+
+    impl Variables for Vars {
+        fn get(&self, variable: &Variable) -> Option<Cow<'_, str>> {
+            Some(match variable {
+                Variable::Scheme => "https".into(),
+                Variable::Host => "example.com".into(),
+                Variable::RequestUri => "/hi/there?a=b".into(),
+                Variable::Arg(name) if name == "a" => "b".into(),
+                Variable::Capture(1) => "one".into(),
+                Variable::Named(name) => format!("<{}>", name).into(),
+                _ => return None,
+            })
+        }
+    }
+
+    fn render(src: &str) -> String {
+        Template::parse(src).unwrap().render(&Vars)
+    }
+
+    #[test]
+    fn literal() {
+        assert_eq!(
+            Template::parse("Hello World!").unwrap().segments(),
+            &[Segment::Literal("Hello World!".to_string())]
+        );
+        assert_eq!(Template::parse("").unwrap().segments(), &[]);
+        assert_eq!(render("100$$"), "100$");
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            Template::parse("https://$host$request_uri")
+                .unwrap()
+                .segments(),
+            &[
+                Segment::Literal("https://".to_string()),
+                Segment::Variable(Variable::Host),
+                Segment::Variable(Variable::RequestUri),
+            ]
+        );
+        assert_eq!(
+            Template::parse("$arg_page $cookie_session ${1}0 $12 $tenant")
+                .unwrap()
+                .variables()
+                .collect::<Vec<_>>(),
+            vec![
+                &Variable::Arg("page".to_string()),
+                &Variable::Cookie("session".to_string()),
+                &Variable::Capture(1),
+                &Variable::Capture(12),
+                &Variable::Named("tenant".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rendering() {
+        assert_eq!(
+            render("$scheme://$host$request_uri"),
+            "https://example.com/hi/there?a=b"
+        );
+        assert_eq!(render("a=$arg_a, c=$arg_c"), "a=b, c=");
+        assert_eq!(render("$1st ${host}s $1abc"), "onest example.coms oneabc");
+        assert_eq!(render("Hi $relative_uri!"), "Hi !");
+        assert_eq!(render("$tenant."), "<tenant>.");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Template::parse("$"), Err(TemplateError::MissingName));
+        assert_eq!(Template::parse("a $ b"), Err(TemplateError::MissingName));
+        assert_eq!(Template::parse("${}"), Err(TemplateError::MissingName));
+        assert_eq!(Template::parse("${host"), Err(TemplateError::Unclosed));
+    }
+
+    #[test]
+    fn check() {
+        let template = Template::parse("$host $tenant").unwrap();
+
+        assert_eq!(template.check(&["tenant"]), Ok(()));
+        assert_eq!(
+            template.check::<&str>(&[]),
+            Err(TemplateError::Unknown("tenant".to_string()))
+        );
+    }
+}
@@ -0,0 +1,699 @@
+use bolt_config::model::{
+    Autoindex, Balance, Breaker, Compression, Eject, Encoding, Fallback, FastCgiParam, FastCgiPass,
+    HealthCheck, Listen, LocationModifier, ProxyPass, ProxyTimeouts, Retry, Return, SiteName, Text,
+    Types, UpstreamServer,
+};
+use bolt_config::template::Template;
+use bolt_config::{ConfigProvider, Diagnostic, FileConfigProvider};
+use bolt_router::Slot;
+use pretty_assertions::assert_eq;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn config_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../config")
+}
+
+/// A config directory in the temp dir, `name` has to be unique across tests since they run in
+/// parallel. Removed again when dropped, also if an assertion fails.
+struct ConfigDir(PathBuf);
+
+impl ConfigDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("bolt_config_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+
+    fn write(&self, file: &str, text: &str) {
+        let path = self.path(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+
+    fn load(&self) -> Result<FileConfigProvider, Diagnostic> {
+        match FileConfigProvider::load(self.path("bolt.conf")) {
+            Ok(provider) => Ok(provider),
+            Err(bolt_config::Error::Invalid(diagnostic)) => Err(*diagnostic),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+impl Drop for ConfigDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Loads `site` as the only site, with `global` following the `sites` and `snips` directives of
+/// `bolt.conf`
+fn load_with(name: &str, global: &str, site: &str) -> Result<FileConfigProvider, Diagnostic> {
+    let dir = ConfigDir::new(name);
+    dir.write(
+        "bolt.conf",
+        &format!("sites './sites'\nsnips './sites'\n{}", global),
+    );
+    dir.write("sites/a.conf", site);
+    dir.load()
+}
+
+fn load_site(name: &str, site: &str) -> Result<FileConfigProvider, Diagnostic> {
+    load_with(name, "", site)
+}
+
+fn rejected(result: Result<FileConfigProvider, Diagnostic>) -> Diagnostic {
+    match result {
+        Err(diagnostic) => diagnostic,
+        Ok(_) => panic!("config should not load"),
+    }
+}
+
+#[test]
+fn load_shipped_config() {
+    let provider = FileConfigProvider::load(config_dir().join("bolt.conf")).unwrap();
+    let config = provider.config();
+
+    assert_eq!(config.cache.mode.as_deref(), Some("static-only"));
+    assert_eq!(config.cache.size.as_ref().map(|s| s.bytes), Some(10 << 20));
+    assert_eq!(config.sites.len(), 2);
+
+    let default = &config.sites[0];
+    assert!(default.is_default());
+    assert_eq!(
+        default.listen,
+        vec![
+            Listen {
+                addr: "0.0.0.0:8080".parse().unwrap(),
+                tls: false,
+                h2: true
+            },
+            Listen {
+                addr: "[::]:8080".parse().unwrap(),
+                tls: false,
+                h2: true
+            },
+        ]
+    );
+
+    let example = &config.sites[1];
+    assert_eq!(
+        example
+            .names
+            .iter()
+            .map(|n| n.node.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            SiteName::Exact("example.com".to_string()),
+            SiteName::Regex(r"^[a-z0-9-]+\.example\.com$".to_string()),
+        ]
+    );
+    assert!(example.listen.iter().all(|l| l.tls && l.h2));
+
+    // pulled in through `snip tls`
+    let tls = example.tls.as_ref().unwrap();
+    assert_eq!(tls.session_timeout, Some(Duration::from_secs(60 * 60 * 24)));
+    assert_eq!(tls.session_cache, Some(10 << 20));
+    assert_eq!(example.headers.len(), 1);
+    assert!(example.headers[0].enforce);
+
+    assert_eq!(example.locations.len(), 2);
+    assert_eq!(example.locations[1].modifier, LocationModifier::Prefix);
+    assert_eq!(example.locations[1].pattern, "/hi");
+    assert_eq!(
+        example.locations[1].r#return,
+        Some(Return {
+            status: 200,
+            body: Some(Text::Format(Template::parse("Hi $relative_uri!").unwrap()))
+        })
+    );
+
+    let hosts = provider.hosts();
+    assert!(hosts.default().is_some());
+
+    let router = hosts.vhosts();
+    let route = |host| router.route(host).map(|m| m.slot);
+    assert_eq!(route("example.com"), Some(Slot(1)));
+    assert_eq!(route("foo.example.com"), Some(Slot(1)));
+    assert_eq!(route("example.org"), Some(Slot(0)));
+}
+
+#[test]
+fn diagnostics_point_into_snippets() {
+    let dir = ConfigDir::new("diag");
+    dir.write("bolt.conf", "sites './sites'\nsnips './snippets'\n");
+    dir.write("sites/a.conf", "site _\nsnip tls\n");
+    dir.write("snippets/tls.snip", "\ntls.sesion.timeout 1d\n");
+
+    let err = rejected(dir.load());
+    assert_eq!(err.path, dir.path("snippets/tls.snip"));
+    assert_eq!((err.line, err.column, err.width), (2, 1, 18));
+    assert_eq!(
+        err.help.as_deref(),
+        Some("did you mean `tls.session.timeout`?")
+    );
+}
+
+#[test]
+fn global_directives_are_validated() {
+    let error = |global: &str| rejected(load_with("global", global, "site _\n"));
+
+    let err = error("cache.mdoe 'static-only'\n");
+    assert_eq!(err.message, "unknown directive `cache.mdoe`");
+    assert_eq!(err.help.as_deref(), Some("did you mean `cache.mode`?"));
+    assert_eq!((err.line, err.column), (3, 1));
+
+    let err = error("cache.size 'max-ram'\n");
+    assert_eq!(err.message, "`cache.size` expects 2 arguments, found 1");
+    assert_eq!(
+        err.help.as_deref(),
+        Some("usage: cache.size '<limit>' <size>")
+    );
+
+    let err = error("listen '::' 80\n");
+    assert_eq!(
+        err.message,
+        "`listen` is not allowed in the main config file"
+    );
+}
+
+#[test]
+fn unknown_variables_are_rejected() {
+    assert!(load_site(
+        "vars",
+        "alias ~ '^(?P<tenant>[a-z]+)\\.example\\.com$'\n\
+         location ~ '^/(?P<page>[a-z]+)$' { return 200 \"$tenant $page $1\" }\n",
+    )
+    .is_ok());
+
+    let err = rejected(load_site(
+        "vars",
+        "site _\nlocation ^ '/' {\n    return 301 \"https://$hots$request_uri\"\n}\n",
+    ));
+    assert_eq!(err.message, "unknown variable `$hots`");
+    assert_eq!(err.help.as_deref(), Some("did you mean `$host`?"));
+    assert_eq!((err.line, err.column), (3, 16));
+}
+
+#[test]
+fn static_file_settings() {
+    let provider = load_site(
+        "static",
+        "site _\nprecompressed gzip\nautoindex on\n\
+         location ^ '/assets' {\n    precompressed zstd br gzip\n    autoindex off\n}\n\
+         location ^ '/artifacts' { autoindex on dotfiles }\n",
+    )
+    .unwrap();
+    let site = &provider.config().sites[0];
+    assert_eq!(site.precompressed, Some(vec![Encoding::Gzip]));
+    assert_eq!(
+        site.locations[0].precompressed,
+        Some(vec![Encoding::Zstd, Encoding::Brotli, Encoding::Gzip])
+    );
+    assert_eq!(
+        site.autoindex,
+        Some(Autoindex {
+            enabled: true,
+            dotfiles: false
+        })
+    );
+    assert_eq!(site.locations[0].autoindex, Some(Autoindex::default()));
+    assert_eq!(
+        site.locations[1].autoindex,
+        Some(Autoindex {
+            enabled: true,
+            dotfiles: true
+        })
+    );
+
+    let err = rejected(load_site("static", "site _\nprecompressed br deflate\n"));
+    assert_eq!(err.message, "unknown encoding `deflate`");
+    assert_eq!((err.line, err.column), (2, 18));
+}
+
+#[test]
+fn compression_settings() {
+    let provider = load_site("compress", "site _\n").unwrap();
+    assert_eq!(provider.config().compression, Compression::default());
+
+    let provider = load_with(
+        "compress",
+        "compress zstd gzip\n\
+         compress.types 'text/HTML' 'application/wasm'\n\
+         compress.min_size 1K\n\
+         compress.level 4\n",
+        "site _\n",
+    )
+    .unwrap();
+    assert_eq!(
+        provider.config().compression,
+        Compression {
+            encodings: vec![Encoding::Zstd, Encoding::Gzip],
+            types: vec!["text/html".to_string(), "application/wasm".to_string()],
+            min_size: 1024,
+            level: Some(4),
+        }
+    );
+
+    let err = rejected(load_with("compress", "compress.level 30\n", "site _\n"));
+    assert_eq!(err.message, "invalid compression level");
+}
+
+#[test]
+fn mime_types() {
+    let global = "types {\n    application/wasm wasm\n    application/x-7z-compressed '7z'\n}\n\
+                  default_type 'application/octet-stream'\n";
+    let provider = load_with(
+        "types",
+        global,
+        "site _\ntypes { text/x-Markdown md '.MARKDOWN' }\ndefault_type 'text/plain'\n",
+    )
+    .unwrap();
+    let config = provider.config();
+    let types = |entries: &[(&str, &str)], default: &str| Types {
+        extensions: entries
+            .iter()
+            .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+            .collect(),
+        default: Some(default.to_string()),
+    };
+    assert_eq!(
+        config.types,
+        types(
+            &[
+                ("wasm", "application/wasm"),
+                ("7z", "application/x-7z-compressed")
+            ],
+            "application/octet-stream"
+        )
+    );
+    assert_eq!(
+        config.sites[0].types,
+        types(
+            &[("md", "text/x-markdown"), ("markdown", "text/x-markdown")],
+            "text/plain"
+        )
+    );
+
+    let err = rejected(load_with("types", global, "site _\ndefault_type 'plain'\n"));
+    assert_eq!(err.message, "invalid mime type `plain`");
+}
+
+#[test]
+fn try_files() {
+    let provider = load_site(
+        "try",
+        "site _\nroot './www'\n\
+         location ^ '/' { try_files \"$uri\" \"$uri/\" '/index.html' }\n\
+         location ^ '/api' { try_files \"$uri\" @ 'backend' }\n\
+         location ^ '/assets' { try_files \"$uri\" 404 }\n\
+         location @ 'backend' { return 503 }\n",
+    )
+    .unwrap();
+    let locations = &provider.config().sites[0].locations;
+    let uri = Text::Format(Template::parse("$uri").unwrap());
+
+    let spa = locations[0].try_files.as_ref().unwrap();
+    assert_eq!(
+        spa.candidates,
+        vec![uri.clone(), Text::Format(Template::parse("$uri/").unwrap())]
+    );
+    assert_eq!(
+        spa.fallback,
+        Fallback::Uri(Text::Plain("/index.html".to_string()))
+    );
+    let api = locations[1].try_files.as_ref().unwrap();
+    assert_eq!(api.candidates, vec![uri]);
+    assert_eq!(api.fallback, Fallback::Named("backend".to_string()));
+    assert_eq!(
+        locations[2].try_files.as_ref().unwrap().fallback,
+        Fallback::Status(404)
+    );
+    assert_eq!(locations[3].modifier, LocationModifier::Named);
+    assert_eq!(locations[3].pattern, "backend");
+
+    let err = rejected(load_site(
+        "try",
+        "site _\n\
+         location ^ '/' { try_files \"$uri\" @ 'backedn' }\n\
+         location @ 'backend' { return 503 }\n",
+    ));
+    assert_eq!(err.message, "unknown named location `backedn`");
+    assert_eq!(err.help.as_deref(), Some("did you mean `@ 'backend'`?"));
+    assert_eq!((err.line, err.column), (2, 18));
+}
+
+#[test]
+fn proxy_pass() {
+    let provider = load_site(
+        "proxy",
+        "site _\n\
+         location ^ '/api' { proxy_pass 'http://127.0.0.1:3000/v1/' }\n\
+         location ^ '/grpc' { proxy_pass 'http://[::1]:50051' h2 }\n\
+         location @ 'app' { proxy_pass 'http://app.internal' }\n\
+         location ^ '/sock' { proxy_pass 'unix:/run/app.sock' }\n\
+         location ^ '/sock-api' { proxy_pass 'unix:/run/app.sock:/v1/' h2 }\n",
+    )
+    .unwrap();
+    let proxies = provider.config().sites[0]
+        .locations
+        .iter()
+        .map(|location| location.proxy_pass.clone().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        proxies,
+        vec![
+            ProxyPass {
+                authority: "127.0.0.1:3000".to_string(),
+                path: Some("/v1/".to_string()),
+                h2: false,
+            },
+            ProxyPass {
+                authority: "[::1]:50051".to_string(),
+                path: None,
+                h2: true,
+            },
+            ProxyPass {
+                authority: "app.internal".to_string(),
+                path: None,
+                h2: false,
+            },
+            ProxyPass {
+                authority: "unix:/run/app.sock".to_string(),
+                path: None,
+                h2: false,
+            },
+            ProxyPass {
+                authority: "unix:/run/app.sock".to_string(),
+                path: Some("/v1/".to_string()),
+                h2: true,
+            },
+        ]
+    );
+
+    let error = |proxy_pass: &str| {
+        let site = format!("site _\nlocation ^ '/' {{ proxy_pass {} }}\n", proxy_pass);
+        rejected(load_site("proxy", &site)).message
+    };
+    assert_eq!(
+        error("'https://example.com'"),
+        "unsupported upstream scheme `https`"
+    );
+    assert_eq!(
+        error("'http://example.com:http'"),
+        "invalid upstream address `example.com:http`"
+    );
+    assert_eq!(error("'http://[::1/'"), "invalid upstream address `[::1`");
+    assert_eq!(error("'http://a/b?c'"), "invalid upstream path");
+    assert_eq!(error("'http://a' tls"), "unknown proxy_pass flag `tls`");
+    assert_eq!(error("'unix:'"), "missing socket path");
+    assert_eq!(error("'unix:/run/app.sock:v1'"), "invalid upstream path");
+
+    let err = rejected(load_site(
+        "proxy",
+        "site _\nlocation ~ '\\.php$' { proxy_pass 'http://php/index.php' }\n",
+    ));
+    assert_eq!(
+        err.message,
+        "`proxy_pass` can't replace a path in regex or named locations"
+    );
+}
+
+#[test]
+fn fastcgi() {
+    let provider = load_site(
+        "fastcgi",
+        "site _\n\
+         location ~ '^(?P<script>.+\\.php)$' {\n\
+           fastcgi_pass 'unix:/run/php-fpm.sock'\n\
+           fastcgi_param 'SCRIPT_FILENAME' \"/srv/app$script\"\n\
+           fastcgi_param 'APP_ENV' 'production'\n\
+         }\n\
+         location @ 'php' { fastcgi_pass '127.0.0.1:9000' }\n",
+    )
+    .unwrap();
+    let locations = &provider.config().sites[0].locations;
+    assert_eq!(
+        locations[0].fastcgi_pass,
+        Some(FastCgiPass {
+            authority: "unix:/run/php-fpm.sock".to_string(),
+        })
+    );
+    assert_eq!(
+        locations[0].fastcgi_params,
+        vec![
+            FastCgiParam {
+                name: "SCRIPT_FILENAME".to_string(),
+                value: Text::Format(Template::parse("/srv/app$script").unwrap()),
+            },
+            FastCgiParam {
+                name: "APP_ENV".to_string(),
+                value: Text::Plain("production".to_string()),
+            },
+        ]
+    );
+    assert_eq!(
+        locations[1]
+            .fastcgi_pass
+            .as_ref()
+            .map(|pass| pass.authority.as_str()),
+        Some("127.0.0.1:9000")
+    );
+
+    let error = |location: &str| {
+        let site = format!("site _\nlocation ^ '/' {{\n{}\n}}\n", location);
+        rejected(load_site("fastcgi", &site)).message
+    };
+    assert_eq!(
+        error("fastcgi_pass 'http://127.0.0.1:9000'"),
+        "invalid FastCGI address `http://127.0.0.1:9000`"
+    );
+    assert_eq!(
+        error("fastcgi_param '' 'x'"),
+        "invalid FastCGI parameter name ``"
+    );
+    assert_eq!(
+        error("fastcgi_param 'SCRIPT_FILENAME' \"$document_root\""),
+        "unknown variable `$document_root`"
+    );
+    assert_eq!(
+        error("fastcgi_pass 'php:9000'\nproxy_pass 'http://app'"),
+        "a location can only have one `proxy_pass` or `fastcgi_pass`"
+    );
+}
+
+#[test]
+fn unix_listen() {
+    let provider = load_site("unix", "site _\nlisten 'unix:/run/bolt.sock' h2\n").unwrap();
+    assert_eq!(
+        provider.config().sites[0].listen,
+        vec![Listen {
+            addr: "unix:/run/bolt.sock".parse().unwrap(),
+            tls: false,
+            h2: true,
+        }]
+    );
+
+    let err = rejected(load_site("unix", "site _\nlisten 'unix:'\n"));
+    assert_eq!(err.message, "missing socket path");
+}
+
+#[test]
+fn upstreams() {
+    let global =
+        "upstream 'app' {\n    server '10.0.0.1:3000' weight 3\n    server '10.0.0.2:3000'\n    \
+                  balance weighted\n}\n";
+    let provider = load_with(
+        "upstream",
+        global,
+        "site _\n\
+         upstream 'sticky' {\n    server '[::1]:8080'\n    server 'backend.internal'\n    server 'unix:/run/app.sock'\n    balance hash \"$cookie_session\"\n}\n\
+         location ^ '/' { proxy_pass 'http://sticky' }\n",
+    )
+    .unwrap();
+    let config = provider.config();
+    let server = |addr: &str, weight| UpstreamServer {
+        addr: addr.to_string(),
+        weight,
+    };
+    assert_eq!(config.upstreams.len(), 1);
+    assert_eq!(config.upstreams[0].name, "app");
+    assert_eq!(
+        config.upstreams[0].servers,
+        vec![server("10.0.0.1:3000", 3), server("10.0.0.2:3000", 1)]
+    );
+    assert_eq!(config.upstreams[0].balance, Balance::Weighted);
+
+    let sticky = &config.sites[0].upstreams[0];
+    assert_eq!(
+        sticky.servers,
+        vec![
+            server("[::1]:8080", 1),
+            server("backend.internal", 1),
+            server("unix:/run/app.sock", 1)
+        ]
+    );
+    assert_eq!(
+        sticky.balance,
+        Balance::Hash(Text::Format(Template::parse("$cookie_session").unwrap()))
+    );
+
+    let error = |upstream: &str| {
+        let site = format!("site _\n{}\n", upstream);
+        rejected(load_with("upstream", global, &site)).message
+    };
+    assert_eq!(
+        error("upstream 'a' { balance least_conn }"),
+        "upstream `a` has no servers"
+    );
+    assert_eq!(
+        error("upstream 'a' { server 'b:1' weight 0 }"),
+        "invalid weight"
+    );
+    assert_eq!(
+        error("upstream 'a' { server 'b:1'\n balance fastest }"),
+        "unknown balance policy `fastest`"
+    );
+    assert_eq!(
+        error("upstream 'a:80' { server 'b:1' }"),
+        "invalid upstream name `a:80`"
+    );
+    assert_eq!(
+        error("upstream 'a' { server 'b:1' }\nupstream 'a' { server 'c:1' }"),
+        "upstream `a` is defined twice"
+    );
+    assert_eq!(
+        error("location ^ '/' { server 'b:1' }"),
+        "`server` is not allowed inside of a location block"
+    );
+}
+
+#[test]
+fn health_checks() {
+    let load = |upstream: &str| load_site("health", &format!("site _\n{}\n", upstream));
+    let error = |upstream: &str| rejected(load(upstream)).message;
+
+    let provider = load(
+        "upstream 'app' {\n    health_check.interval 500ms\n    server 'a:80'\n    \
+         health_check '/healthz'\n    health_check.status 200 204\n    health_check.fall 1\n    \
+         eject.after 2\n    eject.backoff 1m\n}",
+    )
+    .unwrap();
+    let upstream = &provider.config().sites[0].upstreams[0];
+    assert_eq!(
+        upstream.health_check,
+        Some(HealthCheck {
+            path: "/healthz".to_string(),
+            status: vec![200, 204],
+            interval: Duration::from_millis(500),
+            rise: 2,
+            fall: 1,
+        })
+    );
+    assert_eq!(
+        upstream.eject,
+        Eject {
+            after: 2,
+            backoff: Duration::from_secs(60),
+        }
+    );
+
+    let provider = load("upstream 'app' { server 'a:80' }").unwrap();
+    let upstream = &provider.config().sites[0].upstreams[0];
+    assert_eq!(upstream.health_check, None);
+    assert_eq!(upstream.eject, Eject::default());
+
+    assert_eq!(
+        error("upstream 'a' { server 'b:1'\n health_check.rise 3 }"),
+        "health check settings without `health_check`"
+    );
+    assert_eq!(
+        error("upstream 'a' { server 'b:1'\n health_check 'up' }"),
+        "invalid health check path"
+    );
+    assert_eq!(
+        error("upstream 'a' { server 'b:1'\n health_check '/'; health_check.fall 0 }"),
+        "invalid number of probes"
+    );
+    assert_eq!(
+        error("upstream 'a' { server 'b:1'\n health_check '/'; health_check.interval 0s }"),
+        "invalid interval"
+    );
+}
+
+#[test]
+fn proxy_settings() {
+    let load = |site: &str| load_site("proxy_settings", &format!("site _\n{}\n", site));
+    let error = |site: &str| rejected(load(site)).message;
+
+    let provider = load(
+        "proxy_connect_timeout 2s\nproxy_read_timeout 1m\n\
+         upstream 'app' {\n    server 'a:80'\n    breaker.cooldown 5s\n    breaker 50\n    \
+         retry.tries 3\n    retry.budget 10\n}\n\
+         location ^ '/' {\n    proxy_pass 'http://app'\n    proxy_read_timeout 500ms\n    \
+         proxy_send_timeout 10s\n}",
+    )
+    .unwrap();
+    let site = &provider.config().sites[0];
+    assert_eq!(
+        site.proxy_timeouts,
+        ProxyTimeouts {
+            connect: Some(Duration::from_secs(2)),
+            read: Some(Duration::from_secs(60)),
+            send: None,
+        }
+    );
+    assert_eq!(
+        site.locations[0].proxy_timeouts.or(site.proxy_timeouts),
+        ProxyTimeouts {
+            connect: Some(Duration::from_secs(2)),
+            read: Some(Duration::from_millis(500)),
+            send: Some(Duration::from_secs(10)),
+        }
+    );
+    let upstream = &site.upstreams[0];
+    assert_eq!(
+        upstream.retry,
+        Retry {
+            tries: 3,
+            budget: 10,
+        }
+    );
+    assert_eq!(
+        upstream.breaker,
+        Some(Breaker {
+            ratio: 50,
+            min_requests: 20,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(5),
+        })
+    );
+
+    let provider = load("upstream 'app' { server 'a:80' }").unwrap();
+    let upstream = &provider.config().sites[0].upstreams[0];
+    assert_eq!(upstream.retry, Retry::default());
+    assert_eq!(upstream.breaker, None);
+
+    assert_eq!(
+        error("upstream 'a' { server 'b:1'\n breaker.window 1m }"),
+        "breaker settings without `breaker`"
+    );
+    assert_eq!(
+        error("upstream 'a' { server 'b:1'\n breaker 0 }"),
+        "invalid percentage"
+    );
+    assert_eq!(
+        error("upstream 'a' { server 'b:1'\n retry.tries 0 }"),
+        "invalid number of tries"
+    );
+    assert_eq!(error("proxy_read_timeout 0s"), "invalid duration");
+    assert_eq!(
+        error("location ^ '/' { retry.tries 2 }"),
+        "`retry.tries` is not allowed inside of a location block"
+    );
+}
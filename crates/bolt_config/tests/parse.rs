@@ -0,0 +1,55 @@
+use bolt_config::ast::Value;
+use bolt_config::parse::parse;
+use bolt_config::source::FileId;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn parse_fixture() {
+    let directives = parse(FileId(0), include_str!("parse.bcfg")).unwrap();
+
+    let args = directives
+        .iter()
+        .map(|d| {
+            (
+                d.name.node.as_str(),
+                d.args.iter().map(|a| a.node.clone()).collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        args,
+        vec![
+            ("string", vec![Value::String("string".to_string())]),
+            (
+                "format_string",
+                vec![Value::FormatString("format_string".to_string())]
+            ),
+            (
+                "numbers",
+                vec![
+                    Value::Integer {
+                        value: 1,
+                        suffix: None
+                    },
+                    Value::Integer {
+                        value: -1,
+                        suffix: None
+                    },
+                    Value::Float {
+                        value: 1.1,
+                        suffix: None
+                    },
+                    Value::Float {
+                        value: -1.1,
+                        suffix: None
+                    },
+                    Value::Integer {
+                        value: 1,
+                        suffix: Some("suffix".to_string())
+                    },
+                ]
+            ),
+        ]
+    );
+}
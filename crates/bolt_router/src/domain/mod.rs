@@ -1 +1,194 @@
-pub struct DomainRouter;
+use crate::strategies::{
+    Builder, DomainLevelsStrategy, ExactStrategy, RegexStrategy, Slot, Strategy, StrategyMatch,
+};
+
+/// Selects a virtual host by domain name.
+///
+/// Domains are checked against the registered names in the following order:
+/// 1. Exact domains (`site = 'example.com'`)
+/// 2. Hierarchical domains (`site ^ 'example.com'`), the most specific one wins
+/// 3. Regular expressions (`alias ~ '^[a-z]+\.example\.com$'`), the first one added wins
+/// 4. The default slot (`site _`)
+pub struct DomainRouter {
+    exact: ExactStrategy,
+    levels: DomainLevelsStrategy,
+    regex: RegexStrategy,
+    default: Option<Slot>,
+}
+
+#[derive(Default)]
+pub struct DomainRouterBuilder {
+    exact: <ExactStrategy as Strategy>::Builder,
+    levels: <DomainLevelsStrategy as Strategy>::Builder,
+    regex: <RegexStrategy as Strategy>::Builder,
+    default: Option<Slot>,
+}
+
+impl DomainRouter {
+    pub fn builder() -> DomainRouterBuilder {
+        DomainRouterBuilder::default()
+    }
+
+    /// Domains are matched case-insensitively, the trailing dot of a fully qualified name is ignored
+    pub fn route(&self, domain: &str) -> Option<StrategyMatch> {
+        let domain = normalize(domain);
+
+        self.exact
+            .r#match(&domain)
+            .or_else(|| self.levels.r#match(&domain))
+            .or_else(|| self.regex.r#match(&domain))
+            .or_else(|| self.default.map(|slot| StrategyMatch { slot, any: None }))
+    }
+
+    pub fn default_slot(&self) -> Option<Slot> {
+        self.default
+    }
+}
+
+impl DomainRouterBuilder {
+    pub fn exact(&mut self, domain: &str, slot: Slot) -> &mut Self {
+        self.exact.add(&normalize(domain), slot);
+        self
+    }
+
+    pub fn levels(&mut self, domain: &str, slot: Slot) -> &mut Self {
+        self.levels.add(domain, slot);
+        self
+    }
+
+    pub fn regex(&mut self, pattern: &str, slot: Slot) -> &mut Self {
+        self.regex.add(pattern, slot);
+        self
+    }
+
+    /// Only the first default is kept
+    pub fn default_slot(&mut self, slot: Slot) -> &mut Self {
+        self.default.get_or_insert(slot);
+        self
+    }
+
+    pub fn build(self) -> Result<DomainRouter, regex::Error> {
+        Ok(DomainRouter {
+            exact: self.exact.build().expect("infallible"),
+            levels: self.levels.build().expect("infallible"),
+            regex: self.regex.build()?,
+            default: self.default,
+        })
+    }
+}
+
+fn normalize(domain: &str) -> String {
+    domain
+        .strip_suffix('.')
+        .unwrap_or(domain)
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn router() -> DomainRouter {
+        let mut builder = DomainRouter::builder();
+        builder
+            .exact("example.com", Slot(0))
+            .regex(r"^[a-z0-9-]+\.example\.com$", Slot(0))
+            .levels("example.org", Slot(1))
+            .exact("www.example.org", Slot(2))
+            .regex(r"^.*\.example\.org$", Slot(3))
+            .default_slot(Slot(4));
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn exact() {
+        let router = router();
+
+        assert_eq!(router.route("example.com").map(|m| m.slot), Some(Slot(0)));
+        assert_eq!(
+            router.route("www.example.org").map(|m| m.slot),
+            Some(Slot(2))
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let router = router();
+
+        assert_eq!(router.route("EXAMPLE.com").map(|m| m.slot), Some(Slot(0)));
+        assert_eq!(
+            router.route("Foo.Example.Com").map(|m| m.slot),
+            Some(Slot(0))
+        );
+    }
+
+    #[test]
+    fn trailing_dot() {
+        let router = router();
+
+        assert_eq!(router.route("example.com.").map(|m| m.slot), Some(Slot(0)));
+        assert_eq!(
+            router.route("foo.example.com.").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(router.route("example.org.").map(|m| m.slot), Some(Slot(1)));
+    }
+
+    #[test]
+    fn precedence() {
+        let router = router();
+
+        // levels win over regexes
+        assert_eq!(
+            router.route("foo.example.org").map(|m| m.slot),
+            Some(Slot(1))
+        );
+        assert_eq!(router.route("example.org").map(|m| m.slot), Some(Slot(1)));
+        // regexes win over the default
+        assert_eq!(
+            router.route("foo.example.com").map(|m| m.slot),
+            Some(Slot(0))
+        );
+    }
+
+    #[test]
+    fn captures() {
+        let mut builder = DomainRouter::builder();
+        builder.regex(r"^(?P<tenant>[a-z0-9-]+)\.example\.com$", Slot(0));
+        let router = builder.build().unwrap();
+
+        let m = router.route("Acme.example.com").unwrap();
+        assert_eq!(m.captures().and_then(|c| c.name("tenant")), Some("acme"));
+    }
+
+    #[test]
+    fn default() {
+        let router = router();
+
+        assert_eq!(router.route("beispiel.de").map(|m| m.slot), Some(Slot(4)));
+        assert_eq!(
+            router.route("foo.bar.example.com").map(|m| m.slot),
+            Some(Slot(4))
+        );
+        assert_eq!(router.route("").map(|m| m.slot), Some(Slot(4)));
+    }
+
+    #[test]
+    fn no_default() {
+        let mut builder = DomainRouter::builder();
+        builder.exact("example.com", Slot(0));
+        let router = builder.build().unwrap();
+
+        assert_eq!(router.route("example.com").map(|m| m.slot), Some(Slot(0)));
+        assert_eq!(router.route("example.org").map(|m| m.slot), None);
+    }
+
+    #[test]
+    fn first_default_wins() {
+        let mut builder = DomainRouter::builder();
+        builder.default_slot(Slot(1)).default_slot(Slot(2));
+
+        assert_eq!(builder.build().unwrap().default_slot(), Some(Slot(1)));
+    }
+}
@@ -2,4 +2,6 @@ pub mod domain;
 pub mod path;
 mod strategies;
 
-pub use domain::DomainRouter;
+pub use domain::{DomainRouter, DomainRouterBuilder};
+pub use path::{PathRouter, PathRouterBuilder};
+pub use strategies::{RegexMatch, Slot, StrategyMatch};
@@ -1 +1,257 @@
+use crate::strategies::{Builder, ExactStrategy, RegexMatch, RegexStrategy, Slot, Strategy};
+use bolt_url::UrlPath;
+use std::any::Any;
 
+/// Selects a location by request path, modeled after nginx `location` blocks.
+///
+/// Paths are checked against the registered locations in the following order:
+/// 1. Exact paths (`location = '/'`)
+/// 2. Prefixes (`location ^ '/hi'`), the longest one wins
+/// 3. Regular expressions (`location ~ '\.php$'`), the first one added wins
+///
+/// Exact paths and prefixes are compared segment by segment, so `^ '/hi'` matches `/hi` and
+/// `/hi/there` but not `/hikes`. Regular expressions are searched for in the normalized path.
+pub struct PathRouter {
+    exact: ExactStrategy,
+    prefix: ExactStrategy,
+    regex: RegexStrategy,
+}
+
+pub struct PathRouterBuilder {
+    exact: <ExactStrategy as Strategy>::Builder,
+    prefix: <ExactStrategy as Strategy>::Builder,
+    regex: <RegexStrategy as Strategy>::Builder,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MatchKind {
+    Exact,
+    Prefix,
+    Regex,
+}
+
+pub struct PathMatch<'p> {
+    pub slot: Slot,
+    pub kind: MatchKind,
+    /// The segments following the matched prefix, empty for exact and regex matches
+    pub remainder: &'p [String],
+    pub any: Option<Box<dyn Any + Send + Sync + 'static>>,
+}
+
+impl PathMatch<'_> {
+    /// The capture groups if the location is a regular expression
+    pub fn captures(&self) -> Option<&RegexMatch> {
+        self.any.as_ref()?.downcast_ref()
+    }
+}
+
+impl PathRouter {
+    pub fn builder() -> PathRouterBuilder {
+        PathRouterBuilder::default()
+    }
+
+    pub fn route<'p>(&self, path: &'p UrlPath) -> Option<PathMatch<'p>> {
+        let parts = path.parts();
+
+        if let Some(m) = self.exact.r#match(&join(parts)) {
+            return Some(PathMatch {
+                slot: m.slot,
+                kind: MatchKind::Exact,
+                remainder: &[],
+                any: m.any,
+            });
+        }
+
+        for len in (0..=parts.len()).rev() {
+            if let Some(m) = self.prefix.r#match(&join(&parts[..len])) {
+                return Some(PathMatch {
+                    slot: m.slot,
+                    kind: MatchKind::Prefix,
+                    remainder: &parts[len..],
+                    any: m.any,
+                });
+            }
+        }
+
+        self.regex.r#match(&join(parts)).map(|m| PathMatch {
+            slot: m.slot,
+            kind: MatchKind::Regex,
+            remainder: &[],
+            any: m.any,
+        })
+    }
+}
+
+impl Default for PathRouterBuilder {
+    fn default() -> Self {
+        let mut regex = RegexStrategy::builder();
+        regex.partial();
+
+        Self {
+            exact: Default::default(),
+            prefix: Default::default(),
+            regex,
+        }
+    }
+}
+
+impl PathRouterBuilder {
+    pub fn exact(&mut self, path: &UrlPath, slot: Slot) -> &mut Self {
+        self.exact.add(&join(path.parts()), slot);
+        self
+    }
+
+    pub fn prefix(&mut self, path: &UrlPath, slot: Slot) -> &mut Self {
+        self.prefix.add(&join(path.parts()), slot);
+        self
+    }
+
+    pub fn regex(&mut self, pattern: &str, slot: Slot) -> &mut Self {
+        self.regex.add(pattern, slot);
+        self
+    }
+
+    pub fn build(self) -> Result<PathRouter, regex::Error> {
+        Ok(PathRouter {
+            exact: self.exact.build().expect("infallible"),
+            prefix: self.prefix.build().expect("infallible"),
+            regex: self.regex.build()?,
+        })
+    }
+}
+
+/// Turns normalized segments back into a path, `/` for no segments
+fn join(parts: &[String]) -> String {
+    let mut path = String::with_capacity(parts.iter().map(|p| p.len() + 1).sum::<usize>() + 1);
+    for part in parts {
+        path.push('/');
+        path.push_str(part);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn path(path: &str) -> UrlPath {
+        path.parse().unwrap()
+    }
+
+    fn router() -> PathRouter {
+        let mut builder = PathRouter::builder();
+        builder
+            .exact(&path("/"), Slot(0))
+            .prefix(&path("/hi"), Slot(1))
+            .prefix(&path("/hi/there"), Slot(2))
+            .regex(r"\.php$", Slot(3))
+            .regex(r"^/hi/.*\.php$", Slot(4))
+            .prefix(&path("/"), Slot(5));
+        builder.build().unwrap()
+    }
+
+    fn route(router: &PathRouter, path: &str) -> Option<(Slot, MatchKind, Vec<String>)> {
+        let path = path.parse::<UrlPath>().unwrap();
+        router
+            .route(&path)
+            .map(|m| (m.slot, m.kind, m.remainder.to_vec()))
+    }
+
+    #[test]
+    fn exact() {
+        let router = router();
+
+        assert_eq!(
+            route(&router, "/"),
+            Some((Slot(0), MatchKind::Exact, vec![]))
+        );
+        assert_eq!(route(&router, "").map(|m| m.0), Some(Slot(0)));
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let router = router();
+
+        assert_eq!(
+            route(&router, "/hi"),
+            Some((Slot(1), MatchKind::Prefix, vec![]))
+        );
+        assert_eq!(
+            route(&router, "/hi/you"),
+            Some((Slot(1), MatchKind::Prefix, vec!["you".to_string()]))
+        );
+        assert_eq!(
+            route(&router, "/hi/there/you/two"),
+            Some((
+                Slot(2),
+                MatchKind::Prefix,
+                vec!["you".to_string(), "two".to_string()]
+            ))
+        );
+    }
+
+    #[test]
+    fn prefix_is_segment_aligned() {
+        let router = router();
+
+        assert_eq!(route(&router, "/hikes").map(|m| m.0), Some(Slot(5)));
+        assert_eq!(
+            route(&router, "/hikes/a").map(|m| m.2),
+            Some(vec!["hikes".to_string(), "a".to_string()])
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        let router = router();
+
+        // prefixes win over regexes
+        assert_eq!(route(&router, "/hi/index.php").map(|m| m.0), Some(Slot(1)));
+        // which only apply if nothing else matched
+        let mut builder = PathRouter::builder();
+        builder
+            .exact(&path("/index.php"), Slot(0))
+            .regex(r"\.php$", Slot(1))
+            .regex(r"^/a/.*\.php$", Slot(2));
+        let router = builder.build().unwrap();
+
+        assert_eq!(route(&router, "/index.php").map(|m| m.0), Some(Slot(0)));
+        assert_eq!(
+            route(&router, "/a/index.php"),
+            Some((Slot(1), MatchKind::Regex, vec![]))
+        );
+        assert_eq!(route(&router, "/a/index.html"), None);
+    }
+
+    #[test]
+    fn captures() {
+        let mut builder = PathRouter::builder();
+        builder.regex(r"^/user/(?P<id>[0-9]+)$", Slot(0));
+        let router = builder.build().unwrap();
+
+        let path = path("/user/42");
+        let m = router.route(&path).unwrap();
+        assert_eq!(m.captures().and_then(|c| c.name("id")), Some("42"));
+    }
+
+    #[test]
+    fn normalized() {
+        let router = router();
+
+        assert_eq!(route(&router, "/hi/").map(|m| m.0), Some(Slot(1)));
+        assert_eq!(route(&router, "/%68i/there").map(|m| m.0), Some(Slot(2)));
+        assert_eq!(route(&router, "/hi?query").map(|m| m.0), Some(Slot(1)));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        let mut builder = PathRouter::builder();
+        builder.regex("(", Slot(0));
+
+        assert!(builder.build().is_err());
+    }
+}
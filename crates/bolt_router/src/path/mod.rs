@@ -1 +1,3 @@
 mod engine;
+
+pub use engine::{MatchKind, PathMatch, PathRouter, PathRouterBuilder};
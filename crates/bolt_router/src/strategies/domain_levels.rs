@@ -1,4 +1,5 @@
 use crate::strategies::{Builder, Slot, Strategy, StrategyMatch};
+use std::borrow::Cow;
 
 /// This strategy tries to find the most specific match of a domain respecting the domain hierarchy.
 ///
@@ -15,34 +16,63 @@ use crate::strategies::{Builder, Slot, Strategy, StrategyMatch};
 /// subdomain.example.com -> example.com
 /// www.example.com -> www.example.com
 /// ```
+///
+/// A domain can also be added as an explicit wildcard (`*.example.com`), which only matches
+/// subdomains but not `example.com` itself. For subdomains a wildcard takes precedence over
+/// the plain domain.
+///
+/// Domains are matched case-insensitively and a trailing dot (`example.com.`) is ignored.
 pub struct DomainLevelsStrategy {
     // aho corasick is a magical algorithm that lets is search a byte sequence (string) for
     // a bundle of keywords in a very efficient way
     matcher: aho_corasick::AhoCorasick,
-    table: Vec<Slot>,
+    table: Vec<Entry>,
 }
 
 #[derive(Default)]
-pub struct DomainLevelsStrategyBuilder;
+pub struct DomainLevelsStrategyBuilder {
+    patterns: Vec<String>,
+    table: Vec<Entry>,
+}
+
+/// The slots registered for one domain, `example.com` and `*.example.com` share an entry
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+struct Entry {
+    /// Added as `example.com`, matches the domain and its subdomains
+    domain: Option<Slot>,
+    /// Added as `*.example.com`, matches only subdomains
+    wildcard: Option<Slot>,
+}
 
 impl Strategy for DomainLevelsStrategy {
     type Builder = DomainLevelsStrategyBuilder;
 
     fn r#match(&self, string: &str) -> Option<StrategyMatch> {
+        let string = normalize(string);
+        let string = string.as_ref();
+
         self.matcher
-            .find_iter(string)
+            // Overlapping as `example.com` is contained within `www.example.com`
+            .find_overlapping_iter(string)
             // The matches must be aligned to the end of the domain
             .filter(|m| m.end() == string.len())
-            // The match may not be inside of the domain, only two cases are valid:
-            // 1. The match is at the beginning of the domain, meaning that it is a exact match
-            // 2. The match is aligned to a part of the domain, so a dot is on the left of the match
-            .filter(|m| m.start() == 0 || string.as_bytes()[m.start() - 1] == b'.')
-            // Find the longest match as it is the most specific
-            .min_by(|x, y| x.start().cmp(&y.start()))
-            .map(|m| StrategyMatch {
-                slot: self.table[m.pattern()],
-                any: None,
+            .filter_map(|m| {
+                let entry = &self.table[m.pattern()];
+                // The match may not be inside of the domain, only two cases are valid:
+                // 1. The match is at the beginning of the domain, meaning that it is a exact match
+                // 2. The match is aligned to a part of the domain, so a dot is on the left of the match
+                let slot = if m.start() == 0 {
+                    entry.domain?
+                } else if string.as_bytes()[m.start() - 1] == b'.' {
+                    entry.wildcard.or(entry.domain)?
+                } else {
+                    return None;
+                };
+                Some((m.start(), slot))
             })
+            // Find the longest match as it is the most specific
+            .min_by_key(|(start, _)| *start)
+            .map(|(_, slot)| StrategyMatch { slot, any: None })
     }
 }
 
@@ -51,10 +81,214 @@ impl Builder for DomainLevelsStrategyBuilder {
     type Error = ();
 
     fn build(self) -> Result<Self::Strategy, Self::Error> {
-        todo!()
+        Ok(DomainLevelsStrategy {
+            matcher: aho_corasick::AhoCorasick::new(&self.patterns),
+            table: self.table,
+        })
     }
 
     fn add(&mut self, string: &str, slot: Slot) -> &mut Self {
-        todo!()
+        let (domain, wildcard) = match string.strip_prefix("*.") {
+            Some(domain) => (domain, true),
+            None => (string, false),
+        };
+        let domain = normalize(domain);
+
+        let index = match self.patterns.iter().position(|p| *p == domain) {
+            Some(index) => index,
+            None => {
+                self.patterns.push(domain.into_owned());
+                self.table.push(Entry::default());
+                self.patterns.len() - 1
+            }
+        };
+
+        let entry = &mut self.table[index];
+        if wildcard {
+            entry.wildcard = Some(slot);
+        } else {
+            entry.domain = Some(slot);
+        }
+        self
+    }
+}
+
+/// Lowercases the domain and strips the trailing dot of fully qualified domain names
+fn normalize(domain: &str) -> Cow<'_, str> {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    if domain.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(domain.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn builder() {
+        let builder = DomainLevelsStrategy::builder();
+
+        assert_eq!(builder.patterns, Vec::<String>::new());
+        assert_eq!(builder.table, Vec::<Entry>::new());
+    }
+
+    #[test]
+    fn builder_add() {
+        let mut builder = DomainLevelsStrategy::builder();
+        builder
+            .add("Example.com.", Slot(0))
+            .add("*.example.com", Slot(1))
+            .add("www.example.com", Slot(2));
+
+        assert_eq!(
+            builder.patterns,
+            vec!["example.com".to_string(), "www.example.com".to_string()]
+        );
+        assert_eq!(
+            builder.table,
+            vec![
+                Entry {
+                    domain: Some(Slot(0)),
+                    wildcard: Some(Slot(1)),
+                },
+                Entry {
+                    domain: Some(Slot(2)),
+                    wildcard: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn build() {
+        let strategy = DomainLevelsStrategy::builder()
+            .add_owned("example.com", Slot(0))
+            .add_owned("www.example.com", Slot(1))
+            .build()
+            .unwrap();
+
+        assert_eq!(strategy.matcher.pattern_count(), 2);
+        assert_eq!(strategy.table.len(), 2);
+    }
+
+    #[test]
+    fn r#match() {
+        let strategy = DomainLevelsStrategy::builder()
+            .add_owned("example.com", Slot(0))
+            .add_owned("www.example.com", Slot(1))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            strategy.r#match("example.com").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(
+            strategy.r#match("sub.example.com").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(
+            strategy.r#match("www.example.com").map(|m| m.slot),
+            Some(Slot(1))
+        );
+        assert_eq!(
+            strategy.r#match("a.www.example.com").map(|m| m.slot),
+            Some(Slot(1))
+        );
+
+        assert_eq!(strategy.r#match("beispiel.de").map(|m| m.slot), None);
+        assert_eq!(strategy.r#match("notexample.com").map(|m| m.slot), None);
+        assert_eq!(strategy.r#match("example.com.de").map(|m| m.slot), None);
+        assert_eq!(strategy.r#match("").map(|m| m.slot), None);
+    }
+
+    #[test]
+    fn wildcard() {
+        let strategy = DomainLevelsStrategy::builder()
+            .add_owned("*.example.com", Slot(0))
+            .add_owned("example.org", Slot(1))
+            .add_owned("*.example.org", Slot(2))
+            .add_owned("*.www.example.org", Slot(3))
+            .build()
+            .unwrap();
+
+        // wildcards don't match the apex
+        assert_eq!(strategy.r#match("example.com").map(|m| m.slot), None);
+        assert_eq!(
+            strategy.r#match("a.example.com").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(
+            strategy.r#match("a.b.example.com").map(|m| m.slot),
+            Some(Slot(0))
+        );
+
+        // the plain domain still matches the apex, the wildcard wins for subdomains
+        assert_eq!(
+            strategy.r#match("example.org").map(|m| m.slot),
+            Some(Slot(1))
+        );
+        assert_eq!(
+            strategy.r#match("a.example.org").map(|m| m.slot),
+            Some(Slot(2))
+        );
+
+        // an unmatched apex falls back to a less specific domain
+        assert_eq!(
+            strategy.r#match("www.example.org").map(|m| m.slot),
+            Some(Slot(2))
+        );
+        assert_eq!(
+            strategy.r#match("a.www.example.org").map(|m| m.slot),
+            Some(Slot(3))
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let strategy = DomainLevelsStrategy::builder()
+            .add_owned("Example.COM", Slot(0))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            strategy.r#match("example.com").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(
+            strategy.r#match("WWW.EXAMPLE.COM").map(|m| m.slot),
+            Some(Slot(0))
+        );
+    }
+
+    #[test]
+    fn trailing_dot() {
+        let strategy = DomainLevelsStrategy::builder()
+            .add_owned("example.com.", Slot(0))
+            .add_owned("*.example.org", Slot(1))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            strategy.r#match("example.com").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(
+            strategy.r#match("example.com.").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(
+            strategy.r#match("www.example.com.").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(
+            strategy.r#match("www.example.org.").map(|m| m.slot),
+            Some(Slot(1))
+        );
+        assert_eq!(strategy.r#match("example.org.").map(|m| m.slot), None);
     }
 }
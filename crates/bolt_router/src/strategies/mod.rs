@@ -4,6 +4,10 @@ mod domain_levels;
 mod exact;
 mod pattern;
 
+pub use domain_levels::DomainLevelsStrategy;
+pub use exact::ExactStrategy;
+pub use pattern::{RegexMatch, RegexStrategy};
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 #[repr(transparent)]
 pub struct Slot(pub u64);
@@ -14,6 +18,13 @@ pub struct StrategyMatch {
     pub any: Option<Box<dyn Any + Send + Sync + 'static>>
 }
 
+impl StrategyMatch {
+    /// The capture groups if the match was produced by a regular expression
+    pub fn captures(&self) -> Option<&RegexMatch> {
+        self.any.as_ref()?.downcast_ref()
+    }
+}
+
 pub trait Strategy: 'static + Sync {
     type Builder: Builder;
 
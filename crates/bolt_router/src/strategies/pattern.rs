@@ -6,37 +6,98 @@ pub struct RegexStrategy {
     regex_set: regex::RegexSet,
     regexes: Vec<Arc<Regex>>,
     table: Vec<Slot>,
+    partial: bool,
 }
 
 #[derive(Default)]
 pub struct RegexStrategyBuilder {
     patterns: Vec<String>,
     table: Vec<Slot>,
+    partial: bool,
 }
 
+/// Carried in [`StrategyMatch::any`], holds the capture groups of the matching pattern
+#[derive(Debug, Clone)]
 pub struct RegexMatch {
     pattern: Arc<Regex>,
+    /// Indexed like the groups of `pattern`, `0` is the whole match
+    captures: Vec<Option<String>>,
+}
+
+impl RegexMatch {
+    pub fn pattern(&self) -> &Regex {
+        &self.pattern
+    }
+
+    /// Positional capture group, `0` being the whole match
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.captures.get(index)?.as_deref()
+    }
+
+    /// Named capture group, `(?P<name>...)`
+    pub fn name(&self, name: &str) -> Option<&str> {
+        self.pattern
+            .capture_names()
+            .position(|n| n == Some(name))
+            .and_then(|index| self.get(index))
+    }
+
+    /// All named groups which participated in the match
+    pub fn named(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pattern
+            .capture_names()
+            .zip(&self.captures)
+            .filter_map(|(name, value)| Some((name?, value.as_deref()?)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.captures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.captures.is_empty()
+    }
 }
 
 impl Strategy for RegexStrategy {
     type Builder = RegexStrategyBuilder;
 
     fn r#match(&self, segment: &str) -> Option<StrategyMatch> {
-        let index = self.regex_set.matches(segment).into_iter().next()?;
-
-        let regex = &self.regexes[index];
-        regex
-            .find(segment)
-            .filter(|m| m.start() == 0 && m.end() == segment.len())
-            .map(|m| StrategyMatch {
-                slot: self.table[index],
-                any: Some(Box::new(RegexMatch {
-                    pattern: regex.clone(),
-                })),
+        // The set only tells us which patterns match somewhere, the first one that also satisfies
+        // the anchoring wins
+        self.regex_set
+            .matches(segment)
+            .into_iter()
+            .find_map(|index| {
+                let regex = &self.regexes[index];
+                let captures = regex.captures(segment)?;
+                let whole = captures.get(0)?;
+                if !self.partial && (whole.start() != 0 || whole.end() != segment.len()) {
+                    return None;
+                }
+
+                Some(StrategyMatch {
+                    slot: self.table[index],
+                    any: Some(Box::new(RegexMatch {
+                        pattern: regex.clone(),
+                        captures: captures
+                            .iter()
+                            .map(|m| m.map(|m| m.as_str().to_string()))
+                            .collect(),
+                    })),
+                })
             })
     }
 }
 
+impl RegexStrategyBuilder {
+    /// Accept matches anywhere inside of the input instead of requiring the whole input to match
+    pub fn partial(&mut self) -> &mut Self {
+        self.partial = true;
+        self
+    }
+}
+
 impl Builder for RegexStrategyBuilder {
     type Strategy = RegexStrategy;
     type Error = regex::Error;
@@ -57,6 +118,7 @@ impl Builder for RegexStrategyBuilder {
                 })
                 .collect::<Result<Vec<_>, _>>()?,
             table: self.table,
+            partial: self.partial,
         })
     }
 
@@ -122,4 +184,60 @@ mod tests {
         assert_eq!(strategy.r#match("1234").map(|m| m.slot), None);
         assert_eq!(strategy.r#match("").map(|m| m.slot), None);
     }
+
+    #[test]
+    fn match_first_full() {
+        let strategy = RegexStrategy::builder()
+            .add_owned("[a-z]", Slot(0))
+            .add_owned("[a-z]+", Slot(1))
+            .build()
+            .unwrap();
+
+        assert_eq!(strategy.r#match("a").map(|m| m.slot), Some(Slot(0)));
+        assert_eq!(strategy.r#match("abc").map(|m| m.slot), Some(Slot(1)));
+    }
+
+    #[test]
+    fn match_partial() {
+        let mut builder = RegexStrategy::builder();
+        builder
+            .partial()
+            .add(r"\.php$", Slot(0))
+            .add("[0-9]{3}", Slot(1));
+        let strategy = builder.build().unwrap();
+
+        assert_eq!(
+            strategy.r#match("/index.php").map(|m| m.slot),
+            Some(Slot(0))
+        );
+        assert_eq!(strategy.r#match("/a/1234").map(|m| m.slot), Some(Slot(1)));
+        assert_eq!(strategy.r#match("/index.html").map(|m| m.slot), None);
+    }
+
+    #[test]
+    fn captures() {
+        let strategy = RegexStrategy::builder()
+            .add_owned(
+                r"(?P<tenant>[a-z0-9-]+)\.example\.(com|org)(\.local)?",
+                Slot(0),
+            )
+            .build()
+            .unwrap();
+
+        let m = strategy.r#match("acme.example.com").unwrap();
+        let captures = m.captures().unwrap();
+
+        assert_eq!(captures.len(), 4);
+        assert_eq!(captures.get(0), Some("acme.example.com"));
+        assert_eq!(captures.get(1), Some("acme"));
+        assert_eq!(captures.get(2), Some("com"));
+        assert_eq!(captures.get(3), None);
+        assert_eq!(captures.get(4), None);
+        assert_eq!(captures.name("tenant"), Some("acme"));
+        assert_eq!(captures.name("missing"), None);
+        assert_eq!(
+            captures.named().collect::<Vec<_>>(),
+            vec![("tenant", "acme")]
+        );
+    }
 }
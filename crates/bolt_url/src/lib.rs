@@ -2,6 +2,7 @@ mod parse;
 #[cfg(test)]
 mod tests;
 
+use std::fmt::Write;
 use std::str::FromStr;
 use unicode_normalization::UnicodeNormalization;
 
@@ -30,6 +31,22 @@ impl UrlPath {
     pub fn query(&self) -> Option<&str> {
         self.query.as_deref()
     }
+
+    /// Removes `.` and `..` segments, `None` if the path would leave the root
+    pub fn resolve_dots(mut self) -> Option<Self> {
+        let mut parts = Vec::with_capacity(self.parts.len());
+        for part in self.parts {
+            match part.as_str() {
+                "." => {}
+                ".." => {
+                    parts.pop()?;
+                }
+                _ => parts.push(part),
+            }
+        }
+        self.parts = parts;
+        Some(self)
+    }
 }
 
 impl FromStr for UrlPath {
@@ -53,3 +70,19 @@ impl FromStr for UrlPath {
 pub fn normalize_str(s: &str) -> String {
     s.nfc().collect::<String>()
 }
+
+/// Percent-encodes everything but unreserved characters
+pub fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
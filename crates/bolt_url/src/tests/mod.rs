@@ -1,4 +1,4 @@
-use crate::UrlPath;
+use crate::{encode_segment, UrlPath};
 
 #[test]
 fn total_path_is_correct() {
@@ -45,3 +45,26 @@ fn query_url_path() {
     assert_eq!(path.parts.as_slice(), &["foo", "bar"]);
     assert_eq!(path.query.as_ref().map(|r| r.as_str()), Some("baz=qux"));
 }
+
+#[test]
+fn resolve_dots() {
+    let path = "/foo/./bar/../baz".parse::<UrlPath>().unwrap();
+    let path = path.resolve_dots().unwrap();
+    assert_eq!(path.parts.as_slice(), &["foo", "baz"]);
+    assert_eq!(&path.total, "/foo/./bar/../baz");
+}
+
+#[test]
+fn resolve_dots_escaping() {
+    let path = "/foo/../../etc/passwd".parse::<UrlPath>().unwrap();
+    assert!(path.resolve_dots().is_none());
+
+    let path = "/%2e%2e/etc/passwd".parse::<UrlPath>().unwrap();
+    assert!(path.resolve_dots().is_none());
+}
+
+#[test]
+fn encoded_segments() {
+    assert_eq!(encode_segment("a b?#%ä.txt"), "a%20b%3F%23%25%C3%A4.txt");
+    assert_eq!(encode_segment("plain-1.0_~"), "plain-1.0_~");
+}